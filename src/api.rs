@@ -0,0 +1,312 @@
+// api.rs
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use axum::extract::ws::{Message as WsMessage, WebSocket, WebSocketUpgrade};
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{delete, get};
+use axum::{Json, Router};
+use rdkafka::config::ClientConfig;
+use rdkafka::producer::FutureProducer;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{broadcast, Mutex};
+use tokio::time::timeout;
+use crate::broker::blotter::BlotterEntry;
+use crate::broker::borrow::BorrowDesk;
+use crate::broker::Broker;
+use crate::broker::pre_trade::{pre_trade_check, PreTradeContext};
+use crate::broker::data::{find_client_account, ClientAccount};
+use crate::models::{new_unique_id, Order, OrderAction, OrderStatus, OrderType, PriceUpdate};
+use crate::money::{round_price, Rounding};
+use crate::order_status_receiver::{Blotters, ExecutionReport};
+
+pub const API_ADDRESS: &str = "127.0.0.1:8080";
+const JSON_FILE_PATH: &str = "src/data/client_holdings.json";
+
+#[derive(Clone)]
+struct ApiState {
+    blotters: Blotters,
+    borrow_desk: Arc<std::sync::Mutex<BorrowDesk>>,
+    producer: FutureProducer,
+    prices: Arc<Mutex<HashMap<String, PriceUpdate>>>, // Latest update per symbol
+    price_tx: broadcast::Sender<PriceUpdate>,
+    exec_tx: broadcast::Sender<ExecutionReport>,
+}
+
+// Body of POST /clients/:client_id/orders
+#[derive(Deserialize, Debug)]
+struct NewOrderRequest {
+    stock_symbol: String,
+    order_action: OrderAction,
+    order_type: OrderType,
+    quantity: u64,
+    #[serde(default)]
+    price: Option<f64>, // Required for limit orders
+    #[serde(default)]
+    lot_ids: Vec<String>, // Lots to close, by the exec ID of the fill that opened them
+}
+
+#[derive(Serialize)]
+struct OrderAccepted {
+    order_id: String,
+}
+
+#[derive(Serialize)]
+struct Position {
+    stock_symbol: String,
+    quantity: i64,
+    average_price: f64,
+}
+
+#[derive(Serialize)]
+struct PositionsResponse {
+    broker_id: u64,
+    client_id: u64,
+    cash: HashMap<String, f64>, // Currency -> Balance
+    positions: Vec<Position>,
+}
+
+// Events pushed over the WebSocket
+#[derive(Serialize)]
+#[serde(tag = "type", content = "data")]
+enum StreamEvent {
+    Price(PriceUpdate),
+    Execution(ExecutionReport),
+}
+
+struct ApiError(StatusCode, String);
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.0, Json(serde_json::json!({ "error": self.1 }))).into_response()
+    }
+}
+
+// Serve order entry, positions and prices over HTTP, with a WebSocket feed of prices and executions
+pub async fn run_api_server(
+    blotters: Blotters,
+    borrow_desk: Arc<std::sync::Mutex<BorrowDesk>>,
+    price_tx: broadcast::Sender<PriceUpdate>,
+    exec_tx: broadcast::Sender<ExecutionReport>,
+) {
+    let listener = match tokio::net::TcpListener::bind(API_ADDRESS).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            println!("API server could not listen on {}: {}", API_ADDRESS, e);
+            return;
+        }
+    };
+    println!("API server listening on http://{}", API_ADDRESS);
+
+    let state = ApiState {
+        blotters,
+        borrow_desk,
+        producer: ClientConfig::new()
+            .set("bootstrap.servers", "localhost:9092")
+            .set("message.timeout.ms", "5000")
+            .create()
+            .expect("Producer creation error"),
+        prices: Arc::new(Mutex::new(HashMap::new())),
+        price_tx: price_tx.clone(),
+        exec_tx,
+    };
+
+    // Keep the latest price per symbol for GET /prices and market order pricing
+    let prices = state.prices.clone();
+    let mut price_rx = price_tx.subscribe();
+    tokio::spawn(async move {
+        loop {
+            match price_rx.recv().await {
+                Ok(update) => {
+                    prices.lock().await.insert(update.name.clone(), update);
+                }
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    });
+
+    let app = Router::new()
+        .route("/clients/:client_id/orders", get(list_orders).post(submit_order))
+        .route("/clients/:client_id/orders/:order_id", delete(cancel_order))
+        .route("/clients/:client_id/positions", get(positions))
+        .route("/prices", get(prices_handler))
+        .route("/stream", get(stream))
+        .with_state(state);
+
+    let result = timeout(Duration::from_secs(50), async {
+        if let Err(e) = axum::serve(listener, app).await {
+            println!("API server error: {}", e);
+        }
+    });
+    if result.await.is_err() {
+        println!("Stopping API server.");
+    }
+}
+
+fn client_account(client_id: u64) -> Result<(u64, ClientAccount), ApiError> {
+    find_client_account(JSON_FILE_PATH, client_id)
+        .ok_or_else(|| ApiError(StatusCode::NOT_FOUND, format!("Client {} not found", client_id)))
+}
+
+async fn list_orders(State(state): State<ApiState>, Path(client_id): Path<u64>) -> Result<Json<Vec<BlotterEntry>>, ApiError> {
+    let (broker_id, _) = client_account(client_id)?;
+    let orders = match state.blotters.get(&broker_id) {
+        Some(blotter) => blotter.lock().unwrap().orders(client_id).into_iter().cloned().collect(),
+        None => Vec::new(),
+    };
+    Ok(Json(orders))
+}
+
+// Orders go through the client's broker and its pre-trade checks exactly like the simulated clients' orders
+async fn submit_order(
+    State(state): State<ApiState>,
+    Path(client_id): Path<u64>,
+    Json(request): Json<NewOrderRequest>,
+) -> Result<(StatusCode, Json<OrderAccepted>), ApiError> {
+    let (broker_id, account) = client_account(client_id)?;
+    let bad_request = |text: String| ApiError(StatusCode::BAD_REQUEST, text);
+    if request.quantity == 0 {
+        return Err(bad_request("Quantity must be positive".to_string()));
+    }
+    let price = match (&request.order_type, &request.order_action, request.price) {
+        (_, OrderAction::Cancel, _) => {
+            return Err(bad_request("Use DELETE /clients/:client_id/orders/:order_id to cancel".to_string()))
+        }
+        (OrderType::Limit, action, Some(limit)) => {
+            let rounding = if *action == OrderAction::Buy { Rounding::Down } else { Rounding::Up };
+            round_price(&request.stock_symbol, limit, rounding)
+        }
+        (OrderType::Limit, _, None) => return Err(bad_request("Limit orders need a price".to_string())),
+        (OrderType::Market, _, _) => match state.prices.lock().await.get(&request.stock_symbol) {
+            Some(update) => round_price(&request.stock_symbol, update.price, Rounding::Nearest),
+            None => return Err(bad_request(format!("No price yet for {}", request.stock_symbol))),
+        },
+    };
+    let blotter = state
+        .blotters
+        .get(&broker_id)
+        .cloned()
+        .ok_or_else(|| ApiError(StatusCode::SERVICE_UNAVAILABLE, format!("Broker {} is not running", broker_id)))?;
+
+    let order = Order {
+        broker_id,
+        client_id,
+        order_id: new_unique_id(),
+        stock_symbol: request.stock_symbol,
+        order_type: request.order_type,
+        order_action: request.order_action,
+        price,
+        quantity: request.quantity,
+        status: OrderStatus::Pending,
+        reject_reason: None,
+        reject_text: None,
+        liquidity: None,
+        exec_id: None,
+        liquidation: false,
+        lot_ids: request.lot_ids,
+    };
+    let last_prices: HashMap<String, f64> = state
+        .prices
+        .lock()
+        .await
+        .iter()
+        .map(|(stock_symbol, update)| (stock_symbol.clone(), update.price))
+        .collect();
+    let context = PreTradeContext {
+        borrow_desk: &state.borrow_desk,
+        blotter: &blotter,
+        holdings_path: JSON_FILE_PATH,
+        replacing: None,
+    };
+    pre_trade_check(&order, &account, &last_prices, &context)
+        .map_err(|(_, text)| ApiError(StatusCode::UNPROCESSABLE_ENTITY, text))?;
+    let order_id = order.order_id.clone();
+    println!("API order {} for Client {}: {:?} {} {}", order_id, client_id, order.order_action, order.quantity, order.stock_symbol);
+    Broker::send_order_to_kafka(order, &state.producer, &blotter).await;
+    Ok((StatusCode::ACCEPTED, Json(OrderAccepted { order_id })))
+}
+
+// Asks the matcher to pull the order. The order stays working until the matcher confirms; the
+// outcome arrives as an execution report on the stream and in the blotter.
+async fn cancel_order(
+    State(state): State<ApiState>,
+    Path((client_id, order_id)): Path<(u64, String)>,
+) -> Result<(StatusCode, Json<BlotterEntry>), ApiError> {
+    let (broker_id, _) = client_account(client_id)?;
+    let not_found = || ApiError(StatusCode::NOT_FOUND, format!("Order {} not found", order_id));
+    let blotter = state.blotters.get(&broker_id).ok_or_else(not_found)?;
+    let entry = match blotter.lock().unwrap().entry(&order_id) {
+        Some(entry) if entry.order.client_id == client_id => entry.clone(),
+        _ => return Err(not_found()),
+    };
+    if !entry.state.is_open() {
+        return Err(ApiError(StatusCode::CONFLICT, format!("Order is {:?}, cannot cancel", entry.state)));
+    }
+    Broker::send_cancel_to_kafka(&entry.order, &state.producer).await;
+    Ok((StatusCode::ACCEPTED, Json(entry)))
+}
+
+async fn positions(Path(client_id): Path<u64>) -> Result<Json<PositionsResponse>, ApiError> {
+    let (broker_id, account) = client_account(client_id)?;
+    let cash = account.cash_by_currency();
+    let mut positions: Vec<Position> = account
+        .positions
+        .iter()
+        .map(|(stock_symbol, (quantity, average_price))| Position {
+            stock_symbol: stock_symbol.clone(),
+            quantity: *quantity,
+            average_price: *average_price,
+        })
+        .collect();
+    positions.sort_by(|a, b| a.stock_symbol.cmp(&b.stock_symbol));
+    Ok(Json(PositionsResponse {
+        broker_id,
+        client_id,
+        cash,
+        positions,
+    }))
+}
+
+async fn prices_handler(State(state): State<ApiState>) -> Json<HashMap<String, PriceUpdate>> {
+    Json(state.prices.lock().await.clone())
+}
+
+async fn stream(State(state): State<ApiState>, upgrade: WebSocketUpgrade) -> Response {
+    let price_rx = state.price_tx.subscribe();
+    let exec_rx = state.exec_tx.subscribe();
+    upgrade.on_upgrade(move |socket| forward_events(socket, price_rx, exec_rx))
+}
+
+// Push every price update and execution report until the client goes away
+async fn forward_events(
+    mut socket: WebSocket,
+    mut price_rx: broadcast::Receiver<PriceUpdate>,
+    mut exec_rx: broadcast::Receiver<ExecutionReport>,
+) {
+    loop {
+        let event = tokio::select! {
+            update = price_rx.recv() => match update {
+                Ok(update) => StreamEvent::Price(update),
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            report = exec_rx.recv() => match report {
+                Ok(report) => StreamEvent::Execution(report),
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            incoming = socket.recv() => match incoming {
+                Some(Ok(WsMessage::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => continue,
+            },
+        };
+        let text = serde_json::to_string(&event).expect("Failed to serialize stream event");
+        if socket.send(WsMessage::Text(text)).await.is_err() {
+            break;
+        }
+    }
+}
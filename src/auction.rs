@@ -0,0 +1,242 @@
+// auction.rs
+
+use std::collections::HashMap;
+use std::fs;
+use std::time::Duration;
+use crate::models::{new_unique_id, Liquidity, MarketPhase, OfficialPrice, Order, OrderAction, OrderStatus};
+use crate::money::{round_price, Money, Rounding};
+use crate::order_book::{limit_price, OrderBook};
+
+pub const OFFICIAL_PRICES_PATH: &str = "src/data/official_prices.json";
+
+// Session schedule, measured from the moment the matcher starts
+const OPENING_CALL_END: Duration = Duration::from_secs(5);
+const CLOSING_CALL_START: Duration = Duration::from_secs(40);
+const CLOSING_CALL_END: Duration = Duration::from_secs(45);
+
+pub fn market_phase(elapsed: Duration) -> MarketPhase {
+    if elapsed < OPENING_CALL_END {
+        MarketPhase::OpeningCall
+    } else if elapsed < CLOSING_CALL_START {
+        MarketPhase::Continuous
+    } else if elapsed < CLOSING_CALL_END {
+        MarketPhase::ClosingCall
+    } else {
+        MarketPhase::Closed
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct UncrossPoint {
+    pub price: f64,
+    pub volume: u64,
+    pub buy_volume: u64,  // Buy quantity willing to trade at this price
+    pub sell_volume: u64, // Sell quantity willing to trade at this price
+}
+
+impl UncrossPoint {
+    pub fn imbalance(&self) -> u64 {
+        self.buy_volume.abs_diff(self.sell_volume)
+    }
+
+    pub fn imbalance_side(&self) -> Option<OrderAction> {
+        if self.buy_volume > self.sell_volume {
+            Some(OrderAction::Buy)
+        } else if self.sell_volume > self.buy_volume {
+            Some(OrderAction::Sell)
+        } else {
+            None
+        }
+    }
+}
+
+// Find the single price that maximises executed volume.
+// Ties are broken by minimum imbalance, then by distance to the reference price.
+pub fn find_uncross_point(book: &OrderBook, reference_price: Option<f64>) -> Option<UncrossPoint> {
+    let mut candidates: Vec<f64> = book
+        .bids
+        .iter()
+        .chain(book.asks.iter())
+        .map(limit_price)
+        .filter(|p| p.is_finite() && *p > 0.0)
+        .collect();
+    // An all-market book can only trade at the reference price
+    if let Some(reference) = reference_price {
+        candidates.push(reference);
+    }
+    candidates.sort_by(|a, b| a.total_cmp(b));
+    candidates.dedup();
+
+    let mut best: Option<UncrossPoint> = None;
+    for price in candidates {
+        let buy_volume: u64 = book.bids.iter().filter(|o| limit_price(o) >= price).map(|o| o.quantity).sum();
+        let sell_volume: u64 = book.asks.iter().filter(|o| limit_price(o) <= price).map(|o| o.quantity).sum();
+        let point = UncrossPoint {
+            price,
+            volume: buy_volume.min(sell_volume),
+            buy_volume,
+            sell_volume,
+        };
+        if point.volume == 0 {
+            continue;
+        }
+
+        let is_better = match &best {
+            None => true,
+            Some(current) => {
+                if point.volume != current.volume {
+                    point.volume > current.volume
+                } else if point.imbalance() != current.imbalance() {
+                    point.imbalance() < current.imbalance()
+                } else if let Some(reference) = reference_price {
+                    (point.price - reference).abs() < (current.price - reference).abs()
+                } else {
+                    false
+                }
+            }
+        };
+        if is_better {
+            best = Some(point);
+        }
+    }
+    best
+}
+
+// Execute the auction: fill orders in price-time priority at the uncrossing price.
+// Returns the uncross point and one completed order per (partial) fill.
+pub fn uncross(book: &mut OrderBook, reference_price: Option<f64>) -> Option<(UncrossPoint, Vec<Order>)> {
+    let point = find_uncross_point(book, reference_price)?;
+    let auction_price = round_price(&book.symbol, point.price, Rounding::Nearest);
+
+    let mut fills = Vec::new();
+    fill_side(&mut book.bids, point.volume, auction_price, &mut fills);
+    fill_side(&mut book.asks, point.volume, auction_price, &mut fills);
+
+    Some((UncrossPoint { price: auction_price.to_f64(), ..point }, fills))
+}
+
+fn fill_side(orders: &mut Vec<Order>, volume: u64, price: Money, fills: &mut Vec<Order>) {
+    let mut remaining = volume;
+    for order in orders.iter_mut() {
+        if remaining == 0 {
+            break;
+        }
+        let filled = order.quantity.min(remaining);
+        remaining -= filled;
+        order.quantity -= filled;
+
+        let mut fill = order.clone();
+        fill.quantity = filled;
+        fill.price = price;
+        fill.status = OrderStatus::Completed;
+        fill.liquidity = Some(Liquidity::Auction);
+        fill.exec_id = Some(new_unique_id());
+        fills.push(fill);
+    }
+    orders.retain(|o| o.quantity > 0);
+}
+
+pub fn reset_official_prices() {
+    let empty: HashMap<String, OfficialPrice> = HashMap::new();
+    let json_data = serde_json::to_string_pretty(&empty).expect("Failed to serialize official prices");
+    fs::write(OFFICIAL_PRICES_PATH, json_data).expect("Failed to write official prices");
+}
+
+pub fn load_official_prices() -> HashMap<String, OfficialPrice> {
+    match fs::read_to_string(OFFICIAL_PRICES_PATH) {
+        Ok(content) => serde_json::from_str(&content).unwrap_or_default(),
+        Err(_) => HashMap::new(),
+    }
+}
+
+pub fn record_official_price(symbol: &str, phase: MarketPhase, price: f64) {
+    let mut prices = load_official_prices();
+    let entry = prices.entry(symbol.to_string()).or_default();
+    match phase {
+        MarketPhase::OpeningCall => entry.open = Some(price),
+        MarketPhase::ClosingCall => entry.close = Some(price),
+        _ => return,
+    }
+
+    let json_data = serde_json::to_string_pretty(&prices).expect("Failed to serialize official prices");
+    if let Err(e) = fs::write(OFFICIAL_PRICES_PATH, json_data) {
+        println!("Error writing official prices: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::test_order;
+
+    fn book(orders: Vec<Order>) -> OrderBook {
+        let mut book = OrderBook::new("KO");
+        for order in orders {
+            book.add_order(order);
+        }
+        book
+    }
+
+    #[test]
+    fn uncross_price_maximises_volume() {
+        let book = book(vec![
+            test_order("b1", OrderAction::Buy, 101.0, 10),
+            test_order("b2", OrderAction::Buy, 100.0, 5),
+            test_order("s1", OrderAction::Sell, 99.0, 8),
+            test_order("s2", OrderAction::Sell, 100.0, 7),
+        ]);
+        let point = find_uncross_point(&book, None).unwrap();
+        assert_eq!((point.price, point.volume, point.imbalance()), (100.0, 15, 0));
+    }
+
+    #[test]
+    fn equal_volume_goes_to_the_smaller_imbalance() {
+        let book = book(vec![
+            test_order("b1", OrderAction::Buy, 101.0, 10),
+            test_order("b2", OrderAction::Buy, 99.0, 5),
+            test_order("s1", OrderAction::Sell, 99.0, 10),
+        ]);
+        // 10 shares trade at either price, but at 99 five bought shares are left over
+        let point = find_uncross_point(&book, None).unwrap();
+        assert_eq!((point.price, point.volume, point.imbalance()), (101.0, 10, 0));
+    }
+
+    #[test]
+    fn remaining_ties_go_closest_to_the_reference_price() {
+        let book = book(vec![test_order("b1", OrderAction::Buy, 101.0, 10), test_order("s1", OrderAction::Sell, 99.0, 10)]);
+        // A reference outside the crossed range can't trade itself and only breaks the tie
+        assert_eq!(find_uncross_point(&book, Some(105.0)).unwrap().price, 101.0);
+        assert_eq!(find_uncross_point(&book, Some(90.0)).unwrap().price, 99.0);
+        // Inside the range the reference price itself executes the most with no imbalance
+        assert_eq!(find_uncross_point(&book, Some(100.25)).unwrap().price, 100.25);
+        // Without a reference the lowest of the tied prices stands
+        assert_eq!(find_uncross_point(&book, None).unwrap().price, 99.0);
+    }
+
+    #[test]
+    fn uncross_price_is_rounded_to_the_tick() {
+        let mut book = book(vec![test_order("b1", OrderAction::Buy, 101.0, 10), test_order("s1", OrderAction::Sell, 99.0, 10)]);
+        let (point, fills) = uncross(&mut book, Some(100.006)).unwrap();
+        assert_eq!(point.price, 100.01);
+        assert!(fills.iter().all(|fill| fill.price == Money::from_f64(100.01)));
+        assert!(book.is_empty());
+    }
+
+    #[test]
+    fn fill_side_allocates_in_priority_order_and_leaves_the_rest_resting() {
+        let mut orders = vec![
+            test_order("a", OrderAction::Buy, 101.0, 5),
+            test_order("b", OrderAction::Buy, 100.0, 10),
+            test_order("c", OrderAction::Buy, 100.0, 4),
+        ];
+        let mut fills = Vec::new();
+        fill_side(&mut orders, 12, Money::from_f64(100.0), &mut fills);
+
+        let filled: Vec<(&str, u64)> = fills.iter().map(|fill| (fill.order_id.as_str(), fill.quantity)).collect();
+        assert_eq!(filled, vec![("a", 5), ("b", 7)]);
+        assert!(fills.iter().all(|fill| matches!(fill.status, OrderStatus::Completed) && fill.liquidity == Some(Liquidity::Auction)));
+        assert_ne!(fills[0].exec_id, fills[1].exec_id);
+        let resting: Vec<(&str, u64)> = orders.iter().map(|order| (order.order_id.as_str(), order.quantity)).collect();
+        assert_eq!(resting, vec![("b", 3), ("c", 4)]);
+    }
+}
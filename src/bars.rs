@@ -0,0 +1,399 @@
+// bars.rs
+
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::time::Duration;
+use rdkafka::config::ClientConfig;
+use rdkafka::consumer::{Consumer, StreamConsumer};
+use rdkafka::producer::{FutureProducer, FutureRecord};
+use rdkafka::Message;
+use tokio::sync::broadcast;
+use tokio::time::timeout;
+use crate::models::{Bar, PriceUpdate, Trade};
+use crate::trade_tape::TRADE_TOPIC;
+use crate::wire;
+
+pub const BAR_CONFIG_PATH: &str = "src/data/bar_config.json";
+pub const BAR_TOPIC: &str = "bars";
+// Every bar closed this session
+pub const BARS_PATH: &str = "src/data/bars.jsonl";
+// How often open bars are checked for an elapsed interval
+const CLOSE_CHECK_INTERVAL: Duration = Duration::from_millis(200);
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BarConfig {
+    pub intervals_secs: Vec<u64>,
+    pub history_length: usize, // Closed bars kept per symbol and interval for strategies
+}
+
+impl Default for BarConfig {
+    fn default() -> Self {
+        BarConfig {
+            intervals_secs: vec![1, 60, 300],
+            history_length: 100,
+        }
+    }
+}
+
+impl BarConfig {
+    pub fn load(path: &str) -> Self {
+        match fs::read_to_string(path) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+                println!("Error parsing bar config, using default: {}", e);
+                BarConfig::default()
+            }),
+            Err(_) => BarConfig::default(),
+        }
+    }
+
+    // Strategies read signals off the finest bars
+    pub fn signal_interval_secs(&self) -> u64 {
+        self.intervals_secs.iter().copied().min().unwrap_or(1)
+    }
+}
+
+// Start of the interval a timestamp falls in
+fn bar_start(timestamp_ms: i64, interval_secs: u64) -> i64 {
+    timestamp_ms - timestamp_ms.rem_euclid(interval_secs as i64 * 1000)
+}
+
+// Builds one open bar per symbol and interval, handing back each bar once its interval has passed
+pub struct BarAggregator {
+    intervals_secs: Vec<u64>,
+    open_bars: HashMap<(String, u64), Bar>,
+    closed_starts: HashMap<(String, u64), i64>, // Start of the latest bar handed back
+}
+
+impl BarAggregator {
+    pub fn new(intervals_secs: &[u64]) -> Self {
+        BarAggregator {
+            intervals_secs: intervals_secs.iter().copied().filter(|&secs| secs > 0).collect(),
+            open_bars: HashMap::new(),
+            closed_starts: HashMap::new(),
+        }
+    }
+
+    pub fn on_tick(&mut self, stock_symbol: &str, price: f64, timestamp_ms: i64) -> Vec<Bar> {
+        self.update(stock_symbol, price, 0, 0, timestamp_ms)
+    }
+
+    pub fn on_trade(&mut self, trade: &Trade) -> Vec<Bar> {
+        self.update(&trade.stock_symbol, trade.price, trade.quantity, 1, trade.timestamp_ms)
+    }
+
+    fn update(&mut self, stock_symbol: &str, price: f64, volume: u64, trades: u64, timestamp_ms: i64) -> Vec<Bar> {
+        let mut closed = Vec::new();
+        for &interval_secs in &self.intervals_secs {
+            let start_ms = bar_start(timestamp_ms, interval_secs);
+            let key = (stock_symbol.to_string(), interval_secs);
+            // A late update for a bar already closed or replaced is dropped; that bar has been published
+            if self.closed_starts.get(&key).is_some_and(|&closed_start| start_ms <= closed_start) {
+                continue;
+            }
+            match self.open_bars.get_mut(&key) {
+                Some(bar) if bar.start_ms > start_ms => continue,
+                Some(bar) if bar.start_ms == start_ms => {
+                    bar.high = bar.high.max(price);
+                    bar.low = bar.low.min(price);
+                    bar.close = price;
+                    bar.volume += volume;
+                    bar.trades += trades;
+                    continue;
+                }
+                _ => {}
+            }
+            let new_bar = Bar {
+                stock_symbol: stock_symbol.to_string(),
+                interval_secs,
+                start_ms,
+                open: price,
+                high: price,
+                low: price,
+                close: price,
+                volume,
+                trades,
+            };
+            if let Some(previous) = self.open_bars.insert(key.clone(), new_bar) {
+                self.closed_starts.insert(key, previous.start_ms);
+                closed.push(previous);
+            }
+        }
+        closed
+    }
+
+    // Bars whose interval ended by `now_ms`, so quiet symbols still close on time
+    pub fn close_expired(&mut self, now_ms: i64) -> Vec<Bar> {
+        let expired: Vec<(String, u64)> = self
+            .open_bars
+            .iter()
+            .filter(|(_, bar)| bar.start_ms + bar.interval_secs as i64 * 1000 <= now_ms)
+            .map(|(key, _)| key.clone())
+            .collect();
+        let mut closed: Vec<Bar> = expired.iter().filter_map(|key| self.open_bars.remove(key)).collect();
+        for bar in &closed {
+            self.closed_starts.insert((bar.stock_symbol.clone(), bar.interval_secs), bar.start_ms);
+        }
+        closed.sort_by_key(|bar| (bar.start_ms, bar.interval_secs));
+        closed
+    }
+
+    // Close whatever is still open, at the end of the session
+    pub fn flush(&mut self) -> Vec<Bar> {
+        let mut closed: Vec<Bar> = self.open_bars.drain().map(|(_, bar)| bar).collect();
+        closed.sort_by_key(|bar| (bar.start_ms, bar.interval_secs));
+        closed
+    }
+}
+
+// Recent closed bars per symbol and interval, for strategies
+pub struct BarHistory {
+    length: usize,
+    signal_interval_secs: u64,
+    bars: HashMap<(String, u64), VecDeque<Bar>>,
+}
+
+impl BarHistory {
+    pub fn new(config: &BarConfig) -> Self {
+        BarHistory {
+            length: config.history_length.max(1),
+            signal_interval_secs: config.signal_interval_secs(),
+            bars: HashMap::new(),
+        }
+    }
+
+    pub fn push(&mut self, bar: Bar) {
+        let history = self.bars.entry((bar.stock_symbol.clone(), bar.interval_secs)).or_default();
+        if history.len() == self.length {
+            history.pop_front();
+        }
+        history.push_back(bar);
+    }
+
+    // Percentage move from the open of the oldest of the last `count` signal bars to the latest close
+    pub fn momentum(&self, stock_symbol: &str, count: usize) -> Option<f64> {
+        let history = self.bars.get(&(stock_symbol.to_string(), self.signal_interval_secs))?;
+        let first = history.get(history.len().saturating_sub(count.max(1)))?;
+        let last = history.back()?;
+        Some((last.close - first.open) / first.open * 100.0)
+    }
+}
+
+// Each session starts with an empty bar store
+pub fn reset_bar_store(path: &str) {
+    if let Err(e) = fs::write(path, "") {
+        println!("Error resetting bar store {}: {}", path, e);
+    }
+}
+
+pub fn append_bar(path: &str, bar: &Bar) {
+    let line = serde_json::to_string(bar).expect("Failed to serialize bar");
+    let result = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| writeln!(file, "{}", line));
+    if let Err(e) = result {
+        println!("Error appending to bar store {}: {}", path, e);
+    }
+}
+
+// Every stored bar, in the order they closed; unreadable lines are skipped
+pub fn load_bars(path: &str) -> Vec<Bar> {
+    fs::read_to_string(path)
+        .map(|content| {
+            content
+                .lines()
+                .filter_map(|line| serde_json::from_str(line).ok())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+// Aggregate the price feed and the trade tape into bars; closed bars are stored, published and
+// handed to the brokers
+pub async fn run_bar_aggregator(bar_tx: broadcast::Sender<Bar>) {
+    let price_consumer: StreamConsumer = ClientConfig::new()
+        .set("bootstrap.servers", "localhost:9092")
+        .set("group.id", "bar-aggregator-price-group")
+        .set("auto.offset.reset", "latest")
+        .set("enable.auto.commit", "true")
+        .create()
+        .expect("Failed to create Kafka price consumer");
+    let trade_consumer: StreamConsumer = ClientConfig::new()
+        .set("bootstrap.servers", "localhost:9092")
+        .set("group.id", "bar-aggregator-trade-group")
+        .set("auto.offset.reset", "latest")
+        .set("enable.auto.commit", "true")
+        .set("isolation.level", "read_committed")
+        .create()
+        .expect("Failed to create Kafka trade consumer");
+    let producer: FutureProducer = ClientConfig::new()
+        .set("bootstrap.servers", "localhost:9092")
+        .create()
+        .expect("Failed to create Kafka producer");
+    price_consumer.subscribe(&["stock"]).expect("Failed to subscribe to stock topic");
+    trade_consumer.subscribe(&[TRADE_TOPIC]).expect("Failed to subscribe to trades topic");
+
+    let config = BarConfig::load(BAR_CONFIG_PATH);
+    let mut aggregator = BarAggregator::new(&config.intervals_secs);
+    let mut close_ticker = tokio::time::interval(CLOSE_CHECK_INTERVAL);
+    reset_bar_store(BARS_PATH);
+
+    let result = timeout(Duration::from_secs(50), async {
+        loop {
+            let closed = tokio::select! {
+                _ = close_ticker.tick() => aggregator.close_expired(chrono::Utc::now().timestamp_millis()),
+                message = price_consumer.recv() => match message {
+                    Ok(m) => match m.payload().map(wire::decode::<PriceUpdate>) {
+                        Some(Ok(price_update)) => {
+                            // Ticks carry no time of their own; use when the feed produced them
+                            let timestamp_ms = m.timestamp().to_millis().unwrap_or_else(|| chrono::Utc::now().timestamp_millis());
+                            aggregator.on_tick(&price_update.name, price_update.price, timestamp_ms)
+                        }
+                        _ => Vec::new(),
+                    },
+                    Err(err) => {
+                        println!("Error reading prices for bars: {}", err);
+                        Vec::new()
+                    }
+                },
+                message = trade_consumer.recv() => match message {
+                    Ok(m) => match m.payload().map(wire::decode::<Trade>) {
+                        Some(Ok(trade)) => aggregator.on_trade(&trade),
+                        _ => Vec::new(),
+                    },
+                    Err(err) => {
+                        println!("Error reading trades for bars: {}", err);
+                        Vec::new()
+                    }
+                },
+            };
+            publish_bars(&producer, &bar_tx, &closed).await;
+        }
+    });
+
+    if result.await.is_err() {
+        // The session is over; partial bars are closed as they stand
+        publish_bars(&producer, &bar_tx, &aggregator.flush()).await;
+        println!("Stopping bar aggregator.");
+    }
+}
+
+async fn publish_bars(producer: &FutureProducer, bar_tx: &broadcast::Sender<Bar>, bars: &[Bar]) {
+    for bar in bars {
+        append_bar(BARS_PATH, bar);
+        let payload = wire::encode(bar);
+        if let Err((err, _)) = producer
+            .send(
+                FutureRecord::to(BAR_TOPIC).key(&bar.stock_symbol).payload(&payload),
+                rdkafka::util::Timeout::Never,
+            )
+            .await
+        {
+            println!("Failed to send bar to Kafka: {}", err);
+        }
+        // No subscribers just means no broker is running yet
+        let _ = bar_tx.send(bar.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history_with_closes(closes: &[(f64, f64)]) -> BarHistory {
+        let mut history = BarHistory::new(&BarConfig { intervals_secs: vec![1, 60], history_length: 3 });
+        for (i, &(open, close)) in closes.iter().enumerate() {
+            history.push(Bar {
+                stock_symbol: "AAPL".to_string(),
+                interval_secs: 1,
+                start_ms: i as i64 * 1000,
+                open,
+                high: open.max(close),
+                low: open.min(close),
+                close,
+                volume: 0,
+                trades: 0,
+            });
+        }
+        history
+    }
+
+    #[test]
+    fn ticks_build_a_bar_and_close_it_on_the_next_interval() {
+        let mut aggregator = BarAggregator::new(&[1]);
+        assert!(aggregator.on_tick("AAPL", 100.0, 1_000).is_empty());
+        assert!(aggregator.on_tick("AAPL", 102.0, 1_300).is_empty());
+        assert!(aggregator.on_tick("AAPL", 99.0, 1_600).is_empty());
+        assert!(aggregator.on_tick("AAPL", 101.0, 1_900).is_empty());
+        let closed = aggregator.on_tick("AAPL", 105.0, 2_100);
+        assert_eq!(closed.len(), 1);
+        let bar = &closed[0];
+        assert_eq!(bar.start_ms, 1_000);
+        assert_eq!((bar.open, bar.high, bar.low, bar.close), (100.0, 102.0, 99.0, 101.0));
+    }
+
+    #[test]
+    fn trades_add_volume() {
+        let mut aggregator = BarAggregator::new(&[60]);
+        let trade = |quantity, timestamp_ms| Trade {
+            trade_id: String::new(),
+            stock_symbol: "AAPL".to_string(),
+            price: 100.0,
+            quantity,
+            aggressor: None,
+            timestamp_ms,
+        };
+        aggregator.on_trade(&trade(5, 1_000));
+        aggregator.on_trade(&trade(7, 2_000));
+        let closed = aggregator.flush();
+        assert_eq!((closed[0].volume, closed[0].trades), (12, 2));
+    }
+
+    #[test]
+    fn late_ticks_are_dropped() {
+        let mut aggregator = BarAggregator::new(&[1]);
+        aggregator.on_tick("AAPL", 100.0, 1_500);
+        let closed = aggregator.on_tick("AAPL", 101.0, 2_500);
+        assert_eq!(closed[0].close, 100.0);
+
+        // Belongs to the bar already handed back
+        assert!(aggregator.on_tick("AAPL", 50.0, 1_900).is_empty());
+        let closed = aggregator.close_expired(3_000);
+        assert_eq!(closed.len(), 1);
+        assert_eq!((closed[0].low, closed[0].close), (101.0, 101.0));
+
+        // Belongs to a bar closed by the timer
+        assert!(aggregator.on_tick("AAPL", 50.0, 2_900).is_empty());
+        assert!(aggregator.flush().is_empty());
+    }
+
+    #[test]
+    fn each_interval_closes_on_its_own_boundary() {
+        let mut aggregator = BarAggregator::new(&[1, 60]);
+        aggregator.on_tick("AAPL", 100.0, 1_000);
+        let closed = aggregator.on_tick("AAPL", 101.0, 2_000);
+        assert_eq!(closed.len(), 1);
+        assert_eq!(closed[0].interval_secs, 1);
+        let closed = aggregator.close_expired(60_000);
+        assert_eq!(closed.iter().map(|bar| bar.interval_secs).collect::<Vec<_>>(), vec![60, 1]);
+    }
+
+    #[test]
+    fn momentum_spans_the_requested_bars() {
+        let history = history_with_closes(&[(100.0, 101.0), (101.0, 104.0), (104.0, 110.0)]);
+        assert_eq!(history.momentum("AAPL", 1), Some((110.0 - 104.0) / 104.0 * 100.0));
+        assert_eq!(history.momentum("AAPL", 2), Some((110.0 - 101.0) / 101.0 * 100.0));
+        // More bars than are held reads from the oldest kept
+        assert_eq!(history.momentum("AAPL", 10), Some(10.0));
+        assert_eq!(history.momentum("MSFT", 2), None);
+    }
+
+    #[test]
+    fn history_keeps_the_latest_bars() {
+        let history = history_with_closes(&[(90.0, 95.0), (100.0, 101.0), (101.0, 104.0), (104.0, 110.0)]);
+        assert_eq!(history.momentum("AAPL", 3), Some(10.0));
+    }
+}
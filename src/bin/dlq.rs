@@ -0,0 +1,128 @@
+// bin/dlq.rs
+//
+// Inspect and replay dead-lettered messages.
+//   cargo run --bin dlq -- list <topic>
+//   cargo run --bin dlq -- replay <topic> [offset ...]
+// <topic> is the original topic (e.g. "orders"); its dead letters are read from "<topic>.dlq".
+// Replay sends each message back to the topic it came from. Without offsets, everything is replayed.
+
+use std::time::Duration;
+use colored::*;
+use rdkafka::config::ClientConfig;
+use rdkafka::consumer::{Consumer, StreamConsumer};
+use rdkafka::message::{Message, OwnedMessage};
+use tokio::time::timeout;
+use trading_side::dead_letter::{
+    dead_letter_producer, dead_letter_topic, header_value, ERROR_HEADER, SOURCE_OFFSET_HEADER,
+    SOURCE_PARTITION_HEADER, SOURCE_TOPIC_HEADER, TIMESTAMP_HEADER,
+};
+use trading_side::wire;
+
+// Stop reading once the topic has been quiet this long
+const IDLE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[tokio::main]
+async fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("list") if args.len() == 2 => list(&args[1]).await,
+        Some("replay") if args.len() >= 2 => {
+            let offsets: Vec<i64> = args[2..]
+                .iter()
+                .map(|offset| offset.parse().expect("Offsets must be integers"))
+                .collect();
+            replay(&args[1], &offsets).await;
+        }
+        _ => {
+            eprintln!("Usage: dlq list <topic>");
+            eprintln!("       dlq replay <topic> [offset ...]");
+            std::process::exit(2);
+        }
+    }
+}
+
+// Everything currently on the topic's dead-letter topic, oldest first
+async fn read_dead_letters(topic: &str) -> Vec<OwnedMessage> {
+    // A fresh group each run so every message is read from the beginning
+    let consumer: StreamConsumer = ClientConfig::new()
+        .set("bootstrap.servers", "localhost:9092")
+        .set("group.id", format!("dlq-tool-{}", uuid::Uuid::new_v4()))
+        .set("auto.offset.reset", "earliest")
+        .set("enable.auto.commit", "false")
+        .create()
+        .expect("Consumer creation failed");
+    let dlq_topic = dead_letter_topic(topic);
+    consumer.subscribe(&[&dlq_topic]).expect("Can't subscribe to dead-letter topic");
+
+    let mut messages = Vec::new();
+    while let Ok(message) = timeout(IDLE_TIMEOUT, consumer.recv()).await {
+        match message {
+            Ok(m) => messages.push(m.detach()),
+            Err(err) => {
+                eprintln!("Error reading {}: {}", dlq_topic, err);
+                break;
+            }
+        }
+    }
+    messages
+}
+
+fn header(m: &OwnedMessage, key: &str) -> String {
+    m.headers()
+        .and_then(|headers| header_value(headers, key))
+        .unwrap_or_else(|| "-".to_string())
+}
+
+async fn list(topic: &str) {
+    let messages = read_dead_letters(topic).await;
+    println!("{} dead letters on {}", messages.len(), dead_letter_topic(topic));
+    for m in &messages {
+        println!(
+            "{} {}",
+            format!("Offset {}", m.offset()).bold(),
+            format!(
+                "from {}[{}]@{} at {}",
+                header(m, SOURCE_TOPIC_HEADER),
+                header(m, SOURCE_PARTITION_HEADER),
+                header(m, SOURCE_OFFSET_HEADER),
+                header(m, TIMESTAMP_HEADER)
+            )
+            .bright_black()
+        );
+        println!("  {} {}", "Error:".red().bold(), header(m, ERROR_HEADER));
+        // MessagePack payloads aren't printable; show their size and leading bytes instead
+        let payload = match m.payload() {
+            Some(bytes) if bytes.first() == Some(&b'{') => String::from_utf8_lossy(bytes).into_owned(),
+            Some(bytes) => format!("<{}>", wire::describe_payload(bytes)),
+            None => "<empty>".to_string(),
+        };
+        println!("  Payload: {}", payload);
+    }
+}
+
+async fn replay(topic: &str, offsets: &[i64]) {
+    let producer = dead_letter_producer();
+    let messages = read_dead_letters(topic).await;
+    let mut replayed = 0;
+    for m in messages.iter().filter(|m| offsets.is_empty() || offsets.contains(&m.offset())) {
+        let source_topic = m
+            .headers()
+            .and_then(|headers| header_value(headers, SOURCE_TOPIC_HEADER))
+            .unwrap_or_else(|| topic.to_string());
+        let mut record = rdkafka::producer::FutureRecord::<[u8], [u8]>::to(&source_topic);
+        if let Some(key) = m.key() {
+            record = record.key(key);
+        }
+        if let Some(payload) = m.payload() {
+            record = record.payload(payload);
+        }
+        match producer.send(record, rdkafka::util::Timeout::Never).await {
+            Ok(_) => {
+                replayed += 1;
+                println!("Replayed offset {} to {}", m.offset(), source_topic);
+            }
+            Err((err, _)) => println!("Failed to replay offset {} to {}: {}", m.offset(), source_topic, err),
+        }
+    }
+    println!("{} message(s) replayed from {}", replayed, dead_letter_topic(topic));
+}
@@ -0,0 +1,204 @@
+// bin/ticks.rs
+//
+// Query and export the tick store.
+//   cargo run --bin ticks -- symbols
+//   cargo run --bin ticks -- query <symbol> [from_ms] [to_ms]
+//   cargo run --bin ticks -- export <symbol> <csv|parquet> <path> [from_ms] [to_ms]
+// Times are milliseconds since the Unix epoch; the range is from_ms inclusive to to_ms exclusive
+// and defaults to everything stored.
+
+use std::fs::{self, File};
+use std::io;
+use std::sync::Arc;
+use parquet::data_type::{ByteArray, ByteArrayType, DoubleType, Int64Type};
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::schema::parser::parse_message_type;
+use trading_side::tick_store::{query, stored_symbols, Side, TickEvent, TickRecord, TICK_STORE_DIR};
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let time_arg = |index: usize, default: i64| {
+        args.get(index)
+            .map(|value| value.parse().expect("Times must be integers (milliseconds since the epoch)"))
+            .unwrap_or(default)
+    };
+    match args.first().map(String::as_str) {
+        Some("symbols") => {
+            for symbol in stored_symbols(TICK_STORE_DIR) {
+                println!("{}", symbol);
+            }
+        }
+        Some("query") if (2..=4).contains(&args.len()) => {
+            let records = read(&args[1], time_arg(2, i64::MIN), time_arg(3, i64::MAX));
+            for record in &records {
+                println!("{} {:?}", record.timestamp_ms, record.event);
+            }
+            println!("{} record(s) for {}", records.len(), args[1]);
+        }
+        Some("export") if (4..=6).contains(&args.len()) => {
+            let (symbol, format, path) = (&args[1], &args[2], &args[3]);
+            let records = read(symbol, time_arg(4, i64::MIN), time_arg(5, i64::MAX));
+            let result = match format.as_str() {
+                "csv" => export_csv(path, symbol, &records).map_err(|e| e.to_string()),
+                "parquet" => export_parquet(path, symbol, &records),
+                _ => Err(format!("Unknown export format '{}', expected csv or parquet", format)),
+            };
+            match result {
+                Ok(()) => println!("Exported {} record(s) for {} to {}", records.len(), symbol, path),
+                Err(e) => {
+                    eprintln!("Export failed: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        _ => {
+            eprintln!("Usage: ticks symbols");
+            eprintln!("       ticks query <symbol> [from_ms] [to_ms]");
+            eprintln!("       ticks export <symbol> <csv|parquet> <path> [from_ms] [to_ms]");
+            std::process::exit(2);
+        }
+    }
+}
+
+fn read(symbol: &str, from_ms: i64, to_ms: i64) -> Vec<TickRecord> {
+    query(TICK_STORE_DIR, symbol, from_ms, to_ms).unwrap_or_else(|e| {
+        eprintln!("Error reading ticks for {}: {}", symbol, e);
+        std::process::exit(1);
+    })
+}
+
+fn side_name(side: Option<Side>) -> &'static str {
+    match side {
+        Some(Side::Buy) => "Buy",
+        Some(Side::Sell) => "Sell",
+        None => "",
+    }
+}
+
+fn kind_name(event: &TickEvent) -> &'static str {
+    match event {
+        TickEvent::Price { .. } => "Price",
+        TickEvent::Quote { .. } => "Quote",
+        TickEvent::Trade { .. } => "Trade",
+    }
+}
+
+const CSV_HEADER: &str = "symbol,timestamp_ms,kind,price,quantity,aggressor,bid,bid_size,ask,ask_size\n";
+
+fn export_csv(path: &str, symbol: &str, records: &[TickRecord]) -> io::Result<()> {
+    let optional = |value: Option<f64>| value.map(|v| v.to_string()).unwrap_or_default();
+    let mut csv = String::from(CSV_HEADER);
+    for record in records {
+        let columns = match &record.event {
+            TickEvent::Price { price } => format!("{},,,,,,", price),
+            TickEvent::Quote { bid, bid_size, ask, ask_size } => {
+                format!(",,,{},{},{},{}", optional(*bid), bid_size, optional(*ask), ask_size)
+            }
+            TickEvent::Trade { price, quantity, aggressor } => {
+                format!("{},{},{},,,,", price, quantity, side_name(*aggressor))
+            }
+        };
+        csv.push_str(&format!("{},{},{},{}\n", symbol, record.timestamp_ms, kind_name(&record.event), columns));
+    }
+    fs::write(path, csv)
+}
+
+const PARQUET_SCHEMA: &str = "
+    message tick {
+        REQUIRED BYTE_ARRAY symbol (UTF8);
+        REQUIRED INT64 timestamp_ms;
+        REQUIRED BYTE_ARRAY kind (UTF8);
+        OPTIONAL DOUBLE price;
+        OPTIONAL INT64 quantity;
+        OPTIONAL BYTE_ARRAY aggressor (UTF8);
+        OPTIONAL DOUBLE bid;
+        OPTIONAL INT64 bid_size;
+        OPTIONAL DOUBLE ask;
+        OPTIONAL INT64 ask_size;
+    }
+";
+
+// An optional column: the present values plus a definition level per row (1 present, 0 null)
+struct OptionalColumn<T> {
+    values: Vec<T>,
+    definition_levels: Vec<i16>,
+}
+
+impl<T> OptionalColumn<T> {
+    fn from_rows(rows: impl Iterator<Item = Option<T>>) -> Self {
+        let mut column = OptionalColumn {
+            values: Vec::new(),
+            definition_levels: Vec::new(),
+        };
+        for value in rows {
+            column.definition_levels.push(value.is_some() as i16);
+            column.values.extend(value);
+        }
+        column
+    }
+}
+
+fn export_parquet(path: &str, symbol: &str, records: &[TickRecord]) -> Result<(), String> {
+    let schema = Arc::new(parse_message_type(PARQUET_SCHEMA).map_err(|e| e.to_string())?);
+    let file = File::create(path).map_err(|e| e.to_string())?;
+    let mut writer = SerializedFileWriter::new(file, schema, Arc::new(WriterProperties::builder().build()))
+        .map_err(|e| e.to_string())?;
+
+    let price = OptionalColumn::from_rows(records.iter().map(|r| match r.event {
+        TickEvent::Price { price } | TickEvent::Trade { price, .. } => Some(price),
+        TickEvent::Quote { .. } => None,
+    }));
+    let quantity = OptionalColumn::from_rows(records.iter().map(|r| match r.event {
+        TickEvent::Trade { quantity, .. } => Some(quantity as i64),
+        _ => None,
+    }));
+    let aggressor = OptionalColumn::from_rows(records.iter().map(|r| match r.event {
+        TickEvent::Trade { aggressor: Some(side), .. } => Some(ByteArray::from(side_name(Some(side)))),
+        _ => None,
+    }));
+    let quote_field = |field: fn(&TickEvent) -> Option<f64>| OptionalColumn::from_rows(records.iter().map(|r| field(&r.event)));
+    let bid = quote_field(|event| match event {
+        TickEvent::Quote { bid, .. } => *bid,
+        _ => None,
+    });
+    let ask = quote_field(|event| match event {
+        TickEvent::Quote { ask, .. } => *ask,
+        _ => None,
+    });
+    let bid_size = OptionalColumn::from_rows(records.iter().map(|r| match r.event {
+        TickEvent::Quote { bid_size, .. } => Some(bid_size as i64),
+        _ => None,
+    }));
+    let ask_size = OptionalColumn::from_rows(records.iter().map(|r| match r.event {
+        TickEvent::Quote { ask_size, .. } => Some(ask_size as i64),
+        _ => None,
+    }));
+    let symbols: Vec<ByteArray> = records.iter().map(|_| ByteArray::from(symbol)).collect();
+    let timestamps: Vec<i64> = records.iter().map(|r| r.timestamp_ms).collect();
+    let kinds: Vec<ByteArray> = records.iter().map(|r| ByteArray::from(kind_name(&r.event))).collect();
+
+    let mut row_group = writer.next_row_group().map_err(|e| e.to_string())?;
+    let mut index = 0;
+    while let Some(mut column) = row_group.next_column().map_err(|e| e.to_string())? {
+        // Columns come back in schema order
+        let written = match index {
+            0 => column.typed::<ByteArrayType>().write_batch(&symbols, None, None),
+            1 => column.typed::<Int64Type>().write_batch(&timestamps, None, None),
+            2 => column.typed::<ByteArrayType>().write_batch(&kinds, None, None),
+            3 => column.typed::<DoubleType>().write_batch(&price.values, Some(&price.definition_levels), None),
+            4 => column.typed::<Int64Type>().write_batch(&quantity.values, Some(&quantity.definition_levels), None),
+            5 => column.typed::<ByteArrayType>().write_batch(&aggressor.values, Some(&aggressor.definition_levels), None),
+            6 => column.typed::<DoubleType>().write_batch(&bid.values, Some(&bid.definition_levels), None),
+            7 => column.typed::<Int64Type>().write_batch(&bid_size.values, Some(&bid_size.definition_levels), None),
+            8 => column.typed::<DoubleType>().write_batch(&ask.values, Some(&ask.definition_levels), None),
+            _ => column.typed::<Int64Type>().write_batch(&ask_size.values, Some(&ask_size.definition_levels), None),
+        };
+        written.map_err(|e| e.to_string())?;
+        column.close().map_err(|e| e.to_string())?;
+        index += 1;
+    }
+    row_group.close().map_err(|e| e.to_string())?;
+    writer.close().map_err(|e| e.to_string())?;
+    Ok(())
+}
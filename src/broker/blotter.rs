@@ -0,0 +1,335 @@
+// broker/blotter.rs
+
+use serde::Serialize;
+use std::collections::HashMap;
+use colored::*;
+use crate::models::{Order, OrderAction};
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub enum OrderState {
+    New,
+    Acked,
+    PartiallyFilled,
+    Filled,
+    Cancelled,
+    Rejected,
+    Expired,
+}
+
+impl OrderState {
+    pub fn is_open(self) -> bool {
+        matches!(self, OrderState::New | OrderState::Acked | OrderState::PartiallyFilled)
+    }
+
+    // New → Acked → PartiallyFilled → Filled / Cancelled / Rejected / Expired. An order never
+    // acked by the end of the session expires with the rest.
+    pub fn can_transition_to(self, next: OrderState) -> bool {
+        use OrderState::*;
+        matches!(
+            (self, next),
+            (New, Acked | Rejected | Cancelled | Expired)
+                | (Acked, PartiallyFilled | Filled | Cancelled | Rejected | Expired)
+                | (PartiallyFilled, PartiallyFilled | Filled | Cancelled | Expired)
+        )
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct BlotterEntry {
+    pub order: Order,
+    pub state: OrderState,
+    pub filled_quantity: u64,
+    pub sequence: u64, // Order in which the broker sent it
+    pub exchange_order_id: Option<String>, // From the matcher's ack
+    pub history: Vec<OrderState>, // Every state the order has been in, oldest first
+}
+
+// A state change the blotter refused; `from` is None for orders it never saw
+#[derive(Serialize, Debug, Clone)]
+pub struct IllegalTransition {
+    pub order_id: String,
+    pub from: Option<OrderState>,
+    pub to: OrderState,
+}
+
+// Every order a broker has sent this session and where it is in its lifecycle
+#[derive(Debug)]
+pub struct Blotter {
+    broker_id: u64,
+    orders: HashMap<String, BlotterEntry>,
+    next_sequence: u64,
+    illegal_transitions: Vec<IllegalTransition>,
+}
+
+impl Blotter {
+    pub fn new(broker_id: u64) -> Self {
+        Self {
+            broker_id,
+            orders: HashMap::new(),
+            next_sequence: 0,
+            illegal_transitions: Vec::new(),
+        }
+    }
+
+    pub fn broker_id(&self) -> u64 {
+        self.broker_id
+    }
+
+    pub fn add(&mut self, order: Order) {
+        self.next_sequence += 1;
+        self.orders.insert(
+            order.order_id.clone(),
+            BlotterEntry {
+                order,
+                state: OrderState::New,
+                filled_quantity: 0,
+                sequence: self.next_sequence,
+                exchange_order_id: None,
+                history: vec![OrderState::New],
+            },
+        );
+    }
+
+    // Move an order to a new state. Illegal moves leave the order untouched and are flagged.
+    pub fn transition(&mut self, order_id: &str, to: OrderState) -> Result<(), IllegalTransition> {
+        let from = self.orders.get(order_id).map(|entry| entry.state);
+        match (self.orders.get_mut(order_id), from) {
+            (Some(entry), Some(from)) if from.can_transition_to(to) => {
+                entry.state = to;
+                entry.history.push(to);
+                Ok(())
+            }
+            _ => {
+                let illegal = IllegalTransition {
+                    order_id: order_id.to_string(),
+                    from,
+                    to,
+                };
+                println!(
+                    "{}",
+                    format!(
+                        "Broker {}: Illegal order transition for {}: {:?} -> {:?}",
+                        self.broker_id, order_id, from, to
+                    )
+                    .red()
+                    .bold()
+                );
+                self.illegal_transitions.push(illegal.clone());
+                Err(illegal)
+            }
+        }
+    }
+
+    pub fn state(&self, order_id: &str) -> Option<OrderState> {
+        self.orders.get(order_id).map(|entry| entry.state)
+    }
+
+    pub fn entry(&self, order_id: &str) -> Option<&BlotterEntry> {
+        self.orders.get(order_id)
+    }
+
+    // The order is live at the exchange. An ack arriving after a fill already implied it is ignored.
+    pub fn acknowledge(&mut self, order_id: &str, exchange_order_id: Option<&str>) -> Result<(), IllegalTransition> {
+        let already_acked = self
+            .orders
+            .get(order_id)
+            .is_some_and(|entry| entry.history.contains(&OrderState::Acked));
+        if !already_acked {
+            self.transition(order_id, OrderState::Acked)?;
+        }
+        if let Some(entry) = self.orders.get_mut(order_id) {
+            entry.exchange_order_id = exchange_order_id.map(str::to_string);
+        }
+        Ok(())
+    }
+
+    // Partially filled until the cumulative quantity reaches the order quantity.
+    // A fill on an order still New implies the exchange accepted it.
+    pub fn apply_fill(&mut self, order_id: &str, quantity: u64) -> Result<(), IllegalTransition> {
+        if self.state(order_id) == Some(OrderState::New) {
+            self.transition(order_id, OrderState::Acked)?;
+        }
+        let next = match self.orders.get(order_id) {
+            Some(entry) if entry.filled_quantity + quantity < entry.order.quantity => OrderState::PartiallyFilled,
+            _ => OrderState::Filled,
+        };
+        self.transition(order_id, next)?;
+        if let Some(entry) = self.orders.get_mut(order_id) {
+            entry.filled_quantity += quantity;
+        }
+        Ok(())
+    }
+
+    pub fn reject(&mut self, order_id: &str) -> Result<(), IllegalTransition> {
+        self.transition(order_id, OrderState::Rejected)
+    }
+
+    pub fn cancel(&mut self, order_id: &str) -> Result<(), IllegalTransition> {
+        self.transition(order_id, OrderState::Cancelled)
+    }
+
+    // End of session: anything still working expires. Returns the number of orders expired.
+    pub fn expire_open_orders(&mut self) -> usize {
+        let open_ids: Vec<String> = self
+            .orders
+            .values()
+            .filter(|entry| entry.state.is_open())
+            .map(|entry| entry.order.order_id.clone())
+            .collect();
+        open_ids
+            .iter()
+            .filter(|order_id| self.transition(order_id, OrderState::Expired).is_ok())
+            .count()
+    }
+
+    // Working orders for a client, oldest first
+    pub fn open_orders(&self, client_id: u64) -> Vec<&BlotterEntry> {
+        let mut open: Vec<&BlotterEntry> = self
+            .orders
+            .values()
+            .filter(|entry| entry.order.client_id == client_id && entry.state.is_open())
+            .collect();
+        open.sort_by_key(|entry| entry.sequence);
+        open
+    }
+
+    // Shares still to fill on a client's working orders on one side of a symbol
+    pub fn open_quantity(&self, client_id: u64, stock_symbol: &str, order_action: OrderAction) -> u64 {
+        self.orders
+            .values()
+            .filter(|entry| {
+                entry.order.client_id == client_id
+                    && entry.order.stock_symbol == stock_symbol
+                    && entry.order.order_action == order_action
+                    && entry.state.is_open()
+            })
+            .map(|entry| entry.order.quantity.saturating_sub(entry.filled_quantity))
+            .sum()
+    }
+
+    // Every order for a client this session, oldest first
+    pub fn orders(&self, client_id: u64) -> Vec<&BlotterEntry> {
+        let mut orders: Vec<&BlotterEntry> = self
+            .orders
+            .values()
+            .filter(|entry| entry.order.client_id == client_id)
+            .collect();
+        orders.sort_by_key(|entry| entry.sequence);
+        orders
+    }
+
+    pub fn client_ids(&self) -> Vec<u64> {
+        let mut client_ids: Vec<u64> = self.orders.values().map(|entry| entry.order.client_id).collect();
+        client_ids.sort();
+        client_ids.dedup();
+        client_ids
+    }
+
+    pub fn illegal_transitions(&self) -> &[IllegalTransition] {
+        &self.illegal_transitions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::test_order;
+    use OrderState::*;
+
+    const ALL_STATES: [OrderState; 7] = [New, Acked, PartiallyFilled, Filled, Cancelled, Rejected, Expired];
+
+    fn blotter(orders: &[(&str, u64)]) -> Blotter {
+        let mut blotter = Blotter::new(1);
+        for &(order_id, quantity) in orders {
+            blotter.add(test_order(order_id, OrderAction::Buy, 60.0, quantity));
+        }
+        blotter
+    }
+
+    #[test]
+    fn only_new_acked_and_partially_filled_are_open() {
+        let open: Vec<OrderState> = ALL_STATES.into_iter().filter(|state| state.is_open()).collect();
+        assert_eq!(open, vec![New, Acked, PartiallyFilled]);
+    }
+
+    #[test]
+    fn closed_states_are_terminal() {
+        for from in [Filled, Cancelled, Rejected, Expired] {
+            assert!(ALL_STATES.iter().all(|&to| !from.can_transition_to(to)), "{:?} has an exit", from);
+        }
+        assert!(!New.can_transition_to(Filled));
+        assert!(!New.can_transition_to(PartiallyFilled));
+        assert!(!PartiallyFilled.can_transition_to(Rejected));
+        assert!(!Acked.can_transition_to(New));
+    }
+
+    #[test]
+    fn an_order_works_through_acked_and_partial_fills_to_filled() {
+        let mut blotter = blotter(&[("a", 10)]);
+        assert_eq!(blotter.state("a"), Some(New));
+        blotter.acknowledge("a", Some("EX-1")).unwrap();
+        blotter.apply_fill("a", 4).unwrap();
+        assert_eq!(blotter.state("a"), Some(PartiallyFilled));
+        blotter.apply_fill("a", 6).unwrap();
+
+        let entry = blotter.entry("a").unwrap();
+        assert_eq!(entry.history, vec![New, Acked, PartiallyFilled, Filled]);
+        assert_eq!(entry.filled_quantity, 10);
+        assert_eq!(entry.exchange_order_id.as_deref(), Some("EX-1"));
+        assert!(blotter.illegal_transitions().is_empty());
+    }
+
+    #[test]
+    fn a_fill_before_the_ack_implies_it_and_the_late_ack_is_ignored() {
+        let mut blotter = blotter(&[("a", 10)]);
+        blotter.apply_fill("a", 10).unwrap();
+        blotter.acknowledge("a", Some("EX-1")).unwrap();
+        assert_eq!(blotter.entry("a").unwrap().history, vec![New, Acked, Filled]);
+        assert!(blotter.illegal_transitions().is_empty());
+    }
+
+    #[test]
+    fn illegal_transitions_are_refused_and_flagged() {
+        let mut blotter = blotter(&[("a", 10)]);
+        blotter.reject("a").unwrap();
+        let illegal = blotter.cancel("a").unwrap_err();
+        assert_eq!((illegal.from, illegal.to), (Some(Rejected), Cancelled));
+        assert_eq!(blotter.state("a"), Some(Rejected));
+
+        let unknown = blotter.apply_fill("nope", 1).unwrap_err();
+        assert_eq!((unknown.from, unknown.to), (None, Filled));
+        let flagged: Vec<&str> = blotter.illegal_transitions().iter().map(|illegal| illegal.order_id.as_str()).collect();
+        assert_eq!(flagged, vec!["a", "nope"]);
+    }
+
+    #[test]
+    fn only_working_orders_expire() {
+        let mut blotter = blotter(&[("new", 10), ("partial", 10), ("filled", 10), ("cancelled", 10)]);
+        blotter.apply_fill("partial", 3).unwrap();
+        blotter.apply_fill("filled", 10).unwrap();
+        blotter.cancel("cancelled").unwrap();
+
+        assert_eq!(blotter.expire_open_orders(), 2);
+        assert_eq!(blotter.state("new"), Some(Expired));
+        assert_eq!(blotter.state("partial"), Some(Expired));
+        assert_eq!(blotter.state("filled"), Some(Filled));
+        assert_eq!(blotter.state("cancelled"), Some(Cancelled));
+        assert!(blotter.open_orders(1).is_empty());
+        assert!(blotter.illegal_transitions().is_empty());
+    }
+
+    #[test]
+    fn open_quantity_counts_what_is_left_on_working_orders() {
+        let mut blotter = blotter(&[("done", 5)]);
+        blotter.add(test_order("partial", OrderAction::Sell, 60.0, 10));
+        blotter.add(test_order("new", OrderAction::Sell, 60.0, 4));
+        blotter.add(test_order("cancelled", OrderAction::Sell, 60.0, 7));
+        blotter.apply_fill("partial", 3).unwrap();
+        blotter.apply_fill("done", 5).unwrap();
+        blotter.cancel("cancelled").unwrap();
+
+        assert_eq!(blotter.open_quantity(1, "KO", OrderAction::Sell), 11);
+        assert_eq!(blotter.open_quantity(1, "KO", OrderAction::Buy), 0);
+        assert_eq!(blotter.open_quantity(1, "AAPL", OrderAction::Sell), 0);
+    }
+}
@@ -0,0 +1,139 @@
+// broker/borrow.rs
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+
+pub const BORROW_INVENTORY_PATH: &str = "src/data/borrow_inventory.json";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct BorrowTerms {
+    pub available: u64,        // Shares the desk can lend for the day
+    pub fee_bps_per_year: f64, // Annualised borrow fee, charged daily on short market value
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct BorrowInventory {
+    pub default_terms: BorrowTerms,
+    #[serde(default)]
+    pub symbols: HashMap<String, BorrowTerms>, // Hard-to-borrow overrides
+}
+
+// Stock loan desk shared by all brokers. Each locate belongs to one order; it is drawn down for the
+// day by the shares each fill sells short, and what is left goes back to inventory once the order
+// is filled, rejected or cancelled.
+pub struct BorrowDesk {
+    inventory: BorrowInventory,
+    located: HashMap<String, u64>, // Symbol -> Shares already located today
+    order_locates: HashMap<String, (String, u64)>, // Order ID -> (Symbol, Shares located for it)
+}
+
+impl BorrowDesk {
+    pub fn load(path: &str) -> Self {
+        let inventory = match fs::read_to_string(path) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+                println!("Error parsing borrow inventory, nothing is borrowable: {}", e);
+                BorrowInventory::default()
+            }),
+            Err(_) => BorrowInventory::default(),
+        };
+        Self {
+            inventory,
+            located: HashMap::new(),
+            order_locates: HashMap::new(),
+        }
+    }
+
+    pub fn terms(&self, stock_symbol: &str) -> &BorrowTerms {
+        self.inventory
+            .symbols
+            .get(stock_symbol)
+            .unwrap_or(&self.inventory.default_terms)
+    }
+
+    // Reserve borrow for a short sale order. Returns false if the inventory can't cover it.
+    pub fn locate(&mut self, order_id: &str, stock_symbol: &str, quantity: u64) -> bool {
+        let available = self.terms(stock_symbol).available;
+        let located = self.located.entry(stock_symbol.to_string()).or_insert(0);
+        if *located + quantity > available {
+            return false;
+        }
+        *located += quantity;
+        self.order_locates
+            .entry(order_id.to_string())
+            .or_insert_with(|| (stock_symbol.to_string(), 0))
+            .1 += quantity;
+        true
+    }
+
+    // Shares located for an order; a sale may only go short by this much
+    pub fn located_for(&self, order_id: &str) -> u64 {
+        self.order_locates.get(order_id).map_or(0, |(_, quantity)| *quantity)
+    }
+
+    // A fill sold `quantity` shares short: they stay located for the day, and the rest of the
+    // order's locate remains for its later fills. Returns the shares shorted beyond the locate.
+    pub fn consume(&mut self, order_id: &str, quantity: u64) -> u64 {
+        let Some((_, located)) = self.order_locates.get_mut(order_id) else {
+            return quantity;
+        };
+        let drawn = quantity.min(*located);
+        *located -= drawn;
+        if *located == 0 {
+            self.order_locates.remove(order_id);
+        }
+        quantity - drawn
+    }
+
+    // Return the unused part of a locate to inventory once its order can no longer fill
+    pub fn release(&mut self, order_id: &str) {
+        if let Some((stock_symbol, quantity)) = self.order_locates.remove(order_id) {
+            if let Some(located) = self.located.get_mut(&stock_symbol) {
+                *located = located.saturating_sub(quantity);
+            }
+        }
+    }
+
+    // Fraction of short market value charged per day (ACT/360)
+    pub fn daily_fee_rate(&self, stock_symbol: &str) -> f64 {
+        self.terms(stock_symbol).fee_bps_per_year / 10_000.0 / 360.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn desk(available: u64) -> BorrowDesk {
+        BorrowDesk {
+            inventory: BorrowInventory {
+                default_terms: BorrowTerms { available, fee_bps_per_year: 0.0 },
+                symbols: HashMap::new(),
+            },
+            located: HashMap::new(),
+            order_locates: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn partial_fills_draw_the_locate_down() {
+        let mut desk = desk(100);
+        assert!(desk.locate("short", "AAPL", 10));
+        assert_eq!(desk.consume("short", 4), 0);
+        assert_eq!(desk.located_for("short"), 6);
+        assert_eq!(desk.consume("short", 6), 0);
+        assert_eq!(desk.located_for("short"), 0);
+        // Shorting beyond the locate is reported
+        assert_eq!(desk.consume("short", 2), 2);
+    }
+
+    #[test]
+    fn release_returns_only_the_unused_part() {
+        let mut desk = desk(10);
+        assert!(desk.locate("short", "AAPL", 10));
+        desk.consume("short", 4);
+        desk.release("short");
+        assert!(desk.locate("next", "AAPL", 6));
+        assert!(!desk.locate("more", "AAPL", 1));
+    }
+}
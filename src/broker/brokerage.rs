@@ -0,0 +1,459 @@
+// broker/brokerage.rs
+
+use tokio::sync::{broadcast::{error::RecvError, Receiver}, Mutex};
+use tokio::time::{Duration, Instant};
+use std::sync::atomic::Ordering;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::collections::{HashMap, HashSet};
+use colored::*;
+use crate::models::{new_unique_id, Bar, HaltEvent, Order, OrderAction, OrderStatus, OrderType, PriceUpdate, Quote, Trade, TradingStatus};
+use crate::bars::{BarConfig, BarHistory, BAR_CONFIG_PATH};
+use crate::indicators::{IndicatorConfig, IndicatorSet, INDICATOR_CONFIG_PATH};
+use crate::broker::blotter::Blotter;
+use crate::broker::borrow::BorrowDesk;
+use crate::broker::client::{Client, MarketSnapshot, OrderRules};
+use crate::broker::pre_trade::PreTradeContext;
+use crate::broker::data::{load_client_accounts, ClientAccount};
+use crate::currency::{instrument_currency, FxRates, FX_RATES_PATH, HOME_CURRENCY};
+use crate::money::{round_price, Money, Rounding};
+use crate::order_status_receiver::ExecutionReport;
+use crate::wire;
+use crate::broker::margin::{AccountType, MarginRequirements, MARGIN_REQUIREMENTS_PATH};
+
+// How long a liquidation order is given to fill before the monitor sends another
+const LIQUIDATION_RETRY: Duration = Duration::from_secs(5);
+
+// What a broker knows about a symbol: the exchange's best bid/ask and the last price, from the feed or a trade
+#[derive(Debug, Clone, Copy)]
+pub struct StockQuote {
+    pub bid: Option<f64>,
+    pub ask: Option<f64>,
+    pub last: f64,
+}
+
+impl StockQuote {
+    // None when either side is missing or the quote is crossed
+    pub fn spread(&self) -> Option<f64> {
+        let spread = self.ask? - self.bid?;
+        (spread >= 0.0).then_some(spread)
+    }
+
+    pub fn is_crossed(&self) -> bool {
+        matches!((self.bid, self.ask), (Some(bid), Some(ask)) if bid > ask)
+    }
+
+    // Price a marketable order expects to pay (buys) or receive (sells), falling back to last.
+    // A crossed quote says nothing about where the next trade will print, so it falls back too.
+    pub fn marketable_price(&self, action: &OrderAction) -> f64 {
+        if self.is_crossed() {
+            return self.last;
+        }
+        match action {
+            OrderAction::Buy => self.ask.unwrap_or(self.last),
+            _ => self.bid.unwrap_or(self.last),
+        }
+    }
+}
+
+pub struct Broker {
+    pub id: u64,
+    clients: Vec<Arc<Mutex<Client>>>,
+    price_rx: Receiver<PriceUpdate>, // Broadcast receiver for stock updates
+    halt_rx: Receiver<HaltEvent>, // Broadcast receiver for circuit breaker halts
+    quote_rx: Receiver<Quote>, // Broadcast receiver for top-of-book quotes
+    trade_rx: Receiver<Trade>, // Broadcast receiver for the public trade tape
+    bar_rx: Receiver<Bar>, // Broadcast receiver for closed OHLCV bars
+    stock_data: Arc<Mutex<HashMap<String, StockQuote>>>, // Quote cache per stock
+    bar_history: Arc<Mutex<BarHistory>>, // Recent bars per stock for the clients' strategies
+    indicators: Arc<Mutex<IndicatorSet>>, // Streaming technical indicators per stock for the clients' strategies
+    stop_signal: Arc<AtomicBool>, // Shared stop signal for the broker loop
+    borrow_desk: Arc<std::sync::Mutex<BorrowDesk>>, // Shared stock loan desk for short sale locates
+    blotter: Arc<std::sync::Mutex<Blotter>>, // Lifecycle of every order this broker has sent
+}
+
+pub fn initialize_brokers(
+    total_brokers: u64,
+    price_tx: tokio::sync::broadcast::Sender<PriceUpdate>,
+    halt_tx: tokio::sync::broadcast::Sender<HaltEvent>,
+    quote_tx: tokio::sync::broadcast::Sender<Quote>,
+    trade_tx: tokio::sync::broadcast::Sender<Trade>,
+    bar_tx: tokio::sync::broadcast::Sender<Bar>,
+    borrow_desk: Arc<std::sync::Mutex<BorrowDesk>>,
+) -> Vec<Arc<Mutex<Broker>>> {
+    (1..=total_brokers)
+        .map(|broker_id| {
+            Arc::new(Mutex::new(Broker::new(
+                broker_id,
+                price_tx.clone(),
+                halt_tx.clone(),
+                quote_tx.clone(),
+                trade_tx.clone(),
+                bar_tx.clone(),
+                borrow_desk.clone(),
+            )))
+        })
+        .collect()
+}
+
+impl Broker {
+    pub fn new(
+        id: u64,
+        price_tx: tokio::sync::broadcast::Sender<PriceUpdate>,
+        halt_tx: tokio::sync::broadcast::Sender<HaltEvent>,
+        quote_tx: tokio::sync::broadcast::Sender<Quote>,
+        trade_tx: tokio::sync::broadcast::Sender<Trade>,
+        bar_tx: tokio::sync::broadcast::Sender<Bar>,
+        borrow_desk: Arc<std::sync::Mutex<BorrowDesk>>,
+    ) -> Self {
+        // Initialize clients with unique IDs per broker
+        let mut clients = Vec::new();
+
+        // Offset client IDs based on the broker's ID
+        let start_client_id = (id - 1) * 3 + 1;
+        let end_client_id = id * 3;
+
+        for client_id in start_client_id..=end_client_id {
+            let client = Arc::new(Mutex::new(Client::new(client_id)));
+            clients.push(client);
+        }
+
+        let bar_config = BarConfig::load(BAR_CONFIG_PATH);
+        Self {
+            id,
+            clients,
+            price_rx: price_tx.subscribe(), // Subscribe to the broadcast channel
+            halt_rx: halt_tx.subscribe(),
+            quote_rx: quote_tx.subscribe(),
+            trade_rx: trade_tx.subscribe(),
+            bar_rx: bar_tx.subscribe(),
+            stock_data: Arc::new(Mutex::new(HashMap::new())), // Initialize an empty HashMap
+            bar_history: Arc::new(Mutex::new(BarHistory::new(&bar_config))),
+            indicators: Arc::new(Mutex::new(IndicatorSet::new(
+                IndicatorConfig::load(INDICATOR_CONFIG_PATH),
+                bar_config.signal_interval_secs(),
+            ))),
+            stop_signal: Arc::new(AtomicBool::new(false)), // Initialize the stop signal to false
+            borrow_desk,
+            blotter: Arc::new(std::sync::Mutex::new(Blotter::new(id))),
+        }
+    }
+
+    pub fn blotter(&self) -> Arc<std::sync::Mutex<Blotter>> {
+        self.blotter.clone()
+    }
+
+
+    pub async fn start_broker_task(&mut self, producer: rdkafka::producer::FutureProducer, exec_rx: Receiver<ExecutionReport>) {
+        let stock_data = self.stock_data.clone();
+
+        // Task to listen for price updates, quotes, trades, bars and halts
+        let mut price_rx = self.price_rx.resubscribe();
+        let mut halt_rx = self.halt_rx.resubscribe();
+        let mut quote_rx = self.quote_rx.resubscribe();
+        let mut trade_rx = self.trade_rx.resubscribe();
+        let mut bar_rx = self.bar_rx.resubscribe();
+        tokio::spawn({
+            let stock_data = stock_data.clone();
+            let bar_history = self.bar_history.clone();
+            let indicators = self.indicators.clone();
+            async move {
+                // Halted symbols are dropped from the price cache so no orders are generated for them
+                let mut halted_symbols = HashSet::new();
+                loop {
+                    tokio::select! {
+                        Ok(halt_event) = halt_rx.recv() => {
+                            let mut stock_data_guard = stock_data.lock().await;
+                            match halt_event.status {
+                                TradingStatus::Halted => {
+                                    stock_data_guard.remove(&halt_event.stock_symbol);
+                                    halted_symbols.insert(halt_event.stock_symbol);
+                                }
+                                TradingStatus::Resumed => {
+                                    halted_symbols.remove(&halt_event.stock_symbol);
+                                }
+                            }
+                        }
+                        price_update = price_rx.recv() => {
+                            let Ok(price_update) = price_update else { break };
+                            if halted_symbols.contains(&price_update.name) {
+                                continue;
+                            }
+                            indicators.lock().await.on_price(&price_update);
+                            let mut stock_data_guard = stock_data.lock().await;
+                            stock_data_guard
+                                .entry(price_update.name.clone())
+                                .and_modify(|quote| quote.last = price_update.price)
+                                .or_insert(StockQuote {
+                                    bid: None,
+                                    ask: None,
+                                    last: price_update.price,
+                                });
+
+                            // for client in &clients {
+                            //     let mut client = client.lock().await;
+                            //     client.handle_price_update(&price_update).await;
+                            // }
+                            // println!("Broker {} received update: {:?}", broker_id, price_update);
+                        }
+                        Ok(quote) = quote_rx.recv() => {
+                            // Quotes only refine symbols the feed has priced; a quote that arrives
+                            // first is picked up again from the next snapshot
+                            let mut stock_data_guard = stock_data.lock().await;
+                            if let Some(cached) = stock_data_guard.get_mut(&quote.stock_symbol) {
+                                cached.bid = quote.bid.map(Money::to_f64);
+                                cached.ask = quote.ask.map(Money::to_f64);
+                            }
+                        }
+                        Ok(trade) = trade_rx.recv() => {
+                            if halted_symbols.contains(&trade.stock_symbol) {
+                                continue;
+                            }
+                            indicators.lock().await.on_trade(&trade);
+                            let mut stock_data_guard = stock_data.lock().await;
+                            if let Some(cached) = stock_data_guard.get_mut(&trade.stock_symbol) {
+                                cached.last = trade.price;
+                            }
+                        }
+                        Ok(bar) = bar_rx.recv() => {
+                            indicators.lock().await.on_bar(&bar);
+                            bar_history.lock().await.push(bar);
+                        }
+                    }
+                }
+            }
+        });
+
+        // Margin monitor for this broker's margin accounts
+        let margin_price_rx = self.price_rx.resubscribe();
+        tokio::spawn(Broker::run_margin_monitor(
+            self.id,
+            margin_price_rx,
+            exec_rx,
+            producer.clone(),
+            self.blotter.clone(),
+        ));
+
+        // Main broker loop for generating orders and sending to Kafka
+        loop {
+            if self.stop_signal.load(Ordering::SeqCst) {
+                println!("Stopping broker loop");
+                break; // Exit the loop if the stop signal is set
+            }
+            // One snapshot of the market per round, shared by every client
+            let market = Arc::new(MarketSnapshot::take(&self.stock_data, &self.bar_history, &self.indicators).await);
+            for client in &self.clients {
+                let client = client.clone();
+                let market = market.clone();
+                let stop_signal = self.stop_signal.clone();
+                let broker_id = self.id;
+                let producer = producer.clone();
+                let borrow_desk = self.borrow_desk.clone();
+                let blotter = self.blotter.clone();
+                tokio::spawn(async move {
+                    let mut client = client.lock().await;
+                    let checks = PreTradeContext {
+                        borrow_desk: &borrow_desk,
+                        blotter: &blotter,
+                        holdings_path: "src/data/client_holdings.json",
+                        replacing: None,
+                    };
+                    client
+                        .generate_order(broker_id, &market,
+                        OrderRules { upper_threshold: 5.0, lower_threshold: 5.0, max_orders: 1 }, stop_signal, &checks)
+                        .await;
+            
+                    let orders = client.collect_orders();
+                    for order in orders {
+                        Broker::send_order_to_kafka(order, &producer, &blotter).await;
+                    }
+                });
+            }
+            tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+        }
+    }
+
+    // Re-check every margin account on each price update. When equity drops below maintenance,
+    // flatten positions in order (largest requirement first) with market orders. Accounts are
+    // cached and only reloaded after one of this broker's fills has been applied.
+    async fn run_margin_monitor(
+        broker_id: u64,
+        mut price_rx: Receiver<PriceUpdate>,
+        mut exec_rx: Receiver<ExecutionReport>,
+        producer: rdkafka::producer::FutureProducer,
+        blotter: Arc<std::sync::Mutex<Blotter>>,
+    ) {
+        let margin_requirements = MarginRequirements::load(MARGIN_REQUIREMENTS_PATH);
+        let load_margin_accounts = || -> Vec<ClientAccount> {
+            load_client_accounts("src/data/client_holdings.json", broker_id)
+                .into_iter()
+                .filter(|account| account.account_type == AccountType::Margin)
+                .collect()
+        };
+        let mut accounts = load_margin_accounts();
+        let mut fx_rates = FxRates::load(FX_RATES_PATH);
+        let mut last_prices: HashMap<String, f64> = HashMap::new();
+        // Liquidations in flight, so a position isn't sent twice before its fill lands
+        let mut in_flight: HashMap<(u64, String), Instant> = HashMap::new();
+
+        loop {
+            let price_update = tokio::select! {
+                report = exec_rx.recv() => {
+                    match report {
+                        Ok(report) if report.broker_id == broker_id && report.last_quantity > 0 => {
+                            accounts = load_margin_accounts();
+                            fx_rates = FxRates::load(FX_RATES_PATH);
+                        }
+                        // Missed reports may have been fills
+                        Err(RecvError::Lagged(_)) => accounts = load_margin_accounts(),
+                        _ => {}
+                    }
+                    continue;
+                }
+                price_update = price_rx.recv() => match price_update {
+                    Ok(price_update) => price_update,
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                },
+            };
+            last_prices.insert(price_update.name, price_update.price);
+
+            for account in &accounts {
+                // Margin is computed in USD across all currencies
+                let valuation = account.total_cash_usd(&fx_rates).and_then(|cash| {
+                    let positions = account
+                        .positions
+                        .iter()
+                        .map(|(stock_symbol, &(quantity, average_price))| {
+                            let mark_price = last_prices.get(stock_symbol).copied().unwrap_or(average_price);
+                            let mark_price_usd = fx_rates.convert(mark_price, instrument_currency(stock_symbol), HOME_CURRENCY)?;
+                            Ok((stock_symbol.clone(), (quantity, mark_price_usd)))
+                        })
+                        .collect::<Result<HashMap<String, (i64, f64)>, String>>()?;
+                    Ok((cash, positions))
+                });
+                let (cash, positions) = match valuation {
+                    Ok(valuation) => valuation,
+                    Err(e) => {
+                        println!("Client {}: margin check skipped: {}", account.client_id, e);
+                        continue;
+                    }
+                };
+
+                let plan = margin_requirements.liquidation_plan(cash, &positions);
+                if plan.is_empty() {
+                    continue;
+                }
+                let status = margin_requirements.status(cash, &positions);
+                println!(
+                    "{}",
+                    format!(
+                        "Client {}: MARGIN CALL. Equity {:.2} below maintenance {:.2}, liquidating {} position(s).",
+                        account.client_id, status.equity, status.maintenance_requirement, plan.len()
+                    )
+                    .red().bold()
+                );
+
+                for (stock_symbol, quantity) in plan {
+                    let key = (account.client_id, stock_symbol.clone());
+                    if in_flight.get(&key).is_some_and(|sent_at| sent_at.elapsed() < LIQUIDATION_RETRY) {
+                        continue;
+                    }
+                    in_flight.insert(key, Instant::now());
+
+                    let mark_price = last_prices
+                        .get(&stock_symbol)
+                        .copied()
+                        .unwrap_or(account.positions[&stock_symbol].1);
+                    let order = Order {
+                        broker_id,
+                        client_id: account.client_id,
+                        order_id: new_unique_id(),
+                        price: round_price(&stock_symbol, mark_price, Rounding::Nearest),
+                        stock_symbol,
+                        order_type: OrderType::Market,
+                        order_action: if quantity > 0 { OrderAction::Sell } else { OrderAction::Buy },
+                        quantity: quantity.unsigned_abs(),
+                        status: OrderStatus::Pending,
+                        reject_reason: None,
+                        reject_text: None,
+                        liquidity: None,
+                        exec_id: None,
+                        liquidation: true,
+                        lot_ids: Vec::new(),
+                    };
+                    Broker::send_order_to_kafka(order, &producer, &blotter).await;
+                }
+            }
+        }
+    }
+
+    pub async fn send_order_to_kafka(
+        order: Order,
+        producer: &rdkafka::producer::FutureProducer,
+        blotter: &std::sync::Mutex<Blotter>,
+    ) {
+        let payload = wire::encode(&order);
+        let order_id = order.order_id.clone();
+        // Tracked as New until the matcher acks it
+        blotter.lock().unwrap().add(order);
+        producer
+            .send(
+                rdkafka::producer::FutureRecord::to("orders")
+                    .key(&order_id)
+                    .payload(&payload),
+                rdkafka::util::Timeout::Never,
+            )
+            .await
+            .expect("Failed to send order to Kafka");
+
+        //println!("Broker {} sent order to Kafka: {:?}", self.id, order);
+    }
+
+    // Ask the matcher to take a resting order out of its book. The request carries the order's own ID;
+    // the blotter only moves to Cancelled once the matcher confirms on the cancel_acks topic.
+    pub async fn send_cancel_to_kafka(order: &Order, producer: &rdkafka::producer::FutureProducer) {
+        let cancel = Order {
+            order_action: OrderAction::Cancel,
+            status: OrderStatus::Pending,
+            ..order.clone()
+        };
+        let payload = wire::encode(&cancel);
+        producer
+            .send(
+                rdkafka::producer::FutureRecord::to("orders")
+                    .key(&cancel.order_id)
+                    .payload(&payload),
+                rdkafka::util::Timeout::Never,
+            )
+            .await
+            .expect("Failed to send cancel to Kafka");
+    }
+}
+    
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crossed_quote_has_no_spread_and_prices_at_last() {
+        let quote = StockQuote { bid: Some(101.0), ask: Some(100.0), last: 100.5 };
+        assert!(quote.is_crossed());
+        assert_eq!(quote.spread(), None);
+        assert_eq!(quote.marketable_price(&OrderAction::Buy), 100.5);
+        assert_eq!(quote.marketable_price(&OrderAction::Sell), 100.5);
+    }
+
+    #[test]
+    fn quote_prices_marketable_orders_at_the_far_side() {
+        let quote = StockQuote { bid: Some(99.5), ask: Some(100.0), last: 99.8 };
+        assert_eq!(quote.spread(), Some(0.5));
+        assert_eq!(quote.marketable_price(&OrderAction::Buy), 100.0);
+        assert_eq!(quote.marketable_price(&OrderAction::Sell), 99.5);
+
+        let one_sided = StockQuote { bid: None, ask: Some(100.0), last: 99.8 };
+        assert_eq!(one_sided.spread(), None);
+        assert_eq!(one_sided.marketable_price(&OrderAction::Sell), 99.8);
+    }
+}
@@ -1,16 +1,77 @@
 // broker/client.rs;
-use crate::models::{Order, OrderAction, OrderStatus, OrderType};
+use crate::bars::BarHistory;
+use crate::indicators::IndicatorSet;
+use crate::broker::StockQuote;
+use crate::broker::data::load_client_accounts;
+use crate::broker::margin::AccountType;
+use crate::broker::pre_trade::{pre_trade_check, PreTradeContext};
+use crate::money::{round_price, Rounding};
+use crate::models::{new_unique_id, Order, OrderAction, OrderStatus, OrderType};
 use rand::Rng;
-use serde_json::Value;
-use std::fs::File;
-use std::io::Read;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::collections::HashMap;
-use std::sync::atomic::AtomicU64;
 use std::sync::Arc;
 use tokio::sync::Mutex;
 use colored::*;
 
+// Chance that a sell signal with no long position opens or extends a short (margin accounts only)
+const SHORT_SELL_PROBABILITY: f64 = 0.3;
+// Market orders are not sent into a spread wider than this percentage of the last price
+const MAX_MARKET_SPREAD_PERCENT: f64 = 2.0;
+// Momentum is read over this many of the most recent bars
+const MOMENTUM_BARS: usize = 5;
+// Each percent of momentum tilts the buy/sell coin by this much, capped either way
+const MOMENTUM_TILT_PER_PERCENT: f64 = 0.05;
+const MAX_MOMENTUM_TILT: f64 = 0.2;
+// New positions aren't opened into a move that has already run this far on the RSI
+const RSI_OVERBOUGHT: f64 = 70.0;
+const RSI_OVERSOLD: f64 = 30.0;
+
+// How a client trades each round
+#[derive(Debug, Clone, Copy)]
+pub struct OrderRules {
+    pub upper_threshold: f64, // Percent above the average price at which a long takes profit
+    pub lower_threshold: f64, // Percent below the average price at which a long cuts its loss
+    pub max_orders: usize,
+}
+
+// What the clients' strategies read, copied out of the broker's caches so their locks aren't held
+// while orders are generated
+pub struct MarketSnapshot {
+    quotes: Vec<(String, StockQuote)>,
+    momentum: HashMap<String, f64>,
+    rsi: HashMap<String, f64>,
+}
+
+impl MarketSnapshot {
+    pub async fn take(
+        stock_data: &Mutex<HashMap<String, StockQuote>>,
+        bar_history: &Mutex<BarHistory>,
+        indicators: &Mutex<IndicatorSet>,
+    ) -> Self {
+        let quotes: Vec<(String, StockQuote)> = stock_data
+            .lock()
+            .await
+            .iter()
+            .map(|(stock_symbol, quote)| (stock_symbol.clone(), *quote))
+            .collect();
+        let momentum = {
+            let bar_history = bar_history.lock().await;
+            quotes
+                .iter()
+                .filter_map(|(stock_symbol, _)| Some((stock_symbol.clone(), bar_history.momentum(stock_symbol, MOMENTUM_BARS)?)))
+                .collect()
+        };
+        let rsi = {
+            let indicators = indicators.lock().await;
+            quotes
+                .iter()
+                .filter_map(|(stock_symbol, _)| Some((stock_symbol.clone(), indicators.values(stock_symbol)?.rsi?)))
+                .collect()
+        };
+        MarketSnapshot { quotes, momentum, rsi }
+    }
+}
 
 pub struct Client {
     pub id: u64,
@@ -30,53 +91,33 @@ impl Client {
     pub async fn generate_order(
         &mut self,
         broker_id: u64,
-        stock_data: Arc<Mutex<HashMap<String, f64>>>,
-        global_order_counter: Arc<AtomicU64>,
-        json_file_path: &str, // Path to the client holdings JSON file
-        upper_threshold: f64,
-        lower_threshold: f64,
-        max_orders: usize, 
+        market: &MarketSnapshot,
+        rules: OrderRules,
         stop_signal: Arc<AtomicBool>, 
+        checks: &PreTradeContext<'_>,
     ) {
-        let stock_data_guard = stock_data.lock().await;
-    
-        // Load client portfolio from the JSON file
-        let mut json_data = String::new();
-        let mut file = File::open(json_file_path).expect("Failed to open JSON file");
-        file.read_to_string(&mut json_data).expect("Failed to read JSON file");
-    
-        let parsed_data: Value = serde_json::from_str(&json_data).expect("Failed to parse JSON");
-    
-        // Get the client's portfolio based on broker_id and client_id
-        let client_portfolio = parsed_data["brokers"]
-            .as_array()
-            .and_then(|brokers| {
-                brokers.iter().find(|broker| broker["broker_id"] == broker_id)
-            })
-            .and_then(|broker| {
-                broker["clients"]
-                    .as_array()
-                    .and_then(|clients| {
-                        clients.iter().find(|client| client["client_id"] == self.id)
-                    })
-            })
-            .and_then(|client| client["portfolio"].as_object());
-    
-        if client_portfolio.is_none() {
+        // The client's account as last written by the fill consumer
+        let Some(account) = load_client_accounts(checks.holdings_path, broker_id)
+            .into_iter()
+            .find(|account| account.client_id == self.id)
+        else {
             println!(
                 "Client {} (Broker {}) has no portfolio. Skipping sell order generation.",
                 self.id, broker_id
             );
             return;
-        }
-    
-        let portfolio = client_portfolio.unwrap();
-    
+        };
+        let last_prices: HashMap<String, f64> = market
+            .quotes
+            .iter()
+            .map(|(stock_symbol, quote)| (stock_symbol.clone(), quote.last))
+            .collect();
+
         // Generate orders for both buy and sell cases
-        let available_stocks: Vec<(&String, &f64)> = stock_data_guard.iter().collect();
         let mut orders_generated = 0; 
-        for (stock_symbol, &market_price) in available_stocks.iter() {
-            if orders_generated >= max_orders {
+        for (stock_symbol, quote) in market.quotes.iter() {
+            let market_price = quote.last;
+            if orders_generated >= rules.max_orders {
                 break;
             }
             if stop_signal.load(Ordering::SeqCst) {
@@ -85,8 +126,17 @@ impl Client {
             }
             
             let mut rng = rand::thread_rng();
-            let is_limit_order = rng.gen_bool(0.7); // 70% Limit Orders
-            let is_buy_order = rng.gen_bool(0.5);   // 50% Buy, 50% Sell
+            let spread_too_wide = quote.spread().is_some_and(|spread| spread / market_price * 100.0 > MAX_MARKET_SPREAD_PERCENT);
+            let is_limit_order = rng.gen_bool(0.7) || spread_too_wide; // 70% Limit Orders
+            // 50% Buy, 50% Sell, tilted towards the recent trend in the bars
+            let momentum_tilt = market
+                .momentum
+                .get(stock_symbol)
+                .map_or(0.0, |momentum| (momentum * MOMENTUM_TILT_PER_PERCENT).clamp(-MAX_MOMENTUM_TILT, MAX_MOMENTUM_TILT));
+            let is_buy_order = rng.gen_bool(0.5 + momentum_tilt);
+            let rsi = market.rsi.get(stock_symbol).copied();
+            let overbought = rsi.is_some_and(|rsi| rsi >= RSI_OVERBOUGHT);
+            let oversold = rsi.is_some_and(|rsi| rsi <= RSI_OVERSOLD);
             let quantity = rng.gen_range(1..=10); // Random quantity between 1 and 10
     
             let price_modifier = {
@@ -100,13 +150,53 @@ impl Client {
                 }
             };
     
+            // Limits are snapped to the tick grid on the passive side: buys down, sells up
             let limit_price = market_price * price_modifier;
-            let rounded_limit_price = (limit_price * 100.0).round() / 100.0;
+            let buy_limit_price = round_price(stock_symbol, limit_price, Rounding::Down);
+            let sell_limit_price = round_price(stock_symbol, limit_price, Rounding::Up);
             let mut valid_order = None;
+
+            // Current position: positive when long, negative when short
+            let (current_quantity, average_price) = account
+                .positions
+                .get(stock_symbol.as_str())
+                .copied()
+                .unwrap_or((0, 0.0));
+            let price_increase_threshold = average_price * (1.0 + rules.upper_threshold / 100.0);
+            let price_decrease_threshold = average_price * (1.0 - rules.lower_threshold / 100.0);
     
-            if is_buy_order {
+            if is_buy_order && current_quantity < 0 {
+                // BUY TO COVER LOGIC
+                if market_price <= price_decrease_threshold || market_price >= price_increase_threshold {
+                    // Take profit 5% below market, or stop out 10% above it
+                    let limit_price = if market_price <= price_decrease_threshold {
+                        market_price * 0.95
+                    } else {
+                        market_price * 1.10
+                    };
+                    valid_order = Some(Order {
+                        broker_id,
+                        client_id: self.id,
+                        order_id: String::new(), // Placeholder
+                        stock_symbol: stock_symbol.to_string(),
+                        order_type: OrderType::Limit,
+                        order_action: OrderAction::Buy,
+                        price: round_price(stock_symbol, limit_price, Rounding::Down),
+                        quantity: current_quantity.unsigned_abs(),
+                        status: OrderStatus::Pending,
+                        reject_reason: None,
+                        reject_text: None,
+                        liquidity: None,
+                        exec_id: None,
+                        liquidation: false,
+                        lot_ids: Vec::new(),
+                    });
+                }
+            } else if is_buy_order {
                 // BUY ORDER LOGIC
-                if is_limit_order {
+                if overbought {
+                    // Don't chase an overbought stock
+                } else if is_limit_order {
                     valid_order = Some(Order {
                         broker_id,
                         client_id: self.id,
@@ -114,12 +204,19 @@ impl Client {
                         stock_symbol: stock_symbol.to_string(),
                         order_type: OrderType::Limit,
                         order_action: OrderAction::Buy,
-                        price: rounded_limit_price,
+                        price: buy_limit_price,
                         quantity,
                         status: OrderStatus::Pending,
+                        reject_reason: None,
+                        reject_text: None,
+                        liquidity: None,
+                        exec_id: None,
+                        liquidation: false,
+                        lot_ids: Vec::new(),
                     });
                 } else {
-                    let rounded_market_price = (market_price * 100.0).round() / 100.0;
+                    // Market buys expect to pay the offer when one is resting
+                    let rounded_market_price = round_price(stock_symbol, quote.marketable_price(&OrderAction::Buy), Rounding::Nearest);
                     valid_order = Some(Order {
                         broker_id,
                         client_id: self.id,
@@ -130,88 +227,105 @@ impl Client {
                         price: rounded_market_price,
                         quantity,
                         status: OrderStatus::Pending,
+                        reject_reason: None,
+                        reject_text: None,
+                        liquidity: None,
+                        exec_id: None,
+                        liquidation: false,
+                        lot_ids: Vec::new(),
                     });
                 }
             } else {
                 // SELL ORDER LOGIC
-                if let Some(stock_data) = portfolio.get(stock_symbol.as_str()) {
-                    let current_quantity = stock_data["quantity"].as_u64().unwrap_or(0);
-                    let average_price = stock_data["average_price"].as_f64().unwrap_or(0.0);
-
-                    if current_quantity > 0 {
-                        let price_increase_threshold = average_price * (1.0 + upper_threshold / 100.0);
-                        let price_decrease_threshold = average_price * (1.0 - lower_threshold / 100.0);
-
-                        // println!(
-                        //     "Stock: {}, Avg Price: {:.2}, Market Price: {:.2}, Profit Threshold: {:.2}, Loss Threshold: {:.2}",
-                        //     stock_symbol, average_price, market_price, price_increase_threshold, price_decrease_threshold
-                        // );
-
-                        if market_price >= price_increase_threshold {
-                            // Generate a Limit Sell Order for profit-taking
-                            let limit_price = market_price * 1.05; // Set limit price 5% above current market price
-                            valid_order = Some(Order {
-                                broker_id,
-                                client_id: self.id,
-                                order_id: String::new(), // Placeholder
-                                stock_symbol: stock_symbol.to_string(),
-                                order_type: OrderType::Limit,
-                                order_action: OrderAction::Sell,
-                                price: (limit_price * 100.0).round() / 100.0,
-                                quantity: current_quantity,
-                                status: OrderStatus::Pending,
-                            });
-                        } else if market_price <= price_decrease_threshold {
-                            // Generate a Limit Sell Order for loss mitigation
-                            let limit_price = market_price * 0.90; // Set limit price 10% below current market price
-                            valid_order = Some(Order {
-                                broker_id,
-                                client_id: self.id,
-                                order_id: String::new(), // Placeholder
-                                stock_symbol: stock_symbol.to_string(),
-                                order_type: OrderType::Limit,
-                                order_action: OrderAction::Sell,
-                                price: (limit_price * 100.0).round() / 100.0,
-                                quantity: current_quantity,
-                                status: OrderStatus::Pending,
-                            });
-                        }
+                if current_quantity > 0 {
+                    // println!(
+                    //     "Stock: {}, Avg Price: {:.2}, Market Price: {:.2}, Profit Threshold: {:.2}, Loss Threshold: {:.2}",
+                    //     stock_symbol, average_price, market_price, price_increase_threshold, price_decrease_threshold
+                    // );
+
+                    if market_price >= price_increase_threshold {
+                        // Generate a Limit Sell Order for profit-taking
+                        let limit_price = market_price * 1.05; // Set limit price 5% above current market price
+                        valid_order = Some(Order {
+                            broker_id,
+                            client_id: self.id,
+                            order_id: String::new(), // Placeholder
+                            stock_symbol: stock_symbol.to_string(),
+                            order_type: OrderType::Limit,
+                            order_action: OrderAction::Sell,
+                            price: round_price(stock_symbol, limit_price, Rounding::Up),
+                            quantity: current_quantity as u64,
+                            status: OrderStatus::Pending,
+                            reject_reason: None,
+                            reject_text: None,
+                            liquidity: None,
+                            exec_id: None,
+                            liquidation: false,
+                            lot_ids: Vec::new(),
+                        });
+                    } else if market_price <= price_decrease_threshold {
+                        // Generate a Limit Sell Order for loss mitigation
+                        let limit_price = market_price * 0.90; // Set limit price 10% below current market price
+                        valid_order = Some(Order {
+                            broker_id,
+                            client_id: self.id,
+                            order_id: String::new(), // Placeholder
+                            stock_symbol: stock_symbol.to_string(),
+                            order_type: OrderType::Limit,
+                            order_action: OrderAction::Sell,
+                            price: round_price(stock_symbol, limit_price, Rounding::Up),
+                            quantity: current_quantity as u64,
+                            status: OrderStatus::Pending,
+                            reject_reason: None,
+                            reject_text: None,
+                            liquidity: None,
+                            exec_id: None,
+                            liquidation: false,
+                            lot_ids: Vec::new(),
+                        });
                     }
+                } else if account.account_type == AccountType::Margin && !oversold && rng.gen_bool(SHORT_SELL_PROBABILITY) {
+                    // SHORT SALE LOGIC: borrow is located by the pre-trade check
+                    valid_order = Some(Order {
+                        broker_id,
+                        client_id: self.id,
+                        order_id: String::new(), // Placeholder
+                        stock_symbol: stock_symbol.to_string(),
+                        order_type: OrderType::Limit,
+                        order_action: OrderAction::Sell,
+                        price: sell_limit_price,
+                        quantity,
+                        status: OrderStatus::Pending,
+                        reject_reason: None,
+                        reject_text: None,
+                        liquidity: None,
+                        exec_id: None,
+                        liquidation: false,
+                        lot_ids: Vec::new(),
+                    });
                 }
             }
 
             if let Some(mut order) = valid_order {
                 // Assign a unique order ID after validation
-                order.order_id = format!(
-                    "Order {}",
-                    global_order_counter.fetch_add(1, Ordering::SeqCst)
-                );
+                order.order_id = new_unique_id();
+
+                // Buying power and short sale locates, as for orders from the FIX gateway and the API
+                if pre_trade_check(&order, &account, &last_prices, checks).is_err() {
+                    continue;
+                }
+                let total_cost = order.price.times(order.quantity as i64).to_f64();
 
                 // Log Buy or Sell details
                 if order.order_action == OrderAction::Buy {
-                    let total_cost = order.quantity as f64 * order.price;
-                    //if self.capital >= total_cost {
-                        println!(
-                            "{}",
-                            format!(
-                                "Client {}: Placing Buy order for {} ({}, {} shares at {:.2} per share). Total Cost: {:.2}",
-                                self.id, order.stock_symbol, order.order_id, order.quantity, order.price, total_cost
-                            )
-                            .bright_blue().bold()
-                        );
-                        //self.capital -= total_cost;
-                   // } 
-                    // else {
-                    //     println!(
-                    //         "{}",
-                    //         format!(
-                    //             "Client {}: Insufficient funds for Buy order on {}. Required: {:.2}, Available: {:.2}",
-                    //             self.id, order.stock_symbol, total_cost, self.capital
-                    //         )
-                    //         .bright_yellow().bold()
-                    //     );
-                    //     continue;
-                    // }
+                    println!(
+                        "{}",
+                        format!(
+                            "Client {}: Placing Buy order for {} ({}, {} shares at {:.2} per share). Total Cost: {:.2}",
+                            self.id, order.stock_symbol, order.order_id, order.quantity, order.price, total_cost
+                        )
+                        .bright_blue().bold()
+                    );
                 } else {
                     println!(
                         "{}",
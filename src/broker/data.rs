@@ -1,7 +1,18 @@
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fs::File;
 use std::io::{Read, Write};
+use std::sync::{Mutex, MutexGuard};
+use crate::auction::load_official_prices;
+use crate::broker::borrow::BorrowDesk;
+use crate::broker::lots::{relieve_lots, LotMethod, RealizedGain, TaxLot};
+use crate::broker::margin::AccountType;
+use crate::corporate_actions::split_quantity;
+use crate::currency::{instrument_currency, FxRates, FX_RATES_PATH, HOME_CURRENCY};
+use crate::fees::FillFees;
+use crate::models::{new_unique_id, CorporateAction, CorporateActionKind, Order, OrderAction, RejectReason};
+use crate::money::Money;
+use crate::reconciliation::{append_fill, append_split, APPLIED_FILLS_PATH};
 
 #[derive(Serialize, Deserialize)]
 struct ClientData {
@@ -9,12 +20,136 @@ struct ClientData {
     portfolio: HashMap<String, StockHolding>, // Stock symbol to StockHolding mapping
     buy_transaction_count: u64,  // Count of completed buy transactions
     sell_transaction_count: u64, // Count of completed sell transactions
-    capital: f64,
+    capital: Money, // Cash in the home currency (USD)
+    #[serde(default)]
+    cash_balances: HashMap<String, Money>, // Cash in other currencies
+    #[serde(default)]
+    account_type: AccountType,
+    #[serde(default)]
+    rejection_counts: HashMap<RejectReason, u64>, // Rejected orders tallied by reason
+    // Cost and income totals below are kept in the home currency
+    #[serde(default)]
+    commissions_paid: Money,
+    #[serde(default)]
+    exchange_fees_paid: Money, // Net of rebates
+    #[serde(default)]
+    borrow_fees_paid: Money,
+    #[serde(default)]
+    dividends_received: Money, // Negative when short positions paid dividends
+    #[serde(default)]
+    ledger: Vec<LedgerEntry>, // One entry per applied fill
+    #[serde(default)]
+    lot_method: LotMethod, // How closing fills relieve tax lots
+    #[serde(default)]
+    realized_gains: Vec<RealizedGain>, // One entry per closed (part of a) lot
 }
+
+impl ClientData {
+    // Cash balance in the given currency
+    fn cash_mut(&mut self, currency: &str) -> &mut Money {
+        if currency == HOME_CURRENCY {
+            &mut self.capital
+        } else {
+            self.cash_balances.entry(currency.to_string()).or_insert(Money::ZERO)
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 struct StockHolding {
+    quantity: i64, // Negative for short positions
+    average_price: Money,
+    #[serde(default)]
+    lots: Vec<TaxLot>, // Open lots, oldest first
+}
+
+impl StockHolding {
+    // Apply a signed fill (positive = buy). Adding to a position re-weights the average price,
+    // reducing it keeps the average, and flipping through zero opens the new side at the fill price.
+    // The average is held in fixed point, rounded to the nearest unit once per fill.
+    // Any shares the fill opens become a lot identified by `lot_id`; `lot_ids` name the lots to
+    // relieve under specific identification.
+    // Returns the lot slices closed by the fill as (quantity, open price, opened at).
+    fn apply_fill(
+        &mut self,
+        signed_quantity: i64,
+        price: Money,
+        lot_id: &str,
+        lot_ids: &[String],
+        method: LotMethod,
+        now: &str,
+    ) -> Vec<(i64, Money, String)> {
+        // Holdings written before lot tracking become a single lot at their average cost
+        if self.lots.is_empty() && self.quantity != 0 {
+            self.lots.push(TaxLot {
+                id: String::new(),
+                quantity: self.quantity,
+                price: self.average_price,
+                opened_at: String::new(),
+            });
+        }
+
+        let mut closed = Vec::new();
+        let mut opening_quantity = signed_quantity;
+        if self.quantity != 0 && self.quantity.signum() != signed_quantity.signum() {
+            let closing_quantity = signed_quantity.abs().min(self.quantity.abs());
+            closed = relieve_lots(&mut self.lots, closing_quantity * self.quantity.signum(), method, lot_ids);
+            opening_quantity = signed_quantity + closing_quantity * self.quantity.signum();
+        }
+        if opening_quantity != 0 {
+            self.lots.push(TaxLot {
+                id: lot_id.to_string(),
+                quantity: opening_quantity,
+                price,
+                opened_at: now.to_string(),
+            });
+        }
+
+        let new_quantity = self.quantity + signed_quantity;
+        if self.quantity == 0 || self.quantity.signum() == signed_quantity.signum() {
+            let total_cost = self.average_price.times(self.quantity.abs()) + price.times(signed_quantity.abs());
+            self.average_price = total_cost.per_share(new_quantity.abs());
+        } else if new_quantity.signum() == -self.quantity.signum() {
+            self.average_price = price;
+        } else if new_quantity == 0 {
+            self.average_price = Money::ZERO;
+        }
+        self.quantity = new_quantity;
+        closed
+    }
+
+    // Rescale open lots on a split, keeping their total equal to the truncated position
+    fn apply_split(&mut self, ratio: f64, new_quantity: i64) {
+        for lot in self.lots.iter_mut() {
+            lot.quantity = split_quantity(lot.quantity, ratio).0;
+            lot.price = lot.price.split_adjusted(ratio);
+        }
+        let lot_total: i64 = self.lots.iter().map(|lot| lot.quantity).sum();
+        if let Some(last) = self.lots.last_mut() {
+            last.quantity += new_quantity - lot_total;
+        }
+        self.lots.retain(|lot| lot.quantity != 0);
+        self.average_price = self.average_price.split_adjusted(ratio);
+        self.quantity = new_quantity;
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct LedgerEntry {
+    stock_symbol: String,
+    is_buy: bool,
     quantity: u64,
-    average_price: f64,
+    price: Money,
+    commission: Money,
+    exchange_fee: Money,
+    #[serde(default = "home_currency")]
+    currency: String, // Currency of price and fees
+    #[serde(default)]
+    exec_id: Option<String>, // Matcher's fill ID, used to skip redelivered fills
+}
+
+fn home_currency() -> String {
+    HOME_CURRENCY.to_string()
 }
 
 
@@ -27,10 +162,170 @@ struct BrokerData {
 #[derive(Serialize, Deserialize)]
 struct BrokersData {
     brokers: Vec<BrokerData>,
+    #[serde(default)]
+    consumer_offsets: HashMap<String, i64>, // "topic:partition" -> Next offset to apply
+}
+
+// Position of the Kafka message whose effects are being written
+pub struct ConsumedOffset<'a> {
+    pub topic: &'a str,
+    pub partition: i32,
+    pub offset: i64,
+}
+
+fn offset_key(topic: &str, partition: i32) -> String {
+    format!("{}:{}", topic, partition)
+}
+
+impl BrokersData {
+    // Stored alongside the holdings so the file and its consumed offsets always change together
+    fn record_offset(&mut self, consumed: &ConsumedOffset) {
+        self.consumer_offsets
+            .insert(offset_key(consumed.topic, consumed.partition), consumed.offset + 1);
+    }
+}
+
+// Next offset to apply per "topic:partition", as last written with the holdings
+pub fn load_consumer_offsets(file_path: &str) -> HashMap<String, i64> {
+    std::fs::read_to_string(file_path)
+        .ok()
+        .and_then(|json| serde_json::from_str::<BrokersData>(&json).ok())
+        .map(|data| data.consumer_offsets)
+        .unwrap_or_default()
+}
+
+// True when a message was already applied to the holdings file before a restart
+pub fn is_already_applied(offsets: &HashMap<String, i64>, topic: &str, partition: i32, offset: i64) -> bool {
+    offsets
+        .get(&offset_key(topic, partition))
+        .is_some_and(|&next_offset| offset < next_offset)
+}
+
+// Fills, rejections, corporate actions and borrow fees all read, modify and rewrite the whole
+// holdings file from different tasks; each holds this lock from its read to its write so none
+// of them overwrites another's update
+static HOLDINGS_LOCK: Mutex<()> = Mutex::new(());
+
+fn lock_holdings() -> MutexGuard<'static, ()> {
+    // The file itself is only ever replaced whole, so a writer that panicked left nothing half-done
+    HOLDINGS_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+// Replace the holdings file in one step: write a temporary file, then rename it over the original
+fn write_holdings(file_path: &str, data: &BrokersData) -> bool {
+    let updated_json = match serde_json::to_string_pretty(data) {
+        Ok(json) => json,
+        Err(e) => {
+            println!("Error serializing updated JSON data: {}", e);
+            return false;
+        }
+    };
+
+    let temp_path = format!("{}.tmp", file_path);
+    let result = File::create(&temp_path)
+        .and_then(|mut f| f.write_all(updated_json.as_bytes()).and_then(|_| f.sync_all()))
+        .and_then(|_| std::fs::rename(&temp_path, file_path));
+    if let Err(e) = result {
+        println!("Error writing updated JSON data: {}", e);
+        return false;
+    }
+    true
+}
+
+// Read-only view of a client's account for risk checks outside this module
+pub struct ClientAccount {
+    pub client_id: u64,
+    pub account_type: AccountType,
+    pub capital: f64,
+    pub cash_balances: HashMap<String, f64>,
+    pub positions: HashMap<String, (i64, f64)>, // Stock symbol -> (Quantity, Average Price)
+}
+
+impl ClientAccount {
+    // All cash converted to USD; fails if any balance is in a currency with no rate
+    pub fn total_cash_usd(&self, fx_rates: &FxRates) -> Result<f64, String> {
+        let foreign_cash = self
+            .cash_balances
+            .iter()
+            .map(|(currency, amount)| fx_rates.convert(*amount, currency, HOME_CURRENCY))
+            .sum::<Result<f64, String>>()?;
+        Ok(self.capital + foreign_cash)
+    }
+
+    // Every cash balance by currency, the home currency included
+    pub fn cash_by_currency(&self) -> HashMap<String, f64> {
+        let mut cash = self.cash_balances.clone();
+        *cash.entry(HOME_CURRENCY.to_string()).or_insert(0.0) += self.capital;
+        cash
+    }
+}
+
+pub fn load_client_accounts(file_path: &str, broker_id: u64) -> Vec<ClientAccount> {
+    let data: BrokersData = match std::fs::read_to_string(file_path).map(|json| serde_json::from_str(&json)) {
+        Ok(Ok(d)) => d,
+        _ => return Vec::new(),
+    };
+    data.brokers
+        .into_iter()
+        .filter(|broker| broker.broker_id == broker_id)
+        .flat_map(|broker| broker.clients)
+        .map(client_account)
+        .collect()
+}
+
+// A client's account and the broker holding it, for callers that only know the client ID
+pub fn find_client_account(file_path: &str, client_id: u64) -> Option<(u64, ClientAccount)> {
+    let data: BrokersData = std::fs::read_to_string(file_path)
+        .ok()
+        .and_then(|json| serde_json::from_str(&json).ok())?;
+    data.brokers.into_iter().find_map(|broker| {
+        let broker_id = broker.broker_id;
+        broker
+            .clients
+            .into_iter()
+            .find(|client| client.client_id == client_id)
+            .map(|client| (broker_id, client_account(client)))
+    })
+}
+
+// Every client's share count per symbol, for reconciliation against the exchange's fills
+pub fn load_positions(file_path: &str) -> BTreeMap<(u64, String), i64> {
+    let data: BrokersData = match std::fs::read_to_string(file_path).map(|json| serde_json::from_str(&json)) {
+        Ok(Ok(d)) => d,
+        _ => return BTreeMap::new(),
+    };
+    data.brokers
+        .iter()
+        .flat_map(|broker| broker.clients.iter())
+        .flat_map(|client| {
+            client
+                .portfolio
+                .iter()
+                .map(|(stock_symbol, holding)| ((client.client_id, stock_symbol.clone()), holding.quantity))
+        })
+        .collect()
+}
+
+fn client_account(client: ClientData) -> ClientAccount {
+    ClientAccount {
+        client_id: client.client_id,
+        account_type: client.account_type,
+        capital: client.capital.to_f64(),
+        cash_balances: client
+            .cash_balances
+            .iter()
+            .map(|(currency, amount)| (currency.clone(), amount.to_f64()))
+            .collect(),
+        positions: client
+            .portfolio
+            .iter()
+            .map(|(stock_symbol, holding)| (stock_symbol.clone(), (holding.quantity, holding.average_price.to_f64())))
+            .collect(),
+    }
 }
 
 pub fn reset_client_holdings_json(file_path: &str, total_brokers: u64, clients_per_broker: u64) {
-    let initial_capital = 10_000.0; // Default initial capital
+    let initial_capital = Money::from_f64(10_000.0); // Default initial capital
     let mut brokers_data = Vec::new();
 
     for broker_id in 1..=total_brokers {
@@ -44,6 +339,23 @@ pub fn reset_client_holdings_json(file_path: &str, total_brokers: u64, clients_p
                 buy_transaction_count: 0,
                 sell_transaction_count: 0,
                 capital: initial_capital,
+                cash_balances: HashMap::new(),
+                // Every third client trades on margin
+                account_type: if client_id % 3 == 0 { AccountType::Margin } else { AccountType::Cash },
+                rejection_counts: HashMap::new(),
+                commissions_paid: Money::ZERO,
+                exchange_fees_paid: Money::ZERO,
+                borrow_fees_paid: Money::ZERO,
+                dividends_received: Money::ZERO,
+                ledger: Vec::new(),
+                // Rotate relief methods across clients
+                lot_method: match client_id % 4 {
+                    0 => LotMethod::Fifo,
+                    1 => LotMethod::Lifo,
+                    2 => LotMethod::Hifo,
+                    _ => LotMethod::Average,
+                },
+                realized_gains: Vec::new(),
             });
         }
         brokers_data.push(BrokerData {
@@ -52,10 +364,14 @@ pub fn reset_client_holdings_json(file_path: &str, total_brokers: u64, clients_p
         });
     }
 
-    let data = BrokersData { brokers: brokers_data };
+    let data = BrokersData {
+        brokers: brokers_data,
+        consumer_offsets: HashMap::new(),
+    };
 
     let json_data = serde_json::to_string_pretty(&data).expect("Failed to serialize data to JSON");
 
+    let _holdings = lock_holdings();
     let mut file = File::create(file_path).expect("Failed to create/reset JSON file");
     file.write_all(json_data.as_bytes())
         .expect("Failed to write to JSON file");
@@ -64,117 +380,319 @@ pub fn reset_client_holdings_json(file_path: &str, total_brokers: u64, clients_p
 }
 
 
+// Returns the number of shares the fill sold short once it has been written to the holdings file.
+// A fill whose exec ID is already in the client's ledger is a redelivery and is not applied again
+// (Ok(None)). The exchange has already executed the fill, so it is applied whatever the position;
+// locates are enforced by the pre-trade check. Applied fills are journalled under the holdings lock,
+// in the order the holdings took them.
 pub async fn update_client_portfolio_in_json(
     file_path: &str,
-    client_id: u64,
-    stock_symbol: String,
-    quantity: u64,
-    is_buy: bool,
-    price_per_unit: f64,
-) {
+    fill: &Order,
+    fees: FillFees,
+    consumed: Option<ConsumedOffset<'_>>,
+) -> Result<Option<u64>, String> {
+    let client_id = fill.client_id;
+    let exec_id = fill.exec_id.as_deref();
+    let stock_symbol = fill.stock_symbol.clone();
+    let quantity = fill.quantity;
+    let is_buy = matches!(fill.order_action, OrderAction::Buy);
+    let price_per_unit = fill.price;
+
+    let _holdings = lock_holdings();
     // Read the existing JSON file
     let mut file = match File::open(file_path) {
         Ok(f) => f,
-        Err(e) => {
-            println!("Error opening JSON file: {}", e);
-            return;
-        }
+        Err(e) => return Err(format!("Error opening JSON file: {}", e)),
     };
 
     let mut json_data = String::new();
     if let Err(e) = file.read_to_string(&mut json_data) {
-        println!("Error reading JSON file: {}", e);
-        return;
+        return Err(format!("Error reading JSON file: {}", e));
     }
 
     // Parse the JSON data
     let mut data: BrokersData = match serde_json::from_str(&json_data) {
         Ok(d) => d,
-        Err(e) => {
-            println!("Error parsing JSON data: {}", e);
-            return;
-        }
+        Err(e) => return Err(format!("Error parsing JSON data: {}", e)),
     };
 
+    let currency = instrument_currency(&stock_symbol);
+    let fx_rates = FxRates::load(FX_RATES_PATH);
+
     // Update the client portfolio and capital
-    let mut updated = false;
+    let mut shorted_quantity = None;
     for broker in data.brokers.iter_mut() {
         for client in broker.clients.iter_mut() {
             if client.client_id == client_id {
-                if is_buy {
-                    // Update portfolio for Buy
-                    let holding = client.portfolio.entry(stock_symbol.clone()).or_insert(StockHolding {
-                        quantity: 0,
-                        average_price: 0.0,
-                    });
+                if let Some(exec_id) = exec_id {
+                    if client.ledger.iter().any(|entry| entry.exec_id.as_deref() == Some(exec_id)) {
+                        println!("Fill {} already applied for client {}, skipping", exec_id, client_id);
+                        return Ok(None);
+                    }
+                }
 
-                    let total_cost = quantity as f64 * price_per_unit;
+                // Sells beyond the long quantity open or extend a short, drawing on the order's locate
+                let long_quantity = client.portfolio.get(&stock_symbol).map_or(0, |holding| holding.quantity.max(0)) as u64;
+                let short_quantity = if is_buy { 0 } else { quantity.saturating_sub(long_quantity) };
 
-                    // Calculate the new average price
-                    holding.average_price = ((holding.average_price * holding.quantity as f64) + total_cost)
-                        / (holding.quantity + quantity) as f64;
-                    holding.quantity += quantity;
+                // Update the position
+                let signed_quantity = if is_buy { quantity as i64 } else { -(quantity as i64) };
+                let holding = client.portfolio.entry(stock_symbol.clone()).or_insert(StockHolding {
+                    quantity: 0,
+                    average_price: Money::ZERO,
+                    lots: Vec::new(),
+                });
+                let now = chrono::Utc::now().to_rfc3339();
+                let lot_id = exec_id.map_or_else(new_unique_id, str::to_string);
+                let closed_lots = holding.apply_fill(signed_quantity, price_per_unit, &lot_id, &fill.lot_ids, client.lot_method, &now);
+                for (lot_quantity, open_price, opened_at) in closed_lots {
+                    client.realized_gains.push(RealizedGain {
+                        stock_symbol: stock_symbol.clone(),
+                        quantity: lot_quantity,
+                        open_price,
+                        close_price: price_per_unit,
+                        gain: (price_per_unit - open_price).times(lot_quantity),
+                        currency: currency.to_string(),
+                        method: client.lot_method,
+                        opened_at,
+                        closed_at: now.clone(),
+                    });
+                }
 
-                    // Deduct capital
-                    client.capital -= total_cost;
+                // Remove stock entry if quantity becomes zero
+                if holding.quantity == 0 {
+                    client.portfolio.remove(&stock_symbol);
+                }
+
+                // Settle in the instrument's currency
+                let notional = price_per_unit.times(quantity as i64);
+                if is_buy {
+                    // Deduct cash and fees
+                    *client.cash_mut(currency) -= notional + fees.total();
                     client.buy_transaction_count += 1; // Increment buy transaction count
                 } else {
-                    // Update portfolio for Sell
-                    if let Some(holding) = client.portfolio.get_mut(&stock_symbol) {
-                        if holding.quantity >= quantity {
-                            holding.quantity -= quantity;
-
-                            // Add capital
-                            let total_revenue = quantity as f64 * price_per_unit;
-                            client.capital += total_revenue;
-                            client.sell_transaction_count += 1; // Increment sell transaction count
-
-                            // Remove stock entry if quantity becomes zero
-                            if holding.quantity == 0 {
-                                client.portfolio.remove(&stock_symbol);
-                            }
-                        } else {
-                            println!(
-                                "Error: Client {} has insufficient shares of {} to sell.",
-                                client_id, stock_symbol
-                            );
-                            return;
-                        }
-                    } else {
-                        println!(
-                            "Error: Client {} does not own any shares of {} to sell.",
-                            client_id, stock_symbol
-                        );
-                        return;
-                    }
+                    // Add cash (short sale proceeds included), net of fees
+                    *client.cash_mut(currency) += notional - fees.total();
+                    client.sell_transaction_count += 1; // Increment sell transaction count
+                }
+                // Record the fill and its costs. The ledger keeps the fees in the instrument's currency,
+                // so a fill without a rate still applies and only the USD totals miss it.
+                match fx_rates.convert_money(fees.commission, currency, HOME_CURRENCY) {
+                    Ok(commission_usd) => client.commissions_paid += commission_usd,
+                    Err(e) => println!("Client {}: commission left out of the USD total: {}", client_id, e),
+                }
+                match fx_rates.convert_money(fees.exchange_fee, currency, HOME_CURRENCY) {
+                    Ok(exchange_fee_usd) => client.exchange_fees_paid += exchange_fee_usd,
+                    Err(e) => println!("Client {}: exchange fee left out of the USD total: {}", client_id, e),
                 }
-                updated = true;
+                client.ledger.push(LedgerEntry {
+                    stock_symbol: stock_symbol.clone(),
+                    is_buy,
+                    quantity,
+                    price: price_per_unit,
+                    commission: fees.commission,
+                    exchange_fee: fees.exchange_fee,
+                    currency: currency.to_string(),
+                    exec_id: exec_id.map(str::to_string),
+                });
+                shorted_quantity = Some(short_quantity);
                 break;
             }
         }
-        if updated {
+        if shorted_quantity.is_some() {
             break;
         }
     }
 
-    if !updated {
-        println!("Client ID {} not found in JSON data.", client_id);
-        return;
+    let Some(shorted_quantity) = shorted_quantity else {
+        return Err(format!("Client ID {} not found in JSON data", client_id));
+    };
+
+    if let Some(consumed) = &consumed {
+        data.record_offset(consumed);
     }
 
     // Write the updated JSON back to the file
-    let updated_json = match serde_json::to_string_pretty(&data) {
-        Ok(json) => json,
+    if !write_holdings(file_path, &data) {
+        return Err("Failed to write holdings file".to_string());
+    }
+    append_fill(APPLIED_FILLS_PATH, fill);
+    Ok(Some(shorted_quantity))
+}
+
+pub fn record_client_rejection_in_json(
+    file_path: &str,
+    client_id: u64,
+    reason: RejectReason,
+    consumed: Option<ConsumedOffset<'_>>,
+) -> Result<(), String> {
+    let _holdings = lock_holdings();
+    let mut json_data = String::new();
+    if let Err(e) = File::open(file_path).and_then(|mut f| f.read_to_string(&mut json_data)) {
+        return Err(format!("Error reading JSON file: {}", e));
+    }
+
+    let mut data: BrokersData = match serde_json::from_str(&json_data) {
+        Ok(d) => d,
+        Err(e) => return Err(format!("Error parsing JSON data: {}", e)),
+    };
+
+    let Some(client) = data
+        .brokers
+        .iter_mut()
+        .flat_map(|broker| broker.clients.iter_mut())
+        .find(|client| client.client_id == client_id)
+    else {
+        return Err(format!("Client ID {} not found in JSON data", client_id));
+    };
+    *client.rejection_counts.entry(reason).or_insert(0) += 1;
+    if let Some(consumed) = &consumed {
+        data.record_offset(consumed);
+    }
+
+    if !write_holdings(file_path, &data) {
+        return Err("Failed to write holdings file".to_string());
+    }
+    Ok(())
+}
+
+// End-of-day borrow fees on every short position, marked at the official close (or cost)
+pub fn charge_borrow_fees_in_json(file_path: &str, borrow_desk: &BorrowDesk) {
+    let _holdings = lock_holdings();
+    let mut json_data = String::new();
+    if let Err(e) = File::open(file_path).and_then(|mut f| f.read_to_string(&mut json_data)) {
+        println!("Error reading JSON file: {}", e);
+        return;
+    }
+
+    let mut data: BrokersData = match serde_json::from_str(&json_data) {
+        Ok(d) => d,
         Err(e) => {
-            println!("Error serializing updated JSON data: {}", e);
+            println!("Error parsing JSON data: {}", e);
             return;
         }
     };
 
-    if let Err(e) = File::create(file_path).and_then(|mut f| f.write_all(updated_json.as_bytes())) {
-        println!("Error writing updated JSON data: {}", e);
-    } 
+    let official_prices = load_official_prices();
+    let fx_rates = FxRates::load(FX_RATES_PATH);
+    for client in data.brokers.iter_mut().flat_map(|broker| broker.clients.iter_mut()) {
+        // Fees are charged in each instrument's currency
+        let mut borrow_fees: Vec<(&'static str, Money)> = Vec::new();
+        for (stock_symbol, holding) in client.portfolio.iter().filter(|(_, h)| h.quantity < 0) {
+            let mark_price = official_prices
+                .get(stock_symbol)
+                .and_then(|p| p.close.or(p.open))
+                .unwrap_or(holding.average_price.to_f64());
+            let borrow_fee = holding.quantity.unsigned_abs() as f64 * mark_price * borrow_desk.daily_fee_rate(stock_symbol);
+            borrow_fees.push((instrument_currency(stock_symbol), Money::from_f64(borrow_fee).round_cents()));
+        }
+        for (currency, borrow_fee) in borrow_fees {
+            *client.cash_mut(currency) -= borrow_fee;
+            match fx_rates.convert_money(borrow_fee, currency, HOME_CURRENCY) {
+                Ok(borrow_fee_usd) => client.borrow_fees_paid += borrow_fee_usd,
+                Err(e) => println!("Client {}: borrow fee left out of the USD total: {}", client.client_id, e),
+            }
+        }
+    }
+
+    write_holdings(file_path, &data);
 }
 
+// Apply a corporate action on its ex-date to every client holding the symbol
+pub fn apply_corporate_action_in_json(file_path: &str, action: &CorporateAction) {
+    let _holdings = lock_holdings();
+    let mut json_data = String::new();
+    if let Err(e) = File::open(file_path).and_then(|mut f| f.read_to_string(&mut json_data)) {
+        println!("Error reading JSON file: {}", e);
+        return;
+    }
 
+    let mut data: BrokersData = match serde_json::from_str(&json_data) {
+        Ok(d) => d,
+        Err(e) => {
+            println!("Error parsing JSON data: {}", e);
+            return;
+        }
+    };
 
+    let currency = instrument_currency(&action.stock_symbol);
+    let fx_rates = FxRates::load(FX_RATES_PATH);
+    for client in data.brokers.iter_mut().flat_map(|broker| broker.clients.iter_mut()) {
+        let Some(holding) = client.portfolio.get_mut(&action.stock_symbol) else {
+            continue;
+        };
+        match action.kind {
+            CorporateActionKind::Split { ratio } => {
+                // Fractional shares are paid out as cash in lieu at the adjusted price
+                let (new_quantity, fractional) = split_quantity(holding.quantity, ratio);
+                holding.apply_split(ratio, new_quantity);
+                let adjusted_price = action.adjusted_price.map_or(holding.average_price, Money::from_f64);
+                let cash_in_lieu = adjusted_price.scale(fractional);
+                if holding.quantity == 0 {
+                    client.portfolio.remove(&action.stock_symbol);
+                }
+                *client.cash_mut(currency) += cash_in_lieu.round_cents();
+            }
+            CorporateActionKind::CashDividend { amount } => {
+                // Longs receive the dividend; shorts owe it to the lender
+                let dividend = Money::from_f64(amount).times(holding.quantity).round_cents();
+                *client.cash_mut(currency) += dividend;
+                match fx_rates.convert_money(dividend, currency, HOME_CURRENCY) {
+                    Ok(dividend_usd) => client.dividends_received += dividend_usd,
+                    Err(e) => println!("Client {}: dividend left out of the USD total: {}", client.client_id, e),
+                }
+            }
+        }
+    }
+
+    // Journalled with the applied fills so reconciliation replays positions through the split where it happened
+    if write_holdings(file_path, &data) {
+        if let CorporateActionKind::Split { ratio } = action.kind {
+            append_split(APPLIED_FILLS_PATH, &action.stock_symbol, ratio);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Three buys of ten at 100, 120 and 90, lots "a", "b" and "c"
+    fn holding() -> StockHolding {
+        let mut holding = StockHolding { quantity: 0, average_price: Money::ZERO, lots: Vec::new() };
+        for (lot_id, price) in [("a", 100.0), ("b", 120.0), ("c", 90.0)] {
+            holding.apply_fill(10, Money::from_f64(price), lot_id, &[], LotMethod::Fifo, lot_id);
+        }
+        holding
+    }
+
+    // Realized gain of selling 15 at 110, as update_client_portfolio_in_json records it
+    fn gain_on_sale(method: LotMethod, lot_ids: &[&str]) -> Money {
+        let lot_ids: Vec<String> = lot_ids.iter().map(|id| id.to_string()).collect();
+        let close_price = Money::from_f64(110.0);
+        holding()
+            .apply_fill(-15, close_price, "sale", &lot_ids, method, "later")
+            .into_iter()
+            .fold(Money::ZERO, |total, (quantity, open_price, _)| total + (close_price - open_price).times(quantity))
+    }
+
+    #[test]
+    fn realized_gain_depends_on_the_lot_method() {
+        assert_eq!(gain_on_sale(LotMethod::Fifo, &[]), Money::from_f64(50.0));
+        assert_eq!(gain_on_sale(LotMethod::Lifo, &[]), Money::from_f64(150.0));
+        assert_eq!(gain_on_sale(LotMethod::Hifo, &[]), Money::from_f64(-50.0));
+        assert_eq!(gain_on_sale(LotMethod::Average, &[]), Money::from_f64(100.000005));
+        assert_eq!(gain_on_sale(LotMethod::Specific, &["c"]), Money::from_f64(250.0));
+    }
+
+    #[test]
+    fn selling_through_zero_closes_every_lot_and_opens_a_short_one() {
+        let mut holding = holding();
+        let closed = holding.apply_fill(-40, Money::from_f64(110.0), "flip", &[], LotMethod::Fifo, "later");
+        assert_eq!(closed.iter().map(|(quantity, _, _)| quantity).sum::<i64>(), 30);
+        assert_eq!(holding.quantity, -10);
+        assert_eq!(holding.average_price, Money::from_f64(110.0));
+        let open: Vec<(&str, i64)> = holding.lots.iter().map(|lot| (lot.id.as_str(), lot.quantity)).collect();
+        assert_eq!(open, vec![("flip", -10)]);
+    }
+}
@@ -0,0 +1,182 @@
+// broker/lots.rs
+
+use serde::{Deserialize, Serialize};
+use crate::money::Money;
+
+// Which open lots a closing fill relieves
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub enum LotMethod {
+    #[default]
+    Fifo,
+    Lifo,
+    Hifo, // Highest cost first for longs, lowest proceeds first for shorts
+    Average,
+    Specific, // Lots named on the closing order, in the order named, then FIFO
+}
+
+// Shares opened by a single fill; quantity is negative for short lots
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TaxLot {
+    #[serde(default)]
+    pub id: String, // Exec ID of the fill that opened it; empty for lots carried over from average cost
+    pub quantity: i64,
+    pub price: Money,
+    pub opened_at: String, // RFC 3339
+}
+
+// One closed (part of a) lot
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RealizedGain {
+    pub stock_symbol: String,
+    pub quantity: i64, // Negative when a short lot was covered
+    pub open_price: Money,
+    pub close_price: Money,
+    pub gain: Money, // Before fees, in the instrument's currency
+    pub currency: String,
+    pub method: LotMethod,
+    pub opened_at: String,
+    pub closed_at: String,
+}
+
+// Close up to `quantity` shares against the open lots. `quantity` carries the sign of the lots
+// being closed; `lot_ids` are only read under specific identification.
+// Returns the closed slices as (quantity, open price, opened at).
+pub fn relieve_lots(lots: &mut Vec<TaxLot>, quantity: i64, method: LotMethod, lot_ids: &[String]) -> Vec<(i64, Money, String)> {
+    if method == LotMethod::Average {
+        merge_lots(lots);
+    }
+
+    let mut remaining = quantity.abs();
+    let mut closed = Vec::new();
+    while remaining > 0 {
+        let Some(index) = next_lot(lots, method, lot_ids) else {
+            break;
+        };
+        let lot = &mut lots[index];
+        let taken = lot.quantity.abs().min(remaining);
+        let signed_taken = taken * lot.quantity.signum();
+        closed.push((signed_taken, lot.price, lot.opened_at.clone()));
+        lot.quantity -= signed_taken;
+        remaining -= taken;
+        if lot.quantity == 0 {
+            lots.remove(index);
+        }
+    }
+    closed
+}
+
+fn next_lot(lots: &[TaxLot], method: LotMethod, lot_ids: &[String]) -> Option<usize> {
+    if lots.is_empty() {
+        return None;
+    }
+    match method {
+        LotMethod::Fifo | LotMethod::Average => Some(0),
+        LotMethod::Lifo => Some(lots.len() - 1),
+        LotMethod::Hifo => {
+            // Lots are all on one side; flip the key for shorts so the smallest gain is realized first
+            let side = lots[0].quantity.signum();
+            (0..lots.len()).max_by_key(|&i| if side > 0 { lots[i].price } else { Money::ZERO - lots[i].price })
+        }
+        LotMethod::Specific => lot_ids
+            .iter()
+            .find_map(|lot_id| lots.iter().position(|lot| !lot.id.is_empty() && lot.id == *lot_id))
+            .or(Some(0)),
+    }
+}
+
+// Collapse every lot into one at the weighted average cost, keeping the earliest open time
+fn merge_lots(lots: &mut Vec<TaxLot>) {
+    if lots.len() < 2 {
+        return;
+    }
+    let quantity: i64 = lots.iter().map(|lot| lot.quantity).sum();
+    let total_cost = lots
+        .iter()
+        .fold(Money::ZERO, |total, lot| total + lot.price.times(lot.quantity.abs()));
+    let opened_at = lots[0].opened_at.clone();
+    *lots = vec![TaxLot {
+        id: lots[0].id.clone(),
+        quantity,
+        price: total_cost.per_share(quantity.abs()),
+        opened_at,
+    }];
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lot(id: &str, quantity: i64, price: f64) -> TaxLot {
+        TaxLot { id: id.to_string(), quantity, price: Money::from_f64(price), opened_at: id.to_string() }
+    }
+
+    fn long_lots() -> Vec<TaxLot> {
+        vec![lot("a", 10, 100.0), lot("b", 10, 120.0), lot("c", 10, 90.0)]
+    }
+
+    type Slice = (i64, f64, String); // Quantity, open price, lot
+
+    // Slices closed by selling 15, and what is left open as (lot, quantity)
+    fn relieve(method: LotMethod, lot_ids: &[&str]) -> (Vec<Slice>, Vec<(String, i64)>) {
+        let mut lots = long_lots();
+        let lot_ids: Vec<String> = lot_ids.iter().map(|id| id.to_string()).collect();
+        let closed = relieve_lots(&mut lots, 15, method, &lot_ids)
+            .into_iter()
+            .map(|(quantity, price, opened_at)| (quantity, price.to_f64(), opened_at))
+            .collect();
+        (closed, lots.into_iter().map(|lot| (lot.id, lot.quantity)).collect())
+    }
+
+    fn slice(quantity: i64, price: f64, lot: &str) -> Slice {
+        (quantity, price, lot.to_string())
+    }
+
+    #[test]
+    fn fifo_closes_the_oldest_lots_first() {
+        let (closed, open) = relieve(LotMethod::Fifo, &[]);
+        assert_eq!(closed, vec![slice(10, 100.0, "a"), slice(5, 120.0, "b")]);
+        assert_eq!(open, vec![("b".to_string(), 5), ("c".to_string(), 10)]);
+    }
+
+    #[test]
+    fn lifo_closes_the_newest_lots_first() {
+        let (closed, _) = relieve(LotMethod::Lifo, &[]);
+        assert_eq!(closed, vec![slice(10, 90.0, "c"), slice(5, 120.0, "b")]);
+    }
+
+    #[test]
+    fn hifo_closes_the_costliest_long_and_cheapest_short_lots_first() {
+        let (closed, _) = relieve(LotMethod::Hifo, &[]);
+        assert_eq!(closed, vec![slice(10, 120.0, "b"), slice(5, 100.0, "a")]);
+
+        let mut short_lots = vec![lot("x", -10, 120.0), lot("y", -10, 100.0)];
+        let closed = relieve_lots(&mut short_lots, -15, LotMethod::Hifo, &[]);
+        let closed: Vec<(i64, f64)> = closed.into_iter().map(|(quantity, price, _)| (quantity, price.to_f64())).collect();
+        assert_eq!(closed, vec![(-10, 100.0), (-5, 120.0)]);
+    }
+
+    #[test]
+    fn average_closes_against_one_lot_at_the_weighted_cost() {
+        let (closed, open) = relieve(LotMethod::Average, &[]);
+        assert_eq!(closed, vec![slice(15, 103.333333, "a")]);
+        assert_eq!(open, vec![("a".to_string(), 15)]);
+    }
+
+    #[test]
+    fn specific_closes_the_named_lots_in_order_then_falls_back_to_fifo() {
+        let (closed, open) = relieve(LotMethod::Specific, &["c", "b"]);
+        assert_eq!(closed, vec![slice(10, 90.0, "c"), slice(5, 120.0, "b")]);
+        assert_eq!(open, vec![("a".to_string(), 10), ("b".to_string(), 5)]);
+
+        // Unknown IDs and shares beyond the named lots are relieved oldest first
+        let (closed, _) = relieve(LotMethod::Specific, &["zzz", "c"]);
+        assert_eq!(closed, vec![slice(10, 90.0, "c"), slice(5, 100.0, "a")]);
+        let (closed, _) = relieve(LotMethod::Specific, &[]);
+        assert_eq!(closed, vec![slice(10, 100.0, "a"), slice(5, 120.0, "b")]);
+    }
+
+    #[test]
+    fn lot_ids_are_ignored_by_the_other_methods() {
+        assert_eq!(relieve(LotMethod::Fifo, &["c"]), relieve(LotMethod::Fifo, &[]));
+    }
+}
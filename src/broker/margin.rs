@@ -0,0 +1,139 @@
+// broker/margin.rs
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+
+pub const MARGIN_REQUIREMENTS_PATH: &str = "src/data/margin_requirements.json";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub enum AccountType {
+    #[default]
+    Cash,
+    Margin,
+}
+
+// Requirements as a fraction of position market value
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct Requirement {
+    pub initial: f64,
+    pub maintenance: f64,
+}
+
+impl Default for Requirement {
+    fn default() -> Self {
+        // Reg T style defaults
+        Requirement {
+            initial: 0.5,
+            maintenance: 0.25,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct MarginRequirements {
+    pub default_requirement: Requirement,
+    #[serde(default)]
+    pub symbols: HashMap<String, Requirement>, // Per-instrument overrides
+}
+
+#[derive(Debug, Clone)]
+pub struct MarginStatus {
+    pub equity: f64,
+    pub initial_requirement: f64,
+    pub maintenance_requirement: f64,
+}
+
+impl MarginStatus {
+    pub fn is_margin_call(&self) -> bool {
+        self.equity < self.maintenance_requirement
+    }
+
+    // Equity left over after initial requirements
+    pub fn excess(&self) -> f64 {
+        self.equity - self.initial_requirement
+    }
+}
+
+impl MarginRequirements {
+    pub fn load(path: &str) -> Self {
+        match fs::read_to_string(path) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+                println!("Error parsing margin requirements, using defaults: {}", e);
+                MarginRequirements::default()
+            }),
+            Err(_) => MarginRequirements::default(),
+        }
+    }
+
+    pub fn requirement(&self, stock_symbol: &str) -> Requirement {
+        self.symbols
+            .get(stock_symbol)
+            .copied()
+            .unwrap_or(self.default_requirement)
+    }
+
+    // Positions are (quantity, mark price); quantity is negative when short
+    pub fn status(&self, capital: f64, positions: &HashMap<String, (i64, f64)>) -> MarginStatus {
+        let mut status = MarginStatus {
+            equity: capital,
+            initial_requirement: 0.0,
+            maintenance_requirement: 0.0,
+        };
+        for (stock_symbol, &(quantity, mark_price)) in positions {
+            let market_value = quantity as f64 * mark_price;
+            let requirement = self.requirement(stock_symbol);
+            status.equity += market_value;
+            status.initial_requirement += market_value.abs() * requirement.initial;
+            status.maintenance_requirement += market_value.abs() * requirement.maintenance;
+        }
+        status
+    }
+
+    // Additional notional the account can open in a symbol
+    pub fn buying_power(
+        &self,
+        account_type: AccountType,
+        capital: f64,
+        positions: &HashMap<String, (i64, f64)>,
+        stock_symbol: &str,
+    ) -> f64 {
+        match account_type {
+            AccountType::Cash => capital.max(0.0),
+            AccountType::Margin => {
+                let excess = self.status(capital, positions).excess();
+                (excess / self.requirement(stock_symbol).initial).max(0.0)
+            }
+        }
+    }
+
+    // Positions to flatten, in order, until the maintenance deficiency is covered.
+    // Largest maintenance requirement goes first.
+    pub fn liquidation_plan(&self, capital: f64, positions: &HashMap<String, (i64, f64)>) -> Vec<(String, i64)> {
+        let status = self.status(capital, positions);
+        let mut deficiency = status.maintenance_requirement - status.equity;
+        if deficiency <= 0.0 {
+            return Vec::new();
+        }
+
+        let mut by_requirement: Vec<(&String, i64, f64)> = positions
+            .iter()
+            .filter(|(_, (quantity, _))| *quantity != 0)
+            .map(|(stock_symbol, &(quantity, mark_price))| {
+                let freed = (quantity as f64 * mark_price).abs() * self.requirement(stock_symbol).maintenance;
+                (stock_symbol, quantity, freed)
+            })
+            .collect();
+        by_requirement.sort_by(|a, b| b.2.total_cmp(&a.2));
+
+        let mut plan = Vec::new();
+        for (stock_symbol, quantity, freed) in by_requirement {
+            if deficiency <= 0.0 {
+                break;
+            }
+            plan.push((stock_symbol.clone(), quantity));
+            deficiency -= freed;
+        }
+        plan
+    }
+}
@@ -1,8 +1,13 @@
 // broker/mod.rs
 
-pub mod broker;
+pub mod brokerage;
 pub mod client;
+pub mod borrow;
+pub mod margin;
+pub mod lots;
+pub mod blotter;
+pub mod pre_trade;
 pub mod data;
 
-pub use broker::initialize_brokers; 
-pub use data::update_client_portfolio_in_json;
+pub use brokerage::{initialize_brokers, Broker, StockQuote};
+pub use data::{record_client_rejection_in_json, update_client_portfolio_in_json};
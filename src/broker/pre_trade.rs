@@ -0,0 +1,189 @@
+// broker/pre_trade.rs
+
+use std::collections::HashMap;
+use colored::*;
+use crate::broker::blotter::Blotter;
+use crate::broker::borrow::BorrowDesk;
+use crate::broker::data::{record_client_rejection_in_json, ClientAccount};
+use crate::broker::margin::{AccountType, MarginRequirements, MARGIN_REQUIREMENTS_PATH};
+use crate::currency::{instrument_currency, FxRates, FX_RATES_PATH, HOME_CURRENCY};
+use crate::models::{Order, OrderAction, RejectReason};
+
+// The broker state an order is checked against besides the account on file
+pub struct PreTradeContext<'a> {
+    pub borrow_desk: &'a std::sync::Mutex<BorrowDesk>,
+    pub blotter: &'a std::sync::Mutex<Blotter>, // The broker's working orders
+    pub holdings_path: &'a str,
+    pub replacing: Option<&'a str>, // A working order this one replaces, whose shares it takes over
+}
+
+// Broker-side checks every order passes before it is sent, whether a simulated client, the FIX
+// gateway or the REST API entered it. A refused order is tallied against the client like an exchange reject.
+pub fn pre_trade_check(
+    order: &Order,
+    account: &ClientAccount,
+    last_prices: &HashMap<String, f64>, // Symbol -> Latest price in the instrument's currency
+    context: &PreTradeContext,
+) -> Result<(), (RejectReason, String)> {
+    let result = check_order(order, account, last_prices, context);
+    if let Err((reason, text)) = &result {
+        println!(
+            "{}",
+            format!(
+                "Client {}: {:?} order for {} {} refused ({:?}): {}",
+                order.client_id, order.order_action, order.quantity, order.stock_symbol, reason, text
+            )
+            .bright_yellow().bold()
+        );
+        if let Err(e) = record_client_rejection_in_json(context.holdings_path, order.client_id, *reason, None) {
+            println!("Failed to record rejection for client {}: {}", order.client_id, e);
+        }
+    }
+    result
+}
+
+// Only the part of an order that opens or extends a position needs buying power, and only the
+// part of a sell beyond the long position is a short sale needing a locate. Sells still working
+// are taken off the position first, so two sells can't both be covered by the same shares.
+fn check_order(
+    order: &Order,
+    account: &ClientAccount,
+    last_prices: &HashMap<String, f64>,
+    context: &PreTradeContext,
+) -> Result<(), (RejectReason, String)> {
+    let open_sells = {
+        let blotter = context.blotter.lock().unwrap();
+        let replaced_sell = context
+            .replacing
+            .and_then(|order_id| blotter.entry(order_id))
+            .filter(|entry| entry.order.order_action == OrderAction::Sell && entry.state.is_open())
+            .map_or(0, |entry| entry.order.quantity.saturating_sub(entry.filled_quantity));
+        blotter.open_quantity(order.client_id, &order.stock_symbol, OrderAction::Sell) - replaced_sell
+    };
+    let current_quantity = account.positions.get(&order.stock_symbol).map_or(0, |&(quantity, _)| quantity) - open_sells as i64;
+    let signed_quantity = match order.order_action {
+        OrderAction::Buy => order.quantity as i64,
+        OrderAction::Sell => -(order.quantity as i64),
+        OrderAction::Cancel => return Ok(()),
+    };
+    let closing_quantity = if current_quantity.signum() == -signed_quantity.signum() {
+        signed_quantity.abs().min(current_quantity.abs())
+    } else {
+        0
+    };
+    let opening_quantity = signed_quantity.abs() - closing_quantity;
+    if opening_quantity == 0 {
+        return Ok(());
+    }
+
+    let is_short_sale = signed_quantity < 0;
+    if is_short_sale && account.account_type == AccountType::Cash {
+        return Err((
+            RejectReason::InsufficientFunds,
+            format!("Cash account cannot sell {} {} short", opening_quantity, order.stock_symbol),
+        ));
+    }
+
+    // Buying power is computed in USD across all currencies, positions marked at the latest price (or cost)
+    let fx_rates = FxRates::load(FX_RATES_PATH);
+    let margin_requirements = MarginRequirements::load(MARGIN_REQUIREMENTS_PATH);
+    // Without a rate the account can't be valued, so the order is refused
+    let no_rate = |e: String| (RejectReason::RiskLimit, e);
+    let positions: HashMap<String, (i64, f64)> = account
+        .positions
+        .iter()
+        .map(|(stock_symbol, &(quantity, average_price))| {
+            let mark_price = last_prices.get(stock_symbol).copied().unwrap_or(average_price);
+            let mark_price_usd = fx_rates.convert(mark_price, instrument_currency(stock_symbol), HOME_CURRENCY)?;
+            Ok((stock_symbol.clone(), (quantity, mark_price_usd)))
+        })
+        .collect::<Result<_, String>>()
+        .map_err(no_rate)?;
+    let buying_power = margin_requirements.buying_power(
+        account.account_type,
+        account.total_cash_usd(&fx_rates).map_err(no_rate)?,
+        &positions,
+        &order.stock_symbol,
+    );
+    let cost = order.price.times(opening_quantity).to_f64();
+    let cost_usd = fx_rates
+        .convert(cost, instrument_currency(&order.stock_symbol), HOME_CURRENCY)
+        .map_err(no_rate)?;
+    if buying_power < cost_usd {
+        return Err((
+            RejectReason::InsufficientFunds,
+            format!("Insufficient buying power. Required: {:.2}, Available: {:.2} (USD)", cost_usd, buying_power),
+        ));
+    }
+
+    // Borrow is located last, so a refused order never holds any
+    if is_short_sale && !context.borrow_desk.lock().unwrap().locate(&order.order_id, &order.stock_symbol, opening_quantity as u64) {
+        return Err((
+            RejectReason::LocateUnavailable,
+            format!("No locate for short sale of {} {} shares", opening_quantity, order.stock_symbol),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::test_order;
+    use std::sync::Mutex;
+
+    const NO_HOLDINGS: &str = "target/no_such_holdings.json";
+
+    fn sell(order_id: &str, quantity: u64) -> Order {
+        Order { stock_symbol: "AAPL".to_string(), ..test_order(order_id, OrderAction::Sell, 100.0, quantity) }
+    }
+
+    fn account(account_type: AccountType, quantity: i64) -> ClientAccount {
+        ClientAccount {
+            client_id: 1,
+            account_type,
+            capital: 10_000.0,
+            cash_balances: HashMap::new(),
+            positions: HashMap::from([("AAPL".to_string(), (quantity, 100.0))]),
+        }
+    }
+
+    #[test]
+    fn working_sells_use_up_the_long_position() {
+        let borrow_desk = Mutex::new(BorrowDesk::load(crate::broker::borrow::BORROW_INVENTORY_PATH));
+        let blotter = Mutex::new(Blotter::new(1));
+        let context = PreTradeContext { borrow_desk: &borrow_desk, blotter: &blotter, holdings_path: NO_HOLDINGS, replacing: None };
+        let account = account(AccountType::Cash, 10);
+
+        assert!(pre_trade_check(&sell("first", 10), &account, &HashMap::new(), &context).is_ok());
+        blotter.lock().unwrap().add(sell("first", 10));
+        // The same ten shares can't be sold twice; a cash account can't go short
+        assert!(pre_trade_check(&sell("second", 10), &account, &HashMap::new(), &context).is_err());
+    }
+
+    #[test]
+    fn sells_beyond_working_sells_need_a_locate() {
+        let borrow_desk = Mutex::new(BorrowDesk::load(crate::broker::borrow::BORROW_INVENTORY_PATH));
+        let blotter = Mutex::new(Blotter::new(1));
+        blotter.lock().unwrap().add(sell("first", 8));
+        let context = PreTradeContext { borrow_desk: &borrow_desk, blotter: &blotter, holdings_path: NO_HOLDINGS, replacing: None };
+
+        assert!(pre_trade_check(&sell("second", 5), &account(AccountType::Margin, 10), &HashMap::new(), &context).is_ok());
+        assert_eq!(borrow_desk.lock().unwrap().located_for("second"), 3);
+    }
+
+    #[test]
+    fn a_replacement_takes_over_the_shares_of_the_order_it_replaces() {
+        let borrow_desk = Mutex::new(BorrowDesk::load(crate::broker::borrow::BORROW_INVENTORY_PATH));
+        let blotter = Mutex::new(Blotter::new(1));
+        blotter.lock().unwrap().add(sell("original", 10));
+        let context = PreTradeContext {
+            borrow_desk: &borrow_desk,
+            blotter: &blotter,
+            holdings_path: NO_HOLDINGS,
+            replacing: Some("original"),
+        };
+
+        assert!(pre_trade_check(&sell("replacement", 10), &account(AccountType::Cash, 10), &HashMap::new(), &context).is_ok());
+    }
+}
@@ -0,0 +1,149 @@
+// circuit_breaker.rs
+
+use std::collections::VecDeque;
+use tokio::time::{Duration, Instant};
+
+// Limit-up/limit-down band around the reference price
+const LIMIT_BAND_PERCENT: f64 = 15.0;
+// The band reference is the average price over this rolling window
+const REFERENCE_WINDOW: Duration = Duration::from_secs(5 * 60);
+// Volatility interruption: halt when the high-low range within the window exceeds this
+const VOLATILITY_THRESHOLD_PERCENT: f64 = 8.0;
+const VOLATILITY_WINDOW: Duration = Duration::from_secs(10);
+const HALT_DURATION: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone)]
+pub struct CircuitBreaker {
+    reference_prices: VecDeque<(Instant, f64)>, // Prices averaged into the band reference
+    recent_prices: VecDeque<(Instant, f64)>,
+    halted_until: Option<Instant>,
+}
+
+// Drop prices that have aged out of the window
+fn trim_window(prices: &mut VecDeque<(Instant, f64)>, now: Instant, window: Duration) {
+    while let Some(&(at, _)) = prices.front() {
+        if now.duration_since(at) > window {
+            prices.pop_front();
+        } else {
+            break;
+        }
+    }
+}
+
+impl CircuitBreaker {
+    pub fn new(reference_price: f64) -> Self {
+        let now = Instant::now();
+        Self {
+            reference_prices: VecDeque::from([(now, reference_price)]),
+            recent_prices: VecDeque::from([(now, reference_price)]),
+            halted_until: None,
+        }
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.halted_until.is_some()
+    }
+
+    // Average over the reference window, so the band follows the market instead of a fixed opening price
+    pub fn reference_price(&self) -> f64 {
+        self.reference_prices.iter().map(|&(_, price)| price).sum::<f64>() / self.reference_prices.len() as f64
+    }
+
+    // Returns true once when a halt has run its course; the band is re-referenced to the last price
+    pub fn try_resume(&mut self, now: Instant, last_price: f64) -> bool {
+        match self.halted_until {
+            Some(until) if now >= until => {
+                self.halted_until = None;
+                self.reference_prices = VecDeque::from([(now, last_price)]);
+                self.recent_prices = VecDeque::from([(now, last_price)]);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // Scale the band and window by a corporate action's price factor so the
+    // mechanical price change doesn't trip the breaker
+    pub fn rebase(&mut self, factor: f64) {
+        for (_, price) in self.reference_prices.iter_mut().chain(self.recent_prices.iter_mut()) {
+            *price *= factor;
+        }
+    }
+
+    // Clamp a proposed price to the limit-up/limit-down band
+    pub fn apply_band(&self, price: f64) -> f64 {
+        let reference_price = self.reference_price();
+        let lower = reference_price * (1.0 - LIMIT_BAND_PERCENT / 100.0);
+        let upper = reference_price * (1.0 + LIMIT_BAND_PERCENT / 100.0);
+        price.clamp(lower, upper)
+    }
+
+    // Record a new price. Returns a halt reason if it triggers a volatility interruption.
+    pub fn record_price(&mut self, now: Instant, price: f64) -> Option<String> {
+        trim_window(&mut self.reference_prices, now, REFERENCE_WINDOW);
+        self.reference_prices.push_back((now, price));
+        trim_window(&mut self.recent_prices, now, VOLATILITY_WINDOW);
+        self.recent_prices.push_back((now, price));
+
+        // The swing between the window's high and low, wherever in the window they fell
+        let low = self.recent_prices.iter().map(|&(_, p)| p).fold(f64::INFINITY, f64::min);
+        let high = self.recent_prices.iter().map(|&(_, p)| p).fold(f64::NEG_INFINITY, f64::max);
+        let range_percent = (high - low) / low * 100.0;
+        if range_percent > VOLATILITY_THRESHOLD_PERCENT {
+            self.halted_until = Some(now + HALT_DURATION);
+            return Some(format!(
+                "Volatility interruption: {:.2}% high-low range within {}s",
+                range_percent,
+                VOLATILITY_WINDOW.as_secs()
+            ));
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn swing_within_the_window_halts_even_when_it_returns_to_the_start() {
+        let start = Instant::now();
+        let mut breaker = CircuitBreaker::new(100.0);
+        assert!(breaker.record_price(start + Duration::from_secs(1), 93.0).is_none());
+        // Back to 100 from the start, but 9.7% above the window's low
+        assert!(breaker.record_price(start + Duration::from_secs(2), 102.0).is_some());
+        assert!(breaker.is_halted());
+    }
+
+    #[test]
+    fn prices_outside_the_window_do_not_count() {
+        let start = Instant::now();
+        let mut breaker = CircuitBreaker::new(100.0);
+        assert!(breaker.record_price(start + VOLATILITY_WINDOW + Duration::from_secs(1), 107.0).is_none());
+        assert!(breaker.record_price(start + VOLATILITY_WINDOW * 2 + Duration::from_secs(2), 114.0).is_none());
+    }
+
+    #[test]
+    fn band_follows_the_rolling_average() {
+        let start = Instant::now();
+        let mut breaker = CircuitBreaker::new(100.0);
+        for second in 1..=3 {
+            breaker.record_price(start + VOLATILITY_WINDOW * second, 100.0 + 4.0 * second as f64);
+        }
+        // Average of 100, 104, 108 and 112
+        assert_eq!(breaker.reference_price(), 106.0);
+        assert_eq!(breaker.apply_band(200.0), 106.0 * 1.15);
+
+        // Once the window has passed only the latest price is left
+        breaker.record_price(start + REFERENCE_WINDOW * 2, 90.0);
+        assert_eq!(breaker.reference_price(), 90.0);
+    }
+
+    #[test]
+    fn rebase_scales_the_reference() {
+        let mut breaker = CircuitBreaker::new(100.0);
+        breaker.rebase(0.5);
+        assert_eq!(breaker.reference_price(), 50.0);
+        assert_eq!(breaker.apply_band(10.0), 50.0 * 0.85);
+    }
+}
@@ -0,0 +1,105 @@
+// corporate_actions.rs
+
+use std::fs;
+use crate::models::{CorporateAction, CorporateActionKind};
+use crate::money::{is_valid_split_ratio, round_price, tick_size, Money, Rounding};
+
+pub const CORPORATE_ACTIONS_PATH: &str = "src/data/corporate_actions.json";
+
+// Scheduled actions, earliest ex-time first. Entries that don't parse or can't be applied are
+// logged and skipped, so one bad entry doesn't take the rest of the feed with it.
+pub fn load_corporate_actions(path: &str) -> Vec<CorporateAction> {
+    let entries: Vec<serde_json::Value> = match fs::read_to_string(path) {
+        Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+            println!("Error parsing corporate actions feed: {}", e);
+            Vec::new()
+        }),
+        Err(_) => Vec::new(),
+    };
+    let mut actions: Vec<CorporateAction> = entries
+        .into_iter()
+        .filter_map(|entry| {
+            let result = serde_json::from_value::<CorporateAction>(entry.clone())
+                .map_err(|e| e.to_string())
+                .and_then(|action| validate(&action).map(|_| action));
+            result
+                .map_err(|e| println!("Skipping corporate action {}: {}", entry, e))
+                .ok()
+        })
+        .collect();
+    actions.sort_by_key(|action| action.ex_time_secs);
+    actions
+}
+
+fn validate(action: &CorporateAction) -> Result<(), String> {
+    match action.kind {
+        CorporateActionKind::Split { ratio } if !is_valid_split_ratio(ratio) => Err(format!("Invalid split ratio {}", ratio)),
+        CorporateActionKind::CashDividend { amount } if !amount.is_finite() => Err(format!("Invalid dividend amount {}", amount)),
+        _ => Ok(()),
+    }
+}
+
+// Price on the ex-date: splits divide by the ratio, dividends come off the price
+pub fn adjust_price(stock_symbol: &str, price: Money, kind: &CorporateActionKind) -> Money {
+    let adjusted = match kind {
+        CorporateActionKind::Split { ratio } => price.split_adjusted(*ratio),
+        CorporateActionKind::CashDividend { amount } => price - Money::from_f64(*amount),
+    };
+    adjusted
+        .round_to(tick_size(stock_symbol, adjusted.to_f64()), Rounding::Nearest)
+        .max(round_price(stock_symbol, 0.01, Rounding::Up))
+}
+
+// Share count after a split, truncated toward zero. Returns (new quantity, fractional shares left over).
+pub fn split_quantity(quantity: i64, ratio: f64) -> (i64, f64) {
+    let exact = quantity as f64 * ratio;
+    let whole = exact.trunc();
+    (whole as i64, exact - whole)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn action(kind: CorporateActionKind) -> CorporateAction {
+        CorporateAction {
+            stock_symbol: "AAPL".to_string(),
+            kind,
+            ex_time_secs: 10,
+            adjusted_price: None,
+        }
+    }
+
+    #[test]
+    fn split_ratios_must_survive_fixed_point() {
+        assert!(validate(&action(CorporateActionKind::Split { ratio: 2.0 })).is_ok());
+        assert!(validate(&action(CorporateActionKind::Split { ratio: 0.1 })).is_ok());
+        for ratio in [0.0, -2.0, 1e-9, f64::NAN, f64::INFINITY] {
+            assert!(validate(&action(CorporateActionKind::Split { ratio })).is_err(), "ratio {}", ratio);
+        }
+    }
+
+    #[test]
+    fn dividends_must_be_finite() {
+        assert!(validate(&action(CorporateActionKind::CashDividend { amount: 0.24 })).is_ok());
+        assert!(validate(&action(CorporateActionKind::CashDividend { amount: f64::NAN })).is_err());
+        assert!(validate(&action(CorporateActionKind::CashDividend { amount: f64::INFINITY })).is_err());
+    }
+
+    #[test]
+    fn bad_entries_are_skipped_and_the_rest_loaded() {
+        let path = std::env::temp_dir().join(format!("corporate_actions_{}.json", std::process::id()));
+        let feed = r#"[
+            {"stock_symbol": "AAPL", "kind": {"Split": {"ratio": 0.0}}, "ex_time_secs": 5},
+            {"stock_symbol": "MSFT", "kind": "Merger", "ex_time_secs": 6},
+            {"stock_symbol": "MSFT", "kind": {"CashDividend": {"amount": 0.75}}, "ex_time_secs": 20},
+            {"stock_symbol": "AAPL", "kind": {"Split": {"ratio": 4.0}}, "ex_time_secs": 10}
+        ]"#;
+        fs::write(&path, feed).unwrap();
+        let actions = load_corporate_actions(path.to_str().unwrap());
+        fs::remove_file(&path).unwrap();
+
+        let ex_times: Vec<u64> = actions.iter().map(|action| action.ex_time_secs).collect();
+        assert_eq!(ex_times, vec![10, 20]);
+    }
+}
@@ -0,0 +1,96 @@
+// currency.rs
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use crate::money::Money;
+
+// Latest rates from the FX feed; written at runtime, so not checked in
+pub const FX_RATES_PATH: &str = "src/data/fx_rates_live.json";
+pub const CURRENCY_CONFIG_PATH: &str = "src/data/currency_config.json";
+
+// Currency of a client's `capital` field; other currencies live in `cash_balances`
+pub const HOME_CURRENCY: &str = "USD";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CurrencyConfig {
+    pub base_currency: String, // Reporting currency for performance
+}
+
+impl Default for CurrencyConfig {
+    fn default() -> Self {
+        CurrencyConfig {
+            base_currency: HOME_CURRENCY.to_string(),
+        }
+    }
+}
+
+impl CurrencyConfig {
+    pub fn load(path: &str) -> Self {
+        match fs::read_to_string(path) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+                println!("Error parsing currency config, using default: {}", e);
+                CurrencyConfig::default()
+            }),
+            Err(_) => CurrencyConfig::default(),
+        }
+    }
+}
+
+// Starting USD value of one unit of each foreign currency
+pub const INITIAL_USD_RATES: [(&str, f64); 2] = [("GBP", 1.27), ("EUR", 1.08)];
+
+// Listing currency per symbol; anything not listed here trades in USD
+pub fn instrument_currency(stock_symbol: &str) -> &'static str {
+    match stock_symbol {
+        "BP" | "UL" => "GBP",
+        "TOT" => "EUR",
+        _ => HOME_CURRENCY,
+    }
+}
+
+// Latest FX rates, quoted as USD per unit of currency
+#[derive(Debug, Clone)]
+pub struct FxRates {
+    usd_rates: HashMap<String, f64>,
+}
+
+impl FxRates {
+    pub fn load(path: &str) -> Self {
+        let mut usd_rates: HashMap<String, f64> = INITIAL_USD_RATES
+            .iter()
+            .map(|(currency, rate)| (currency.to_string(), *rate))
+            .collect();
+        if let Ok(content) = fs::read_to_string(path) {
+            if let Ok(stored) = serde_json::from_str::<HashMap<String, f64>>(&content) {
+                usd_rates.extend(stored);
+            }
+        }
+        Self { usd_rates }
+    }
+
+    // A currency with no rate is an error; converting it at par would misstate every amount in it
+    pub fn usd_rate(&self, currency: &str) -> Result<f64, String> {
+        if currency == HOME_CURRENCY {
+            return Ok(1.0);
+        }
+        self.usd_rates
+            .get(currency)
+            .copied()
+            .ok_or_else(|| format!("No FX rate for {}", currency))
+    }
+
+    pub fn convert(&self, amount: f64, from: &str, to: &str) -> Result<f64, String> {
+        if from == to {
+            return Ok(amount);
+        }
+        Ok(amount * self.usd_rate(from)? / self.usd_rate(to)?)
+    }
+
+    pub fn convert_money(&self, amount: Money, from: &str, to: &str) -> Result<Money, String> {
+        if from == to {
+            return Ok(amount);
+        }
+        self.convert(amount.to_f64(), from, to).map(Money::from_f64)
+    }
+}
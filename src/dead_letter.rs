@@ -0,0 +1,114 @@
+// dead_letter.rs
+
+use std::time::Duration;
+use rdkafka::config::ClientConfig;
+use rdkafka::consumer::{CommitMode, Consumer, StreamConsumer};
+use rdkafka::error::KafkaResult;
+use rdkafka::message::{BorrowedMessage, Header, Headers, Message, OwnedHeaders};
+use rdkafka::producer::{FutureProducer, FutureRecord};
+use rdkafka::Offset;
+
+// Messages that cannot be handled go to "<topic>.dlq" rather than being dropped
+pub const DEAD_LETTER_SUFFIX: &str = ".dlq";
+
+// Headers describing why and where a dead letter came from
+pub const ERROR_HEADER: &str = "dlq.error";
+pub const SOURCE_TOPIC_HEADER: &str = "dlq.source.topic";
+pub const SOURCE_PARTITION_HEADER: &str = "dlq.source.partition";
+pub const SOURCE_OFFSET_HEADER: &str = "dlq.source.offset";
+pub const TIMESTAMP_HEADER: &str = "dlq.timestamp";
+
+pub fn dead_letter_topic(topic: &str) -> String {
+    format!("{}{}", topic, DEAD_LETTER_SUFFIX)
+}
+
+// The original key and payload, unchanged, plus the failure details
+pub struct DeadLetter {
+    pub topic: String,
+    pub key: Option<Vec<u8>>,
+    pub payload: Option<Vec<u8>>,
+    pub headers: OwnedHeaders,
+}
+
+impl DeadLetter {
+    pub fn from_message(m: &BorrowedMessage, error: &str) -> Self {
+        let partition = m.partition().to_string();
+        let offset = m.offset().to_string();
+        let timestamp = chrono::Utc::now().to_rfc3339();
+        let headers = OwnedHeaders::new()
+            .insert(Header { key: ERROR_HEADER, value: Some(error) })
+            .insert(Header { key: SOURCE_TOPIC_HEADER, value: Some(m.topic()) })
+            .insert(Header { key: SOURCE_PARTITION_HEADER, value: Some(&partition) })
+            .insert(Header { key: SOURCE_OFFSET_HEADER, value: Some(&offset) })
+            .insert(Header { key: TIMESTAMP_HEADER, value: Some(&timestamp) });
+        DeadLetter {
+            topic: dead_letter_topic(m.topic()),
+            key: m.key().map(<[u8]>::to_vec),
+            payload: m.payload().map(<[u8]>::to_vec),
+            headers,
+        }
+    }
+
+    pub fn record(&self) -> FutureRecord<'_, [u8], [u8]> {
+        let mut record = FutureRecord::to(&self.topic).headers(self.headers.clone());
+        if let Some(key) = &self.key {
+            record = record.key(key.as_slice());
+        }
+        if let Some(payload) = &self.payload {
+            record = record.payload(payload.as_slice());
+        }
+        record
+    }
+}
+
+// Value of a header as text, if present
+pub fn header_value<H: Headers>(headers: &H, key: &str) -> Option<String> {
+    headers
+        .iter()
+        .find(|header| header.key == key)
+        .and_then(|header| header.value)
+        .map(|value| String::from_utf8_lossy(value).into_owned())
+}
+
+pub fn dead_letter_producer() -> FutureProducer {
+    ClientConfig::new()
+        .set("bootstrap.servers", "localhost:9092")
+        .set("message.timeout.ms", "5000")
+        .create()
+        .expect("Dead letter producer creation failed")
+}
+
+// Park a message that failed deserialization, validation or processing on its dead-letter topic.
+// An error means the message is in neither place, so its offset must not be committed.
+pub async fn send_to_dead_letter(producer: &FutureProducer, m: &BorrowedMessage<'_>, error: &str) -> KafkaResult<()> {
+    let dead_letter = DeadLetter::from_message(m, error);
+    println!(
+        "Dead-lettering offset {} on {} to {}: {}",
+        m.offset(),
+        m.topic(),
+        dead_letter.topic,
+        error
+    );
+    match producer.send(dead_letter.record(), rdkafka::util::Timeout::Never).await {
+        Ok(_) => Ok(()),
+        Err((err, _)) => {
+            println!("Failed to send message to {}: {}", dead_letter.topic, err);
+            Err(err)
+        }
+    }
+}
+
+// Commit a message once it has been handled or parked. If parking it failed, rewind to it
+// instead so it is read and dead-lettered again rather than lost.
+pub fn commit_or_rewind(consumer: &StreamConsumer, m: &BorrowedMessage<'_>, parked: KafkaResult<()>) {
+    let result = match parked {
+        Ok(()) => consumer.commit_message(m, CommitMode::Async),
+        Err(_) => {
+            println!("Offset {} on {} not dead-lettered, reading it again", m.offset(), m.topic());
+            consumer.seek(m.topic(), m.partition(), Offset::Offset(m.offset()), Duration::from_secs(5))
+        }
+    };
+    if let Err(err) = result {
+        println!("Failed to commit or rewind offset {} on {}: {}", m.offset(), m.topic(), err);
+    }
+}
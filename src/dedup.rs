@@ -0,0 +1,39 @@
+// dedup.rs
+
+use std::collections::{HashSet, VecDeque};
+
+// IDs seen recently, forgetting the oldest once capacity is reached so memory stays bounded
+pub struct RecentIds {
+    capacity: usize,
+    ids: HashSet<String>,
+    insertion_order: VecDeque<String>,
+}
+
+impl RecentIds {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            ids: HashSet::new(),
+            insertion_order: VecDeque::new(),
+        }
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.ids.contains(id)
+    }
+
+    // Returns false when the ID has been seen within the window
+    pub fn insert(&mut self, id: &str) -> bool {
+        if self.ids.contains(id) {
+            return false;
+        }
+        if self.insertion_order.len() >= self.capacity {
+            if let Some(oldest) = self.insertion_order.pop_front() {
+                self.ids.remove(&oldest);
+            }
+        }
+        self.ids.insert(id.to_string());
+        self.insertion_order.push_back(id.to_string());
+        true
+    }
+}
@@ -0,0 +1,94 @@
+// execution_model.rs
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use crate::models::{Order, OrderAction, OrderType};
+use crate::money::{round_price, Money, Rounding};
+
+pub const EXECUTION_MODEL_PATH: &str = "src/data/execution_model.json";
+
+// How a book-less fill is priced relative to the reference (last) price.
+// Every model returns an adverse cost in bps; buys pay it above the reference, sells below.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "model")]
+pub enum ExecutionModel {
+    // Constant slippage on every fill
+    FixedBps { bps: f64 },
+    // Cross half of an assumed quoted spread
+    Spread { spread_bps: f64 },
+    // Almgren-style impact: coefficient * daily volatility * sqrt(quantity / ADV)
+    SquareRootImpact {
+        coefficient: f64,
+        daily_volatility_bps: f64,
+        default_adv: f64,
+        #[serde(default)]
+        adv: HashMap<String, f64>, // Symbol -> Average daily volume override
+    },
+    // Expected adverse drift while the order is in flight, scaling with sqrt(latency)
+    Latency {
+        latency_ms: f64,
+        volatility_bps_per_sqrt_sec: f64,
+    },
+}
+
+impl Default for ExecutionModel {
+    fn default() -> Self {
+        ExecutionModel::Spread { spread_bps: 10.0 }
+    }
+}
+
+impl ExecutionModel {
+    pub fn load(path: &str) -> Self {
+        match fs::read_to_string(path) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+                println!("Error parsing execution model, using default: {}", e);
+                ExecutionModel::default()
+            }),
+            Err(_) => ExecutionModel::default(),
+        }
+    }
+
+    pub fn slippage_bps(&self, order: &Order) -> f64 {
+        match self {
+            ExecutionModel::FixedBps { bps } => *bps,
+            ExecutionModel::Spread { spread_bps } => spread_bps / 2.0,
+            ExecutionModel::SquareRootImpact { coefficient, daily_volatility_bps, default_adv, adv } => {
+                let adv = adv.get(&order.stock_symbol).copied().unwrap_or(*default_adv).max(1.0);
+                coefficient * daily_volatility_bps * (order.quantity as f64 / adv).sqrt()
+            }
+            ExecutionModel::Latency { latency_ms, volatility_bps_per_sqrt_sec } => {
+                volatility_bps_per_sqrt_sec * (latency_ms / 1000.0).sqrt()
+            }
+        }
+    }
+
+    // Fill price for an order given the reference price, on the symbol's tick grid.
+    // Limit orders never fill through their limit, and one that is not marketable against the
+    // reference price does not fill at all (None).
+    pub fn fill_price(&self, order: &Order, reference_price: f64) -> Option<Money> {
+        if !is_marketable(order, reference_price) {
+            return None;
+        }
+        let slippage = self.slippage_bps(order) / 10_000.0;
+        let model_price = match order.order_action {
+            OrderAction::Sell => reference_price * (1.0 - slippage),
+            _ => reference_price * (1.0 + slippage),
+        };
+        let model_price = round_price(&order.stock_symbol, model_price, Rounding::Nearest);
+        Some(match (&order.order_type, &order.order_action) {
+            (OrderType::Limit, OrderAction::Buy) => model_price.min(order.price),
+            (OrderType::Limit, OrderAction::Sell) => model_price.max(order.price),
+            _ => model_price,
+        })
+    }
+}
+
+// A buy limit at or above the reference price, or a sell limit at or below it, can trade now
+fn is_marketable(order: &Order, reference_price: f64) -> bool {
+    match (&order.order_type, &order.order_action) {
+        (OrderType::Limit, OrderAction::Buy) => order.price.to_f64() >= reference_price,
+        (OrderType::Limit, OrderAction::Sell) => order.price.to_f64() <= reference_price,
+        _ => true,
+    }
+}
@@ -0,0 +1,81 @@
+// fees.rs
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use crate::models::{Liquidity, Order};
+use crate::money::Money;
+
+pub const FEE_SCHEDULE_PATH: &str = "src/data/fee_schedule.json";
+
+// Broker commission: per-share and bps components, floored at a minimum ticket charge
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct CommissionSchedule {
+    pub per_share: f64,
+    pub bps: f64,
+    pub min_ticket: f64,
+}
+
+// Exchange fees in bps of notional; negative values are rebates
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ExchangeFees {
+    pub maker_bps: f64,
+    pub taker_bps: f64,
+    pub auction_bps: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct FeeSchedule {
+    pub default_commission: CommissionSchedule,
+    #[serde(default)]
+    pub broker_commissions: HashMap<u64, CommissionSchedule>, // Broker ID -> Override
+    pub exchange: ExchangeFees,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+pub struct FillFees {
+    pub commission: Money,
+    pub exchange_fee: Money, // Negative when the fill earned a rebate
+}
+
+impl FillFees {
+    pub fn total(&self) -> Money {
+        self.commission + self.exchange_fee
+    }
+}
+
+impl FeeSchedule {
+    // Missing or unreadable schedules charge nothing, matching the old behaviour
+    pub fn load(path: &str) -> Self {
+        match fs::read_to_string(path) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+                println!("Error parsing fee schedule, charging no fees: {}", e);
+                FeeSchedule::default()
+            }),
+            Err(_) => FeeSchedule::default(),
+        }
+    }
+
+    pub fn fees_for_fill(&self, order: &Order) -> FillFees {
+        let notional = order.price.times(order.quantity as i64).to_f64();
+
+        let schedule = self
+            .broker_commissions
+            .get(&order.broker_id)
+            .unwrap_or(&self.default_commission);
+        let commission = (schedule.per_share * order.quantity as f64 + schedule.bps * notional / 10_000.0)
+            .max(schedule.min_ticket);
+
+        let exchange_bps = match order.liquidity {
+            Some(Liquidity::Maker) => self.exchange.maker_bps,
+            Some(Liquidity::Taker) | None => self.exchange.taker_bps,
+            Some(Liquidity::Auction) => self.exchange.auction_bps,
+        };
+        let exchange_fee = exchange_bps * notional / 10_000.0;
+
+        FillFees {
+            commission: Money::from_f64(commission).round_cents(),
+            exchange_fee: Money::from_f64(exchange_fee).round_cents(),
+        }
+    }
+}
@@ -0,0 +1,962 @@
+// fix/gateway.rs
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::Duration;
+use colored::*;
+use rdkafka::config::ClientConfig;
+use rdkafka::consumer::{Consumer, StreamConsumer};
+use rdkafka::message::Message;
+use rdkafka::producer::FutureProducer;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::OwnedWriteHalf;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{broadcast, mpsc, Mutex};
+use tokio::time::timeout;
+use crate::broker::blotter::{Blotter, OrderState};
+use crate::broker::borrow::BorrowDesk;
+use crate::broker::Broker;
+use crate::broker::data::find_client_account;
+use crate::broker::pre_trade::{pre_trade_check, PreTradeContext};
+use crate::dedup::RecentIds;
+use crate::fix::message::*;
+use crate::fix::session::{
+    FixSessions, MessageStore, SequenceStore, SessionConfig, StoredMessage, FIX_MESSAGES_PATH, FIX_SEQUENCES_PATH, FIX_SESSIONS_PATH,
+};
+use crate::models::{new_unique_id, CancelAck, Order, OrderAck, OrderAction, OrderStatus, OrderType, PriceUpdate};
+use crate::money::{round_price, Money, Rounding};
+use crate::order_status_receiver::Blotters;
+use crate::wire;
+
+pub const FIX_GATEWAY_ADDRESS: &str = "127.0.0.1:9878";
+pub const GATEWAY_COMP_ID: &str = "TRADINGSIDE";
+const CLIENT_HOLDINGS_PATH: &str = "src/data/client_holdings.json";
+// Fills already reported, so redelivered completed_order messages aren't reported twice
+const REPORTED_EXEC_ID_CAPACITY: usize = 100_000;
+
+// An order entered over FIX, kept so exchange messages can be reported back to its session
+struct FixOrder {
+    comp_id: String,
+    cl_ord_id: String,
+    side: String, // Side as the client sent it, echoed on reports
+    order: Order,
+    exchange_order_id: Option<String>,
+    cum_qty: u64,
+    notional: Money, // Filled so far, for AvgPx
+}
+
+// A cancel or cancel/replace sent to the matcher, answered once it confirms or refuses
+struct PendingCancel {
+    request: FixMessage,
+    replacement: Option<FixOrder>, // Submitted only once the original is out of the book
+}
+
+// A numbered message for a session's writer
+struct Outbound {
+    message: FixMessage,
+    seq_num: u64,
+    poss_dup: bool, // Resent in answer to a ResendRequest
+    sending_time: String,
+}
+
+struct GatewayState {
+    sessions: FixSessions,
+    sequences: SequenceStore,
+    messages: MessageStore, // Application messages sent, for resends
+    connections: HashMap<String, mpsc::UnboundedSender<Outbound>>, // SenderCompID -> Writer queue
+    orders: HashMap<String, FixOrder>,                             // Order ID -> FIX order
+    cl_ord_ids: HashMap<(String, String), String>,                 // (SenderCompID, ClOrdID) -> Order ID
+    pending_cancels: HashMap<String, PendingCancel>,               // Order ID -> Cancel awaiting the matcher
+    last_prices: HashMap<String, f64>,
+    reported_exec_ids: RecentIds,
+}
+
+type SharedState = Arc<Mutex<GatewayState>>;
+
+// Messages that arrived after a sequence gap, by MsgSeqNum, held until the resend fills the gap
+type HeldMessages = BTreeMap<u64, FixMessage>;
+
+impl GatewayState {
+    // Number a message for a client and queue it. Application messages are stored first, so a report
+    // for a session that is logged out still takes its sequence number and reaches the client through
+    // the ResendRequest that follows its next Logon. Session messages for an offline client are dropped.
+    fn send(&mut self, comp_id: &str, message: FixMessage) {
+        if message.is_admin() && !self.connections.contains_key(comp_id) {
+            println!("FIX session {} offline, dropping {} message", comp_id, message.msg_type());
+            return;
+        }
+        let seq_num = self.sequences.take_outgoing(comp_id);
+        let sending_time = timestamp();
+        if !message.is_admin() {
+            let stored = StoredMessage { message: message.clone(), sending_time: sending_time.clone() };
+            self.messages.store(comp_id, seq_num, stored);
+        }
+        if let Some(tx) = self.connections.get(comp_id) {
+            let _ = tx.send(Outbound { message, seq_num, poss_dup: false, sending_time });
+        }
+    }
+
+    // Answer a ResendRequest with the stored messages and gap fills for the rest
+    fn resend(&self, comp_id: &str, begin: u64, end: u64) {
+        let Some(tx) = self.connections.get(comp_id) else {
+            return;
+        };
+        let next_outgoing = self.sequences.get(comp_id).next_outgoing;
+        for (seq_num, message) in self.messages.resend(comp_id, begin, end, next_outgoing) {
+            let _ = tx.send(Outbound { message, seq_num, poss_dup: true, sending_time: timestamp() });
+        }
+    }
+}
+
+// Every message after Logon must come from the session's SenderCompID and be addressed to the gateway
+fn check_comp_ids(message: &FixMessage, comp_id: &str) -> Result<(), String> {
+    match (message.get(SENDER_COMP_ID), message.get(TARGET_COMP_ID)) {
+        (Some(sender), _) if sender != comp_id => Err(format!("SenderCompID {} does not match session {}", sender, comp_id)),
+        (None, _) => Err("Missing SenderCompID".to_string()),
+        (_, Some(GATEWAY_COMP_ID)) => Ok(()),
+        (_, target) => Err(format!("TargetCompID must be {}, got {}", GATEWAY_COMP_ID, target.unwrap_or("none"))),
+    }
+}
+
+// Accept FIX 4.4 clients and route their orders through their broker, reporting executions back
+pub async fn run_fix_gateway(
+    blotters: Blotters,
+    borrow_desk: Arc<std::sync::Mutex<BorrowDesk>>,
+    mut price_rx: broadcast::Receiver<PriceUpdate>,
+) {
+    let listener = match TcpListener::bind(FIX_GATEWAY_ADDRESS).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            println!("FIX gateway could not listen on {}: {}", FIX_GATEWAY_ADDRESS, e);
+            return;
+        }
+    };
+    println!("FIX gateway listening on {}", FIX_GATEWAY_ADDRESS);
+
+    let state: SharedState = Arc::new(Mutex::new(GatewayState {
+        sessions: FixSessions::load(FIX_SESSIONS_PATH),
+        sequences: SequenceStore::load(FIX_SEQUENCES_PATH),
+        messages: MessageStore::load(FIX_MESSAGES_PATH),
+        connections: HashMap::new(),
+        orders: HashMap::new(),
+        cl_ord_ids: HashMap::new(),
+        pending_cancels: HashMap::new(),
+        last_prices: HashMap::new(),
+        reported_exec_ids: RecentIds::new(REPORTED_EXEC_ID_CAPACITY),
+    }));
+    let producer: FutureProducer = ClientConfig::new()
+        .set("bootstrap.servers", "localhost:9092")
+        .set("message.timeout.ms", "5000")
+        .create()
+        .expect("Producer creation error");
+
+    let result = timeout(Duration::from_secs(50), async {
+        // Market orders are priced off the latest update
+        let price_state = state.clone();
+        let price_task = tokio::spawn(async move {
+            loop {
+                match price_rx.recv().await {
+                    Ok(update) => {
+                        price_state.lock().await.last_prices.insert(update.name, update.price);
+                    }
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+
+        let report_task = tokio::spawn(report_executions(state.clone(), blotters.clone(), borrow_desk.clone(), producer.clone()));
+
+        loop {
+            match listener.accept().await {
+                Ok((stream, address)) => {
+                    println!("FIX connection from {}", address);
+                    tokio::spawn(handle_connection(
+                        stream,
+                        state.clone(),
+                        blotters.clone(),
+                        borrow_desk.clone(),
+                        producer.clone(),
+                    ));
+                }
+                Err(e) => println!("FIX gateway accept failed: {}", e),
+            }
+            if price_task.is_finished() || report_task.is_finished() {
+                break;
+            }
+        }
+    });
+
+    if result.await.is_err() {
+        println!("Stopping FIX gateway.");
+    }
+}
+
+async fn handle_connection(
+    stream: TcpStream,
+    state: SharedState,
+    blotters: Blotters,
+    borrow_desk: Arc<std::sync::Mutex<BorrowDesk>>,
+    producer: FutureProducer,
+) {
+    let (mut reader, writer) = stream.into_split();
+    let mut writer = Some(writer);
+    let mut session: Option<(String, SessionConfig)> = None;
+    let mut held = HeldMessages::new();
+    let mut buffer = String::new();
+    let mut chunk = [0u8; 4096];
+
+    'connection: loop {
+        let read = match reader.read(&mut chunk).await {
+            Ok(0) | Err(_) => break,
+            Ok(read) => read,
+        };
+        buffer.push_str(&String::from_utf8_lossy(&chunk[..read]));
+
+        while let Some(raw) = next_message(&mut buffer) {
+            let message = match FixMessage::parse(&raw) {
+                Ok(message) => message,
+                Err(e) => {
+                    // Garbled messages are ignored, as FIX requires; the sequence gap triggers a resend
+                    println!("Dropping garbled FIX message: {}", e);
+                    continue;
+                }
+            };
+
+            // The first message on a connection must be a Logon from a known client
+            let Some((comp_id, account)) = session.clone() else {
+                let Some(mut writer) = writer.take() else {
+                    break 'connection;
+                };
+                match logon(&message, &state).await {
+                    Ok((comp_id, account, replies)) => {
+                        let (tx, rx) = mpsc::unbounded_channel();
+                        {
+                            let mut state = state.lock().await;
+                            state.connections.insert(comp_id.clone(), tx);
+                            for reply in replies {
+                                state.send(&comp_id, reply);
+                            }
+                        }
+                        tokio::spawn(write_messages(writer, comp_id.clone(), rx));
+                        // A Logon ahead of the expected sequence is held behind the gap it opened
+                        let next_incoming = state.lock().await.sequences.get(&comp_id).next_incoming;
+                        if let Some(seq_num) = message.seq_num().filter(|&seq_num| seq_num >= next_incoming) {
+                            held.insert(seq_num, message);
+                        }
+                        session = Some((comp_id, account));
+                    }
+                    Err(text) => {
+                        println!("{}", format!("FIX logon refused: {}", text).red());
+                        let target = message.get(SENDER_COMP_ID).unwrap_or("UNKNOWN");
+                        let logout = FixMessage::new(LOGOUT).with(TEXT, text).encode(GATEWAY_COMP_ID, target, 1, false);
+                        let _ = writer.write_all(logout.as_bytes()).await;
+                        break 'connection;
+                    }
+                }
+                continue;
+            };
+
+            if let Err(text) = check_comp_ids(&message, &comp_id) {
+                println!("{}", format!("FIX session {} dropped: {}", comp_id, text).red());
+                let mut state = state.lock().await;
+                let reject = FixMessage::new(REJECT)
+                    .with(REF_SEQ_NUM, message.seq_num().unwrap_or(0))
+                    .with(SESSION_REJECT_REASON, 9) // CompID problem
+                    .with(TEXT, &text);
+                state.send(&comp_id, reject);
+                state.send(&comp_id, FixMessage::new(LOGOUT).with(TEXT, text));
+                break 'connection;
+            }
+            let Some(ready) = check_sequence(message, &comp_id, &state, &mut held).await else {
+                break 'connection;
+            };
+            for message in ready {
+                match message.msg_type() {
+                    HEARTBEAT | SEQUENCE_RESET | LOGON => {}
+                    TEST_REQUEST => {
+                        let mut heartbeat = FixMessage::new(HEARTBEAT);
+                        if let Some(test_req_id) = message.get(TEST_REQ_ID) {
+                            heartbeat = heartbeat.with(TEST_REQ_ID, test_req_id);
+                        }
+                        state.lock().await.send(&comp_id, heartbeat);
+                    }
+                    RESEND_REQUEST => {
+                        let begin = message.get(BEGIN_SEQ_NO).and_then(|value| value.parse().ok()).unwrap_or(1);
+                        let end = message.get(END_SEQ_NO).and_then(|value| value.parse().ok()).unwrap_or(0);
+                        state.lock().await.resend(&comp_id, begin, end);
+                    }
+                    LOGOUT => {
+                        state.lock().await.send(&comp_id, FixMessage::new(LOGOUT));
+                        break 'connection;
+                    }
+                    NEW_ORDER_SINGLE => {
+                        new_order(&message, &comp_id, &account, &state, &blotters, &borrow_desk, &producer).await;
+                    }
+                    ORDER_CANCEL_REQUEST => {
+                        cancel_order(&message, &comp_id, &state, &blotters, &producer).await;
+                    }
+                    ORDER_CANCEL_REPLACE_REQUEST => {
+                        replace_order(&message, &comp_id, &state, &blotters, &borrow_desk, &producer).await;
+                    }
+                    other => {
+                        let reject = FixMessage::new(REJECT)
+                            .with(REF_SEQ_NUM, message.seq_num().unwrap_or(0))
+                            .with(TEXT, format!("Unsupported MsgType {}", other));
+                        state.lock().await.send(&comp_id, reject);
+                    }
+                }
+            }
+        }
+    }
+
+    if let Some((comp_id, _)) = session {
+        println!("FIX session {} disconnected", comp_id);
+        state.lock().await.connections.remove(&comp_id);
+    }
+}
+
+// Validate a Logon. Returns the session and the messages to send it first.
+async fn logon(message: &FixMessage, state: &SharedState) -> Result<(String, SessionConfig, Vec<FixMessage>), String> {
+    if message.msg_type() != LOGON {
+        return Err(format!("Expected Logon, got MsgType {}", message.msg_type()));
+    }
+    let comp_id = message.get(SENDER_COMP_ID).ok_or("Logon without SenderCompID")?.to_string();
+    check_comp_ids(message, &comp_id)?;
+    let mut state = state.lock().await;
+    let account = state
+        .sessions
+        .sessions
+        .get(&comp_id)
+        .cloned()
+        .ok_or_else(|| format!("Unknown SenderCompID {}", comp_id))?;
+    if state.connections.contains_key(&comp_id) {
+        return Err(format!("{} is already logged on", comp_id));
+    }
+
+    if message.get(RESET_SEQ_NUM_FLAG) == Some("Y") {
+        state.sequences.reset(&comp_id);
+        state.messages.reset(&comp_id);
+    }
+    let seq_num = message.seq_num().ok_or("Logon without MsgSeqNum")?;
+    let expected = state.sequences.get(&comp_id).next_incoming;
+    if seq_num < expected {
+        return Err(format!("MsgSeqNum {} too low, expecting {}", seq_num, expected));
+    }
+    if seq_num == expected {
+        state.sequences.set_next_incoming(&comp_id, seq_num + 1);
+    }
+
+    println!(
+        "{}",
+        format!("FIX session {} logged on for broker {} client {}", comp_id, account.broker_id, account.client_id)
+            .green()
+            .bold()
+    );
+    let heart_bt_int = message.get(HEART_BT_INT).unwrap_or("30").to_string();
+    let mut replies = vec![FixMessage::new(LOGON).with(ENCRYPT_METHOD, 0).with(HEART_BT_INT, heart_bt_int)];
+    if seq_num > expected {
+        replies.push(FixMessage::new(RESEND_REQUEST).with(BEGIN_SEQ_NO, expected).with(END_SEQ_NO, 0));
+    }
+    Ok((comp_id, account, replies))
+}
+
+// Put a message in sequence and return the messages now ready to process, in order, or None if the
+// session has to be dropped. A gap is answered with a ResendRequest and everything after it is held
+// until the resent messages or a gap fill close it.
+async fn check_sequence(message: FixMessage, comp_id: &str, state: &SharedState, held: &mut HeldMessages) -> Option<Vec<FixMessage>> {
+    let mut state = state.lock().await;
+    let expected = state.sequences.get(comp_id).next_incoming;
+    let Some(seq_num) = message.seq_num() else {
+        state.send(comp_id, FixMessage::new(LOGOUT).with(TEXT, "MsgSeqNum missing"));
+        return None;
+    };
+
+    let mut ready = Vec::new();
+    if message.msg_type() == SEQUENCE_RESET {
+        // Sequence numbers only move forward
+        if let Some(new_seq_no) = message.get(NEW_SEQ_NO).and_then(|value| value.parse().ok()).filter(|&new_seq_no| new_seq_no > expected) {
+            state.sequences.set_next_incoming(comp_id, new_seq_no);
+        }
+    } else if seq_num < expected {
+        if message.get(POSS_DUP_FLAG) == Some("Y") {
+            return Some(ready); // Already processed
+        }
+        let text = format!("MsgSeqNum {} too low, expecting {}", seq_num, expected);
+        state.send(comp_id, FixMessage::new(LOGOUT).with(TEXT, text));
+        return None;
+    } else if seq_num > expected {
+        // Ask for the gap once, when it opens
+        if held.is_empty() {
+            state.send(
+                comp_id,
+                FixMessage::new(RESEND_REQUEST).with(BEGIN_SEQ_NO, expected).with(END_SEQ_NO, 0),
+            );
+        }
+        held.insert(seq_num, message);
+        return Some(ready);
+    } else {
+        ready.push(message);
+        state.sequences.set_next_incoming(comp_id, seq_num + 1);
+    }
+
+    // Release held messages that are now in sequence; any a gap fill skipped over are dropped
+    loop {
+        let next = state.sequences.get(comp_id).next_incoming;
+        while held.first_key_value().is_some_and(|(&held_seq, _)| held_seq < next) {
+            held.pop_first();
+        }
+        match held.remove(&next) {
+            Some(message) => {
+                ready.push(message);
+                state.sequences.set_next_incoming(comp_id, next + 1);
+            }
+            None => break,
+        }
+    }
+    Some(ready)
+}
+
+// Send each queued message; they are numbered as they are queued
+async fn write_messages(mut writer: OwnedWriteHalf, comp_id: String, mut rx: mpsc::UnboundedReceiver<Outbound>) {
+    while let Some(outbound) = rx.recv().await {
+        let raw = outbound
+            .message
+            .encode_at(GATEWAY_COMP_ID, &comp_id, outbound.seq_num, outbound.poss_dup, &outbound.sending_time);
+        if let Err(e) = writer.write_all(raw.as_bytes()).await {
+            println!("Failed to write to FIX session {}: {}", comp_id, e);
+            break;
+        }
+    }
+}
+
+// Map a FIX side onto an order action. Sell short (5) and any other sale beyond the position need a locate.
+fn parse_side(side: &str) -> Result<OrderAction, String> {
+    match side {
+        "1" => Ok(OrderAction::Buy),
+        "2" | "5" => Ok(OrderAction::Sell),
+        other => Err(format!("Unsupported Side {}", other)),
+    }
+}
+
+// Buy limits round down and sell limits up, as the simulated clients do; market orders use the last price
+fn order_price(
+    symbol: &str,
+    order_type: &OrderType,
+    action: &OrderAction,
+    limit: Option<&str>,
+    last_prices: &HashMap<String, f64>,
+) -> Result<Money, String> {
+    match order_type {
+        OrderType::Limit => {
+            let limit: f64 = limit
+                .ok_or("Limit order without Price")?
+                .parse()
+                .map_err(|_| "Invalid Price".to_string())?;
+            let rounding = if *action == OrderAction::Buy { Rounding::Down } else { Rounding::Up };
+            Ok(round_price(symbol, limit, rounding))
+        }
+        OrderType::Market => last_prices
+            .get(symbol)
+            .map(|&last| round_price(symbol, last, Rounding::Nearest))
+            .ok_or_else(|| format!("No reference price for {}", symbol)),
+    }
+}
+
+fn parse_order_type(ord_type: Option<&str>) -> Result<OrderType, String> {
+    match ord_type {
+        Some("1") => Ok(OrderType::Market),
+        Some("2") => Ok(OrderType::Limit),
+        Some(other) => Err(format!("Unsupported OrdType {}", other)),
+        None => Err("Missing OrdType".to_string()),
+    }
+}
+
+fn parse_quantity(quantity: Option<&str>) -> Result<u64, String> {
+    match quantity.map(str::parse::<u64>) {
+        Some(Ok(quantity)) if quantity > 0 => Ok(quantity),
+        Some(_) => Err("Invalid OrderQty".to_string()),
+        None => Err("Missing OrderQty".to_string()),
+    }
+}
+
+// ExecutionReport for an order the gateway is tracking
+fn execution_report(fix_order: &FixOrder, exec_type: &str, ord_status: &str, exec_id: &str) -> FixMessage {
+    let avg_px = if fix_order.cum_qty > 0 { fix_order.notional.per_share(fix_order.cum_qty as i64) } else { Money::ZERO };
+    let leaves_qty = if matches!(ord_status, "0" | "1") { fix_order.order.quantity - fix_order.cum_qty } else { 0 };
+    let mut report = FixMessage::new(EXECUTION_REPORT)
+        .with(ORDER_ID, fix_order.exchange_order_id.as_deref().unwrap_or(&fix_order.order.order_id))
+        .with(CL_ORD_ID, &fix_order.cl_ord_id)
+        .with(EXEC_ID, exec_id)
+        .with(EXEC_TYPE, exec_type)
+        .with(ORD_STATUS, ord_status)
+        .with(SYMBOL, &fix_order.order.stock_symbol)
+        .with(SIDE, &fix_order.side)
+        .with(ORDER_QTY, fix_order.order.quantity);
+    if let OrderType::Limit = fix_order.order.order_type {
+        report = report.with(ORD_TYPE, "2").with(PRICE, fix_order.order.price);
+    } else {
+        report = report.with(ORD_TYPE, "1");
+    }
+    report
+        .with(CUM_QTY, fix_order.cum_qty)
+        .with(LEAVES_QTY, leaves_qty)
+        .with(AVG_PX, avg_px)
+        .with(TRANSACT_TIME, timestamp())
+}
+
+// ExecutionReport rejecting a request the gateway could not turn into an order
+fn rejected_request(message: &FixMessage, text: &str) -> FixMessage {
+    FixMessage::new(EXECUTION_REPORT)
+        .with(ORDER_ID, "NONE")
+        .with(CL_ORD_ID, message.get(CL_ORD_ID).unwrap_or(""))
+        .with(EXEC_ID, new_unique_id())
+        .with(EXEC_TYPE, "8")
+        .with(ORD_STATUS, "8")
+        .with(SYMBOL, message.get(SYMBOL).unwrap_or(""))
+        .with(SIDE, message.get(SIDE).unwrap_or(""))
+        .with(ORDER_QTY, message.get(ORDER_QTY).unwrap_or("0"))
+        .with(CUM_QTY, 0)
+        .with(LEAVES_QTY, 0)
+        .with(AVG_PX, 0)
+        .with(TEXT, text)
+        .with(TRANSACT_TIME, timestamp())
+}
+
+fn cancel_reject(message: &FixMessage, response_to: &str, ord_status: &str, text: &str) -> FixMessage {
+    FixMessage::new(ORDER_CANCEL_REJECT)
+        .with(ORDER_ID, "NONE")
+        .with(CL_ORD_ID, message.get(CL_ORD_ID).unwrap_or(""))
+        .with(ORIG_CL_ORD_ID, message.get(ORIG_CL_ORD_ID).unwrap_or(""))
+        .with(ORD_STATUS, ord_status)
+        .with(CXL_REJ_RESPONSE_TO, response_to)
+        .with(TEXT, text)
+}
+
+// FIX OrdStatus for a blotter state
+fn ord_status(state: Option<OrderState>) -> &'static str {
+    match state {
+        Some(OrderState::New) | Some(OrderState::Acked) => "0",
+        Some(OrderState::PartiallyFilled) => "1",
+        Some(OrderState::Filled) => "2",
+        Some(OrderState::Cancelled) => "4",
+        Some(OrderState::Expired) => "C",
+        Some(OrderState::Rejected) | None => "8",
+    }
+}
+
+// The broker's pre-trade checks, against the account on file and the gateway's last prices.
+// `replacing` is the working order a replacement takes over from.
+fn check_order(
+    order: &Order,
+    last_prices: &HashMap<String, f64>,
+    borrow_desk: &std::sync::Mutex<BorrowDesk>,
+    blotter: &std::sync::Mutex<Blotter>,
+    replacing: Option<&str>,
+) -> Result<(), String> {
+    let (_, account) = find_client_account(CLIENT_HOLDINGS_PATH, order.client_id)
+        .ok_or_else(|| format!("No account for client {}", order.client_id))?;
+    let context = PreTradeContext {
+        borrow_desk,
+        blotter,
+        holdings_path: CLIENT_HOLDINGS_PATH,
+        replacing,
+    };
+    pre_trade_check(order, &account, last_prices, &context).map_err(|(_, text)| text)
+}
+
+fn broker_blotter(blotters: &Blotters, broker_id: u64) -> Result<Arc<std::sync::Mutex<Blotter>>, String> {
+    blotters.get(&broker_id).cloned().ok_or_else(|| format!("Unknown broker {}", broker_id))
+}
+
+// What is left for the matcher to work: OrderQty less what has already filled
+fn working_order(fix_order: &FixOrder) -> Order {
+    Order {
+        quantity: fix_order.order.quantity - fix_order.cum_qty,
+        ..fix_order.order.clone()
+    }
+}
+
+// Check the order, then register it with the gateway and send it down the broker's order path
+async fn submit(
+    fix_order: FixOrder,
+    state: &SharedState,
+    blotters: &Blotters,
+    borrow_desk: &std::sync::Mutex<BorrowDesk>,
+    producer: &FutureProducer,
+) -> Result<(), String> {
+    let blotter = broker_blotter(blotters, fix_order.order.broker_id)?;
+    let last_prices = state.lock().await.last_prices.clone();
+    check_order(&fix_order.order, &last_prices, borrow_desk, &blotter, None)?;
+    send_order(fix_order, state, &blotter, producer).await;
+    Ok(())
+}
+
+// Register an order that has passed its checks and send it to the matcher
+async fn send_order(fix_order: FixOrder, state: &SharedState, blotter: &std::sync::Mutex<Blotter>, producer: &FutureProducer) {
+    let order = working_order(&fix_order);
+    println!(
+        "{}",
+        format!(
+            "FIX {} {}: {:?} {} {} @ {:.2} for Client {}",
+            fix_order.comp_id, fix_order.cl_ord_id, order.order_action, order.quantity, order.stock_symbol, order.price, order.client_id
+        )
+        .bright_blue()
+        .bold()
+    );
+    {
+        let mut state = state.lock().await;
+        state
+            .cl_ord_ids
+            .insert((fix_order.comp_id.clone(), fix_order.cl_ord_id.clone()), order.order_id.clone());
+        state.orders.insert(order.order_id.clone(), fix_order);
+    }
+    Broker::send_order_to_kafka(order, producer, blotter).await;
+}
+
+async fn new_order(
+    message: &FixMessage,
+    comp_id: &str,
+    account: &SessionConfig,
+    state: &SharedState,
+    blotters: &Blotters,
+    borrow_desk: &std::sync::Mutex<BorrowDesk>,
+    producer: &FutureProducer,
+) {
+    let fix_order = {
+        let state = state.lock().await;
+        (|| -> Result<FixOrder, String> {
+            let cl_ord_id = message.get(CL_ORD_ID).ok_or("Missing ClOrdID")?;
+            if state.cl_ord_ids.contains_key(&(comp_id.to_string(), cl_ord_id.to_string())) {
+                return Err(format!("Duplicate ClOrdID {}", cl_ord_id));
+            }
+            let symbol = message.get(SYMBOL).ok_or("Missing Symbol")?;
+            let side = message.get(SIDE).ok_or("Missing Side")?;
+            let order_action = parse_side(side)?;
+            let order_type = parse_order_type(message.get(ORD_TYPE))?;
+            let quantity = parse_quantity(message.get(ORDER_QTY))?;
+            let price = order_price(symbol, &order_type, &order_action, message.get(PRICE), &state.last_prices)?;
+            Ok(FixOrder {
+                comp_id: comp_id.to_string(),
+                cl_ord_id: cl_ord_id.to_string(),
+                side: side.to_string(),
+                order: Order {
+                    broker_id: account.broker_id,
+                    client_id: account.client_id,
+                    order_id: new_unique_id(),
+                    stock_symbol: symbol.to_string(),
+                    order_type,
+                    order_action,
+                    price,
+                    quantity,
+                    status: OrderStatus::Pending,
+                    reject_reason: None,
+                    reject_text: None,
+                    liquidity: None,
+                    exec_id: None,
+                    liquidation: false,
+                    lot_ids: Vec::new(),
+                },
+                exchange_order_id: None,
+                cum_qty: 0,
+                notional: Money::ZERO,
+            })
+        })()
+    };
+
+    let result = match fix_order {
+        Ok(fix_order) => submit(fix_order, state, blotters, borrow_desk, producer).await,
+        Err(text) => Err(text),
+    };
+    if let Err(text) = result {
+        println!("{}", format!("FIX {} order rejected: {}", comp_id, text).bright_yellow());
+        state.lock().await.send(comp_id, rejected_request(message, &text));
+    }
+}
+
+// Only working orders without a cancel already in flight can be cancelled.
+// Errors carry the OrdStatus and text for the OrderCancelReject.
+fn check_cancellable(state: &GatewayState, order_id: &str, blotters: &Blotters) -> Result<(), (&'static str, &'static str)> {
+    let current = blotter_state(state, order_id, blotters);
+    if !current.is_some_and(OrderState::is_open) {
+        return Err((ord_status(current), "Order is no longer working"));
+    }
+    if state.pending_cancels.contains_key(order_id) {
+        return Err((ord_status(current), "A cancel is already pending"));
+    }
+    Ok(())
+}
+
+fn blotter_state(state: &GatewayState, order_id: &str, blotters: &Blotters) -> Option<OrderState> {
+    let broker_id = state.orders.get(order_id)?.order.broker_id;
+    blotters.get(&broker_id)?.lock().unwrap().state(order_id)
+}
+
+// Ask the matcher to pull the order; the cancel is reported when it confirms
+async fn cancel_order(message: &FixMessage, comp_id: &str, state: &SharedState, blotters: &Blotters, producer: &FutureProducer) {
+    let order = {
+        let mut state = state.lock().await;
+        let orig_cl_ord_id = message.get(ORIG_CL_ORD_ID).unwrap_or("");
+        let Some(order_id) = state.cl_ord_ids.get(&(comp_id.to_string(), orig_cl_ord_id.to_string())).cloned() else {
+            state.send(comp_id, cancel_reject(message, "1", "8", "Unknown order"));
+            return;
+        };
+        if let Err((status, text)) = check_cancellable(&state, &order_id, blotters) {
+            state.send(comp_id, cancel_reject(message, "1", status, text));
+            return;
+        }
+        state.pending_cancels.insert(order_id.clone(), PendingCancel { request: message.clone(), replacement: None });
+        state.orders[&order_id].order.clone()
+    };
+    Broker::send_cancel_to_kafka(&order, producer).await;
+}
+
+// Cancel the original and, once the matcher confirms, send a replacement with the new quantity, price or type.
+// The original is out of the book before the replacement exists, so the two can never both fill. The
+// replacement carries over what the original filled and is checked up front, so a replacement that
+// would be refused never costs the client the original.
+async fn replace_order(
+    message: &FixMessage,
+    comp_id: &str,
+    state: &SharedState,
+    blotters: &Blotters,
+    borrow_desk: &std::sync::Mutex<BorrowDesk>,
+    producer: &FutureProducer,
+) {
+    let original = {
+        let mut state = state.lock().await;
+        let orig_cl_ord_id = message.get(ORIG_CL_ORD_ID).unwrap_or("");
+        let Some(order_id) = state.cl_ord_ids.get(&(comp_id.to_string(), orig_cl_ord_id.to_string())).cloned() else {
+            state.send(comp_id, cancel_reject(message, "2", "8", "Unknown order"));
+            return;
+        };
+        let original = &state.orders[&order_id];
+        let result = (|| -> Result<FixOrder, String> {
+            let cl_ord_id = message.get(CL_ORD_ID).ok_or("Missing ClOrdID")?;
+            if state.cl_ord_ids.contains_key(&(comp_id.to_string(), cl_ord_id.to_string())) {
+                return Err(format!("Duplicate ClOrdID {}", cl_ord_id));
+            }
+            let order_type = match message.get(ORD_TYPE) {
+                Some(_) => parse_order_type(message.get(ORD_TYPE))?,
+                None => original.order.order_type.clone(),
+            };
+            let quantity = match message.get(ORDER_QTY) {
+                Some(_) => parse_quantity(message.get(ORDER_QTY))?,
+                None => original.order.quantity,
+            };
+            let symbol = &original.order.stock_symbol;
+            let price = match (message.get(PRICE), &order_type) {
+                (None, OrderType::Limit) => original.order.price,
+                (limit, _) => order_price(symbol, &order_type, &original.order.order_action, limit, &state.last_prices)?,
+            };
+            if quantity <= original.cum_qty {
+                return Err(format!("OrderQty {} is not above CumQty {}", quantity, original.cum_qty));
+            }
+            let mut order = original.order.clone();
+            order.order_id = new_unique_id();
+            order.order_type = order_type;
+            order.quantity = quantity;
+            order.price = price;
+            Ok(FixOrder {
+                comp_id: comp_id.to_string(),
+                cl_ord_id: cl_ord_id.to_string(),
+                side: original.side.clone(),
+                order,
+                exchange_order_id: None,
+                cum_qty: original.cum_qty,
+                notional: original.notional,
+            })
+        })();
+        let current = ord_status(blotter_state(&state, &order_id, blotters));
+        let replacement = match result {
+            Ok(replacement) => replacement,
+            Err(text) => {
+                state.send(comp_id, cancel_reject(message, "2", current, &text));
+                return;
+            }
+        };
+        if let Err((status, text)) = check_cancellable(&state, &order_id, blotters) {
+            state.send(comp_id, cancel_reject(message, "2", status, text));
+            return;
+        }
+        let checked = broker_blotter(blotters, replacement.order.broker_id).and_then(|blotter| {
+            check_order(&working_order(&replacement), &state.last_prices, borrow_desk, &blotter, Some(&order_id))
+        });
+        if let Err(text) = checked {
+            println!("{}", format!("FIX {} replace refused: {}", comp_id, text).bright_yellow());
+            state.send(comp_id, cancel_reject(message, "2", current, &text));
+            return;
+        }
+        let original = state.orders[&order_id].order.clone();
+        state
+            .pending_cancels
+            .insert(order_id, PendingCancel { request: message.clone(), replacement: Some(replacement) });
+        original
+    };
+    Broker::send_cancel_to_kafka(&original, producer).await;
+}
+
+// The matcher's answer to a cancel request. Returns the replacement to send, with the ClOrdID it replaces,
+// for a confirmed cancel/replace.
+fn complete_cancel(
+    state: &mut GatewayState,
+    cancel_ack: &CancelAck,
+    blotters: &Blotters,
+    borrow_desk: &std::sync::Mutex<BorrowDesk>,
+) -> Option<(FixOrder, String)> {
+    let pending = state.pending_cancels.remove(&cancel_ack.order_id)?;
+    let comp_id = state.orders.get(&cancel_ack.order_id)?.comp_id.clone();
+    let orig_cl_ord_id = pending.request.get(ORIG_CL_ORD_ID).unwrap_or("").to_string();
+    if !cancel_ack.cancelled {
+        let response_to = if let Some(replacement) = &pending.replacement {
+            // The replacement will never be sent, so the borrow it located goes back
+            borrow_desk.lock().unwrap().release(&replacement.order.order_id);
+            "2"
+        } else {
+            "1"
+        };
+        let status = ord_status(blotter_state(state, &cancel_ack.order_id, blotters));
+        let text = cancel_ack.reject_text.as_deref().unwrap_or("Order is no longer working");
+        state.send(&comp_id, cancel_reject(&pending.request, response_to, status, text));
+        return None;
+    }
+    match pending.replacement {
+        Some(mut replacement) => {
+            // Fills that reached the original while the cancel was in flight count towards the replacement
+            let original = &state.orders[&cancel_ack.order_id];
+            replacement.cum_qty = original.cum_qty;
+            replacement.notional = original.notional;
+            if replacement.cum_qty < replacement.order.quantity {
+                return Some((replacement, orig_cl_ord_id));
+            }
+            // Nothing left to work: the replace is reported done without a new order
+            borrow_desk.lock().unwrap().release(&replacement.order.order_id);
+            let report = execution_report(&replacement, "5", "2", &new_unique_id()).with(ORIG_CL_ORD_ID, &orig_cl_ord_id);
+            state.send(&comp_id, report);
+            None
+        }
+        None => {
+            let cl_ord_id = pending.request.get(CL_ORD_ID).unwrap_or(&orig_cl_ord_id).to_string();
+            state.cl_ord_ids.insert((comp_id.clone(), cl_ord_id.clone()), cancel_ack.order_id.clone());
+            let fix_order = state.orders.get_mut(&cancel_ack.order_id)?;
+            fix_order.cl_ord_id = cl_ord_id;
+            let report = execution_report(fix_order, "4", "4", &new_unique_id()).with(ORIG_CL_ORD_ID, &orig_cl_ord_id);
+            println!("FIX {} cancelled {}", comp_id, orig_cl_ord_id);
+            state.send(&comp_id, report);
+            None
+        }
+    }
+}
+
+fn report_consumer(group_id: &str, topic: &str) -> StreamConsumer {
+    let consumer: StreamConsumer = ClientConfig::new()
+        .set("bootstrap.servers", "localhost:9092")
+        .set("group.id", group_id)
+        .set("auto.offset.reset", "latest")
+        .set("enable.auto.commit", "true")
+        .set("isolation.level", "read_committed") // Only outcomes from committed matcher transactions
+        .create()
+        .expect("Consumer creation failed");
+    consumer.subscribe(&[topic]).expect("Can't subscribe to specified topic");
+    consumer
+}
+
+// Turn acks, fills, rejects and cancels for FIX orders into ExecutionReports on the owning session
+async fn report_executions(
+    state: SharedState,
+    blotters: Blotters,
+    borrow_desk: Arc<std::sync::Mutex<BorrowDesk>>,
+    producer: FutureProducer,
+) {
+    let ack_consumer = report_consumer("fix-gateway-ack-group", "order_acks");
+    let cancel_ack_consumer = report_consumer("fix-gateway-cancel-ack-group", "cancel_acks");
+    let completed_consumer = report_consumer("fix-gateway-completed-group", "completed_order");
+    let rejected_consumer = report_consumer("fix-gateway-rejected-group", "rejected_order");
+
+    loop {
+        tokio::select! {
+            message = ack_consumer.recv() => {
+                let Ok(m) = message else { continue };
+                let Some(Ok(ack)) = m.payload().map(wire::decode::<OrderAck>) else { continue };
+                let mut state = state.lock().await;
+                let Some(fix_order) = state.orders.get_mut(&ack.order_id) else { continue };
+                let report = if ack.accepted {
+                    fix_order.exchange_order_id = ack.exchange_order_id.clone();
+                    execution_report(fix_order, "0", "0", &new_unique_id())
+                } else {
+                    execution_report(fix_order, "8", "8", &new_unique_id())
+                        .with(TEXT, ack.reject_text.as_deref().unwrap_or("Rejected"))
+                };
+                let comp_id = fix_order.comp_id.clone();
+                state.send(&comp_id, report);
+            }
+            message = completed_consumer.recv() => {
+                let Ok(m) = message else { continue };
+                let Some(Ok(fill)) = m.payload().map(wire::decode::<Order>) else { continue };
+                let mut state = state.lock().await;
+                let exec_id = fill.exec_id.clone().unwrap_or_else(new_unique_id);
+                if !state.orders.contains_key(&fill.order_id) || !state.reported_exec_ids.insert(&exec_id) {
+                    continue;
+                }
+                let fix_order = state.orders.get_mut(&fill.order_id).unwrap();
+                fix_order.cum_qty += fill.quantity;
+                fix_order.notional += fill.price.times(fill.quantity as i64);
+                let status = if fix_order.cum_qty < fix_order.order.quantity { "1" } else { "2" };
+                let report = execution_report(fix_order, "F", status, &exec_id)
+                    .with(LAST_QTY, fill.quantity)
+                    .with(LAST_PX, fill.price);
+                let comp_id = fix_order.comp_id.clone();
+                state.send(&comp_id, report);
+            }
+            message = rejected_consumer.recv() => {
+                let Ok(m) = message else { continue };
+                let Some(Ok(rejected)) = m.payload().map(wire::decode::<Order>) else { continue };
+                let mut state = state.lock().await;
+                let Some(fix_order) = state.orders.get(&rejected.order_id) else { continue };
+                let report = execution_report(fix_order, "8", "8", &new_unique_id())
+                    .with(TEXT, rejected.reject_text.as_deref().unwrap_or("Rejected"));
+                let comp_id = fix_order.comp_id.clone();
+                state.send(&comp_id, report);
+            }
+            message = cancel_ack_consumer.recv() => {
+                let Ok(m) = message else { continue };
+                let Some(Ok(cancel_ack)) = m.payload().map(wire::decode::<CancelAck>) else { continue };
+                let replacement = complete_cancel(&mut *state.lock().await, &cancel_ack, &blotters, &borrow_desk);
+                if let Some((replacement, orig_cl_ord_id)) = replacement {
+                    // Checked when the replace arrived; Replaced is reported once the new order is on its way
+                    let Ok(blotter) = broker_blotter(&blotters, replacement.order.broker_id) else { continue };
+                    let status = if replacement.cum_qty > 0 { "1" } else { "0" };
+                    let report = execution_report(&replacement, "5", status, &new_unique_id()).with(ORIG_CL_ORD_ID, &orig_cl_ord_id);
+                    let comp_id = replacement.comp_id.clone();
+                    send_order(replacement, &state, &blotter, &producer).await;
+                    state.lock().await.send(&comp_id, report);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn heartbeat(sender: Option<&str>, target: Option<&str>) -> FixMessage {
+        let mut message = FixMessage::new(HEARTBEAT);
+        if let Some(sender) = sender {
+            message = message.with(SENDER_COMP_ID, sender);
+        }
+        if let Some(target) = target {
+            message = message.with(TARGET_COMP_ID, target);
+        }
+        message
+    }
+
+    #[test]
+    fn messages_must_come_from_the_session_and_be_addressed_to_the_gateway() {
+        assert!(check_comp_ids(&heartbeat(Some("CLIENT1"), Some(GATEWAY_COMP_ID)), "CLIENT1").is_ok());
+        assert!(check_comp_ids(&heartbeat(Some("CLIENT2"), Some(GATEWAY_COMP_ID)), "CLIENT1").is_err());
+        assert!(check_comp_ids(&heartbeat(None, Some(GATEWAY_COMP_ID)), "CLIENT1").is_err());
+        assert!(check_comp_ids(&heartbeat(Some("CLIENT1"), Some("OTHERGATEWAY")), "CLIENT1").is_err());
+        assert!(check_comp_ids(&heartbeat(Some("CLIENT1"), None), "CLIENT1").is_err());
+    }
+}
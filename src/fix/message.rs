@@ -0,0 +1,303 @@
+// fix/message.rs
+
+use serde::{Deserialize, Serialize};
+
+pub const SOH: char = '\u{1}';
+pub const BEGIN_STRING: &str = "FIX.4.4";
+
+// Tags used by the gateway
+pub const AVG_PX: u32 = 6;
+pub const BEGIN_SEQ_NO: u32 = 7;
+pub const BEGIN_STRING_TAG: u32 = 8;
+pub const BODY_LENGTH: u32 = 9;
+pub const CHECKSUM: u32 = 10;
+pub const CL_ORD_ID: u32 = 11;
+pub const CUM_QTY: u32 = 14;
+pub const END_SEQ_NO: u32 = 16;
+pub const EXEC_ID: u32 = 17;
+pub const LAST_PX: u32 = 31;
+pub const LAST_QTY: u32 = 32;
+pub const MSG_SEQ_NUM: u32 = 34;
+pub const MSG_TYPE: u32 = 35;
+pub const NEW_SEQ_NO: u32 = 36;
+pub const ORDER_ID: u32 = 37;
+pub const ORDER_QTY: u32 = 38;
+pub const ORD_STATUS: u32 = 39;
+pub const ORD_TYPE: u32 = 40;
+pub const ORIG_CL_ORD_ID: u32 = 41;
+pub const POSS_DUP_FLAG: u32 = 43;
+pub const PRICE: u32 = 44;
+pub const REF_SEQ_NUM: u32 = 45;
+pub const SENDER_COMP_ID: u32 = 49;
+pub const SENDING_TIME: u32 = 52;
+pub const SIDE: u32 = 54;
+pub const SYMBOL: u32 = 55;
+pub const TARGET_COMP_ID: u32 = 56;
+pub const TEXT: u32 = 58;
+pub const TRANSACT_TIME: u32 = 60;
+pub const ENCRYPT_METHOD: u32 = 98;
+pub const HEART_BT_INT: u32 = 108;
+pub const TEST_REQ_ID: u32 = 112;
+pub const ORIG_SENDING_TIME: u32 = 122;
+pub const GAP_FILL_FLAG: u32 = 123;
+pub const RESET_SEQ_NUM_FLAG: u32 = 141;
+pub const EXEC_TYPE: u32 = 150;
+pub const LEAVES_QTY: u32 = 151;
+pub const SESSION_REJECT_REASON: u32 = 373;
+pub const CXL_REJ_RESPONSE_TO: u32 = 434;
+
+// Message types (tag 35)
+pub const HEARTBEAT: &str = "0";
+pub const TEST_REQUEST: &str = "1";
+pub const RESEND_REQUEST: &str = "2";
+pub const REJECT: &str = "3";
+pub const SEQUENCE_RESET: &str = "4";
+pub const LOGOUT: &str = "5";
+pub const EXECUTION_REPORT: &str = "8";
+pub const ORDER_CANCEL_REJECT: &str = "9";
+pub const LOGON: &str = "A";
+pub const NEW_ORDER_SINGLE: &str = "D";
+pub const ORDER_CANCEL_REQUEST: &str = "F";
+pub const ORDER_CANCEL_REPLACE_REQUEST: &str = "G";
+
+// A tag=value message, fields in wire order
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct FixMessage {
+    fields: Vec<(u32, String)>,
+}
+
+impl FixMessage {
+    pub fn new(msg_type: &str) -> Self {
+        FixMessage {
+            fields: vec![(MSG_TYPE, msg_type.to_string())],
+        }
+    }
+
+    pub fn with(mut self, tag: u32, value: impl ToString) -> Self {
+        self.fields.push((tag, value.to_string()));
+        self
+    }
+
+    pub fn get(&self, tag: u32) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(field_tag, _)| *field_tag == tag)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn msg_type(&self) -> &str {
+        self.get(MSG_TYPE).unwrap_or("")
+    }
+
+    pub fn seq_num(&self) -> Option<u64> {
+        self.get(MSG_SEQ_NUM).and_then(|value| value.parse().ok())
+    }
+
+    // Session-level messages are never resent; a ResendRequest covering them is answered with a gap fill
+    pub fn is_admin(&self) -> bool {
+        matches!(
+            self.msg_type(),
+            HEARTBEAT | TEST_REQUEST | RESEND_REQUEST | REJECT | SEQUENCE_RESET | LOGOUT | LOGON
+        )
+    }
+
+    // Parse one complete message, checking BodyLength and CheckSum
+    pub fn parse(raw: &str) -> Result<FixMessage, String> {
+        let mut fields = Vec::new();
+        for field in raw.split(SOH).filter(|field| !field.is_empty()) {
+            let (tag, value) = field
+                .split_once('=')
+                .ok_or_else(|| format!("Malformed field '{}'", field))?;
+            let tag: u32 = tag.parse().map_err(|_| format!("Invalid tag '{}'", tag))?;
+            fields.push((tag, value.to_string()));
+        }
+
+        let tag_at = |index: usize| fields.get(index).map(|(tag, _)| *tag);
+        if tag_at(0) != Some(BEGIN_STRING_TAG) || tag_at(1) != Some(BODY_LENGTH) || tag_at(2) != Some(MSG_TYPE) {
+            return Err("Message must start with BeginString, BodyLength and MsgType".to_string());
+        }
+        if fields[0].1 != BEGIN_STRING {
+            return Err(format!("Unsupported BeginString {}", fields[0].1));
+        }
+        if fields.last().map(|(tag, _)| *tag) != Some(CHECKSUM) {
+            return Err("Message must end with CheckSum".to_string());
+        }
+
+        // BodyLength counts from after the BodyLength field up to the CheckSum field
+        let body_start = raw
+            .find(&format!("{}{}=", SOH, MSG_TYPE))
+            .map(|index| index + 1)
+            .ok_or("Missing MsgType")?;
+        let checksum_start = raw
+            .rfind(&format!("{}{}=", SOH, CHECKSUM))
+            .map(|index| index + 1)
+            .ok_or("Missing CheckSum")?;
+        let declared_length: usize = fields[1].1.parse().map_err(|_| "Invalid BodyLength")?;
+        if checksum_start - body_start != declared_length {
+            return Err(format!(
+                "BodyLength {} does not match actual {}",
+                declared_length,
+                checksum_start - body_start
+            ));
+        }
+        let declared_checksum: u32 = fields.last().unwrap().1.parse().map_err(|_| "Invalid CheckSum")?;
+        if checksum(&raw[..checksum_start]) != declared_checksum {
+            return Err("CheckSum mismatch".to_string());
+        }
+
+        // Header and trailer framing fields are not needed once validated
+        fields.retain(|(tag, _)| !matches!(*tag, BEGIN_STRING_TAG | BODY_LENGTH | CHECKSUM));
+        Ok(FixMessage { fields })
+    }
+
+    // Serialise with the standard header and trailer
+    pub fn encode(&self, sender_comp_id: &str, target_comp_id: &str, seq_num: u64, poss_dup: bool) -> String {
+        self.encode_at(sender_comp_id, target_comp_id, seq_num, poss_dup, &timestamp())
+    }
+
+    // Serialise with a given SendingTime, so a stored message can be resent with it as OrigSendingTime
+    pub fn encode_at(&self, sender_comp_id: &str, target_comp_id: &str, seq_num: u64, poss_dup: bool, sending_time: &str) -> String {
+        let mut body = format!("{}={}{}", MSG_TYPE, self.msg_type(), SOH);
+        body.push_str(&format!("{}={}{}", SENDER_COMP_ID, sender_comp_id, SOH));
+        body.push_str(&format!("{}={}{}", TARGET_COMP_ID, target_comp_id, SOH));
+        body.push_str(&format!("{}={}{}", MSG_SEQ_NUM, seq_num, SOH));
+        if poss_dup {
+            body.push_str(&format!("{}=Y{}", POSS_DUP_FLAG, SOH));
+        }
+        body.push_str(&format!("{}={}{}", SENDING_TIME, sending_time, SOH));
+        for (tag, value) in self.fields.iter().filter(|(tag, _)| *tag != MSG_TYPE) {
+            body.push_str(&format!("{}={}{}", tag, value, SOH));
+        }
+
+        let mut message = format!("{}={}{}{}={}{}", BEGIN_STRING_TAG, BEGIN_STRING, SOH, BODY_LENGTH, body.len(), SOH);
+        message.push_str(&body);
+        let sum = checksum(&message);
+        message.push_str(&format!("{}={:03}{}", CHECKSUM, sum, SOH));
+        message
+    }
+}
+
+// Sum of all bytes modulo 256
+fn checksum(text: &str) -> u32 {
+    text.bytes().map(u32::from).sum::<u32>() % 256
+}
+
+// UTCTimestamp with milliseconds
+pub fn timestamp() -> String {
+    chrono::Utc::now().format("%Y%m%d-%H:%M:%S%.3f").to_string()
+}
+
+// Take the next complete message off the front of the buffer, if one has fully arrived
+pub fn next_message(buffer: &mut String) -> Option<String> {
+    let start = buffer.find(&format!("{}=", BEGIN_STRING_TAG))?;
+    let trailer = format!("{}{}=", SOH, CHECKSUM);
+    let checksum_at = buffer[start..].find(&trailer)? + start;
+    let end = buffer[checksum_at + 1..].find(SOH)? + checksum_at + 2;
+    let message = buffer[start..end].to_string();
+    buffer.drain(..end);
+    Some(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GATEWAY: &str = "TRADINGSIDE";
+
+    fn new_order() -> FixMessage {
+        FixMessage::new(NEW_ORDER_SINGLE)
+            .with(CL_ORD_ID, "ord-1")
+            .with(SYMBOL, "AAPL")
+            .with(SIDE, "1")
+            .with(ORDER_QTY, 10)
+            .with(ORD_TYPE, "2")
+            .with(PRICE, "187.25")
+    }
+
+    // A message as a client would send it, with SOH written as '|'
+    fn raw(fields: &str) -> String {
+        let body = fields.replace('|', &SOH.to_string());
+        let mut message = format!("8=FIX.4.4{}9={}{}{}", SOH, body.len(), SOH, body);
+        let sum = checksum(&message);
+        message.push_str(&format!("10={:03}{}", sum, SOH));
+        message
+    }
+
+    #[test]
+    fn checksum_is_byte_sum_modulo_256() {
+        assert_eq!(checksum(""), 0);
+        assert_eq!(checksum("A"), 65);
+        assert_eq!(checksum("ddd"), (100 * 3) % 256);
+        // Bytes, not chars: 'ÿ' is two bytes in UTF-8
+        assert_eq!(checksum("\u{ff}"), (0xc3 + 0xbf) % 256);
+    }
+
+    #[test]
+    fn encoded_message_parses_back() {
+        let raw = new_order().encode("CLIENT1", GATEWAY, 7, true);
+        let parsed = FixMessage::parse(&raw).unwrap();
+        assert_eq!(parsed.msg_type(), NEW_ORDER_SINGLE);
+        assert_eq!(parsed.seq_num(), Some(7));
+        assert_eq!(parsed.get(SENDER_COMP_ID), Some("CLIENT1"));
+        assert_eq!(parsed.get(TARGET_COMP_ID), Some(GATEWAY));
+        assert_eq!(parsed.get(POSS_DUP_FLAG), Some("Y"));
+        assert_eq!(parsed.get(CL_ORD_ID), Some("ord-1"));
+        assert_eq!(parsed.get(PRICE), Some("187.25"));
+        // Framing fields are stripped once validated
+        assert_eq!(parsed.get(BODY_LENGTH), None);
+        assert_eq!(parsed.get(CHECKSUM), None);
+    }
+
+    #[test]
+    fn encode_writes_header_body_length_and_trailer() {
+        let raw = new_order().encode("CLIENT1", GATEWAY, 1, false);
+        assert!(raw.starts_with(&format!("8=FIX.4.4{}9=", SOH)));
+        assert!(raw.contains(&format!("{}35=D{}49=CLIENT1{}56={}{}34=1{}", SOH, SOH, SOH, GATEWAY, SOH, SOH)));
+        assert!(!raw.contains(&format!("{}43=", SOH)));
+
+        let checksum_at = raw.rfind(&format!("{}10=", SOH)).unwrap() + 1;
+        let trailer = &raw[checksum_at..];
+        assert_eq!(trailer, format!("10={:03}{}", checksum(&raw[..checksum_at]), SOH));
+    }
+
+    #[test]
+    fn parse_rejects_bad_framing() {
+        let good = raw("35=0|34=2|49=CLIENT1|56=TRADINGSIDE|");
+        assert!(FixMessage::parse(&good).is_ok());
+
+        let wrong_checksum = good.replace(&good[good.len() - 4..], "000\u{1}");
+        assert_eq!(FixMessage::parse(&wrong_checksum).unwrap_err(), "CheckSum mismatch");
+
+        let wrong_length = good.replacen("9=", "9=1", 1);
+        assert!(FixMessage::parse(&wrong_length).unwrap_err().starts_with("BodyLength"));
+
+        let wrong_version = good.replacen("FIX.4.4", "FIX.4.2", 1);
+        assert!(FixMessage::parse(&wrong_version).is_err());
+
+        assert!(FixMessage::parse("35=0\u{1}10=000\u{1}").is_err());
+        assert!(FixMessage::parse(&format!("8=FIX.4.4{}junk{}", SOH, SOH)).is_err());
+    }
+
+    #[test]
+    fn next_message_splits_a_stream() {
+        let first = raw("35=0|34=1|");
+        let second = raw("35=1|34=2|112=test|");
+        let mut buffer = format!("{}{}", first, &second[..10]);
+
+        assert_eq!(next_message(&mut buffer), Some(first));
+        // The second message has not fully arrived yet
+        assert_eq!(next_message(&mut buffer), None);
+        buffer.push_str(&second[10..]);
+        assert_eq!(next_message(&mut buffer), Some(second));
+        assert_eq!(next_message(&mut buffer), None);
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn next_message_skips_leading_noise() {
+        let message = raw("35=0|34=1|");
+        let mut buffer = format!("noise{}", message);
+        assert_eq!(next_message(&mut buffer), Some(message));
+    }
+
+}
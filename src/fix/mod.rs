@@ -0,0 +1,7 @@
+// fix/mod.rs
+
+pub mod message;
+pub mod session;
+pub mod gateway;
+
+pub use gateway::run_fix_gateway;
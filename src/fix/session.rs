@@ -0,0 +1,247 @@
+// fix/session.rs
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::Write;
+use crate::fix::message::{FixMessage, GAP_FILL_FLAG, NEW_SEQ_NO, ORIG_SENDING_TIME, SEQUENCE_RESET};
+
+pub const FIX_SESSIONS_PATH: &str = "src/data/fix_sessions.json";
+pub const FIX_SEQUENCES_PATH: &str = "src/data/fix_sequences.json";
+pub const FIX_MESSAGES_PATH: &str = "src/data/fix_messages.json";
+// Application messages kept per counterparty for resends; anything older is gap filled
+const STORED_MESSAGES_PER_SESSION: usize = 1_000;
+
+// Which broker account an external FIX client trades through
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SessionConfig {
+    pub broker_id: u64,
+    pub client_id: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct FixSessions {
+    pub sessions: HashMap<String, SessionConfig>, // SenderCompID -> Account
+}
+
+impl FixSessions {
+    // Missing or unreadable config accepts no logons
+    pub fn load(path: &str) -> Self {
+        match fs::read_to_string(path) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+                println!("Error parsing FIX sessions, no client can log on: {}", e);
+                FixSessions::default()
+            }),
+            Err(_) => FixSessions::default(),
+        }
+    }
+}
+
+// Next sequence numbers for one counterparty
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct SequenceNumbers {
+    pub next_incoming: u64,
+    pub next_outgoing: u64,
+}
+
+impl Default for SequenceNumbers {
+    fn default() -> Self {
+        SequenceNumbers {
+            next_incoming: 1,
+            next_outgoing: 1,
+        }
+    }
+}
+
+// Sequence numbers survive restarts so a reconnecting client resumes where it left off
+pub struct SequenceStore {
+    path: String,
+    sequences: HashMap<String, SequenceNumbers>, // SenderCompID -> Sequence numbers
+}
+
+impl SequenceStore {
+    pub fn load(path: &str) -> Self {
+        let sequences = fs::read_to_string(path)
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default();
+        SequenceStore {
+            path: path.to_string(),
+            sequences,
+        }
+    }
+
+    pub fn get(&self, comp_id: &str) -> SequenceNumbers {
+        self.sequences.get(comp_id).copied().unwrap_or_default()
+    }
+
+    pub fn reset(&mut self, comp_id: &str) {
+        self.sequences.insert(comp_id.to_string(), SequenceNumbers::default());
+        self.save();
+    }
+
+    pub fn set_next_incoming(&mut self, comp_id: &str, next_incoming: u64) {
+        self.sequences.entry(comp_id.to_string()).or_default().next_incoming = next_incoming;
+        self.save();
+    }
+
+    // Claim the sequence number for the next outbound message
+    pub fn take_outgoing(&mut self, comp_id: &str) -> u64 {
+        let sequence = self.sequences.entry(comp_id.to_string()).or_default();
+        let seq_num = sequence.next_outgoing;
+        sequence.next_outgoing += 1;
+        self.save();
+        seq_num
+    }
+
+    fn save(&self) {
+        let json = serde_json::to_string_pretty(&self.sequences).expect("Failed to serialize FIX sequences");
+        if let Err(e) = write_atomically(&self.path, &json) {
+            println!("Error writing FIX sequence numbers: {}", e);
+        }
+    }
+}
+
+// An application message as it was first sent
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StoredMessage {
+    pub message: FixMessage,
+    pub sending_time: String,
+}
+
+// Outbound application messages by sequence number, kept across restarts with the sequence numbers
+// so a ResendRequest is answered with the messages themselves
+pub struct MessageStore {
+    path: String,
+    messages: HashMap<String, BTreeMap<u64, StoredMessage>>, // SenderCompID -> MsgSeqNum -> Message
+}
+
+impl MessageStore {
+    pub fn load(path: &str) -> Self {
+        let messages = fs::read_to_string(path)
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default();
+        MessageStore {
+            path: path.to_string(),
+            messages,
+        }
+    }
+
+    pub fn store(&mut self, comp_id: &str, seq_num: u64, message: StoredMessage) {
+        let messages = self.messages.entry(comp_id.to_string()).or_default();
+        messages.insert(seq_num, message);
+        while messages.len() > STORED_MESSAGES_PER_SESSION {
+            messages.pop_first();
+        }
+        self.save();
+    }
+
+    pub fn reset(&mut self, comp_id: &str) {
+        self.messages.remove(comp_id);
+        self.save();
+    }
+
+    // The answer to a ResendRequest for begin..=end (end 0 meaning everything sent so far), as
+    // (MsgSeqNum, message) pairs. Stored messages go out again carrying their OrigSendingTime; each run
+    // of sequence numbers with nothing stored (session messages, or messages no longer kept) is one gap fill.
+    pub fn resend(&self, comp_id: &str, begin: u64, end: u64, next_outgoing: u64) -> Vec<(u64, FixMessage)> {
+        let begin = begin.max(1);
+        let last = if end == 0 { next_outgoing - 1 } else { end.min(next_outgoing - 1) };
+        if begin > last {
+            return Vec::new();
+        }
+        let gap_fill = |new_seq_no: u64| FixMessage::new(SEQUENCE_RESET).with(GAP_FILL_FLAG, "Y").with(NEW_SEQ_NO, new_seq_no);
+
+        let mut resends = Vec::new();
+        let mut next = begin;
+        if let Some(messages) = self.messages.get(comp_id) {
+            for (&seq_num, stored) in messages.range(begin..=last) {
+                if seq_num > next {
+                    resends.push((next, gap_fill(seq_num)));
+                }
+                resends.push((seq_num, stored.message.clone().with(ORIG_SENDING_TIME, &stored.sending_time)));
+                next = seq_num + 1;
+            }
+        }
+        if next <= last {
+            resends.push((next, gap_fill(last + 1)));
+        }
+        resends
+    }
+
+    fn save(&self) {
+        let json = serde_json::to_string(&self.messages).expect("Failed to serialize FIX messages");
+        if let Err(e) = write_atomically(&self.path, &json) {
+            println!("Error writing FIX message store: {}", e);
+        }
+    }
+}
+
+// Written in one step, like the holdings file, so a crash never leaves it half written
+fn write_atomically(path: &str, contents: &str) -> std::io::Result<()> {
+    let temp_path = format!("{}.tmp", path);
+    File::create(&temp_path)
+        .and_then(|mut f| f.write_all(contents.as_bytes()).and_then(|_| f.sync_all()))
+        .and_then(|_| fs::rename(&temp_path, path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fix::message::{CL_ORD_ID, EXECUTION_REPORT};
+
+    fn store_with(name: &str, seq_nums: &[u64]) -> MessageStore {
+        let path = std::env::temp_dir().join(format!("fix_messages_{}_{}.json", name, std::process::id()));
+        let mut store = MessageStore::load(path.to_str().unwrap());
+        for &seq_num in seq_nums {
+            let message = FixMessage::new(EXECUTION_REPORT).with(CL_ORD_ID, seq_num);
+            store.store("CLIENT1", seq_num, StoredMessage { message, sending_time: format!("t{}", seq_num) });
+        }
+        let _ = fs::remove_file(&path);
+        store
+    }
+
+    // Each resent message as (MsgSeqNum, MsgType, ClOrdID or NewSeqNo)
+    fn summary(resends: &[(u64, FixMessage)]) -> Vec<(u64, String, String)> {
+        resends
+            .iter()
+            .map(|(seq_num, message)| {
+                let detail = message.get(CL_ORD_ID).or(message.get(NEW_SEQ_NO)).unwrap_or("").to_string();
+                (*seq_num, message.msg_type().to_string(), detail)
+            })
+            .collect()
+    }
+
+    fn entry(seq_num: u64, msg_type: &str, detail: u64) -> (u64, String, String) {
+        (seq_num, msg_type.to_string(), detail.to_string())
+    }
+
+    #[test]
+    fn stored_messages_are_replayed_and_the_gaps_between_them_filled() {
+        let store = store_with("replay", &[2, 3, 6]);
+        let resends = store.resend("CLIENT1", 1, 0, 9);
+        assert_eq!(
+            summary(&resends),
+            vec![
+                entry(1, SEQUENCE_RESET, 2),
+                entry(2, "8", 2),
+                entry(3, "8", 3),
+                entry(4, SEQUENCE_RESET, 6),
+                entry(6, "8", 6),
+                entry(7, SEQUENCE_RESET, 9),
+            ]
+        );
+        assert_eq!(resends[1].1.get(ORIG_SENDING_TIME), Some("t2"));
+    }
+
+    #[test]
+    fn resend_is_bounded_by_end_and_by_what_was_sent() {
+        let store = store_with("bounds", &[2, 3, 6]);
+        assert_eq!(summary(&store.resend("CLIENT1", 3, 4, 9)), vec![entry(3, "8", 3), entry(4, SEQUENCE_RESET, 5)]);
+        assert_eq!(summary(&store.resend("CLIENT1", 6, 100, 7)), vec![entry(6, "8", 6)]);
+        assert!(store.resend("CLIENT1", 9, 0, 9).is_empty());
+        // Nothing stored for a counterparty: one gap fill over the whole range
+        assert_eq!(summary(&store.resend("CLIENT2", 1, 0, 4)), vec![entry(1, SEQUENCE_RESET, 4)]);
+    }
+}
@@ -0,0 +1,614 @@
+// indicators.rs
+
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use crate::models::{Bar, PriceUpdate, Trade};
+
+pub const INDICATOR_CONFIG_PATH: &str = "src/data/indicator_config.json";
+
+// Periods are counted in ticks for price-driven indicators and in bars for ATR
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct IndicatorConfig {
+    pub sma_period: usize,
+    pub ema_period: usize,
+    pub bollinger_period: usize,
+    pub bollinger_width: f64, // Standard deviations either side of the middle band
+    pub rsi_period: usize,
+    pub macd_fast: usize,
+    pub macd_slow: usize,
+    pub macd_signal: usize,
+    pub atr_period: usize,
+    pub volatility_window: usize,
+}
+
+impl Default for IndicatorConfig {
+    fn default() -> Self {
+        IndicatorConfig {
+            sma_period: 20,
+            ema_period: 20,
+            bollinger_period: 20,
+            bollinger_width: 2.0,
+            rsi_period: 14,
+            macd_fast: 12,
+            macd_slow: 26,
+            macd_signal: 9,
+            atr_period: 14,
+            volatility_window: 20,
+        }
+    }
+}
+
+impl IndicatorConfig {
+    pub fn load(path: &str) -> Self {
+        match fs::read_to_string(path) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+                println!("Error parsing indicator config, using default: {}", e);
+                IndicatorConfig::default()
+            }),
+            Err(_) => IndicatorConfig::default(),
+        }
+    }
+}
+
+// Mean and sum of squared deviations over the last `period` values, kept with Welford's update
+// so the variance doesn't cancel away at high price levels. Both are recomputed from the values
+// each time the window turns over, so rounding can't accumulate over a long session.
+#[derive(Debug, Clone)]
+struct RollingWindow {
+    period: usize,
+    values: VecDeque<f64>,
+    mean: f64,
+    squared_deviations: f64,
+    replaced: usize, // Values replaced since the last recompute
+}
+
+impl RollingWindow {
+    fn new(period: usize) -> Self {
+        RollingWindow {
+            period: period.max(1),
+            values: VecDeque::new(),
+            mean: 0.0,
+            squared_deviations: 0.0,
+            replaced: 0,
+        }
+    }
+
+    fn push(&mut self, value: f64) {
+        if self.values.len() == self.period {
+            // Replace the oldest value in one step
+            let oldest = self.values.pop_front().unwrap_or_default();
+            let previous_mean = self.mean;
+            self.mean += (value - oldest) / self.period as f64;
+            self.squared_deviations += (value - oldest) * (value - self.mean + oldest - previous_mean);
+        } else {
+            let delta = value - self.mean;
+            self.mean += delta / (self.values.len() + 1) as f64;
+            self.squared_deviations += delta * (value - self.mean);
+        }
+        self.values.push_back(value);
+        if self.is_full() {
+            self.replaced += 1;
+            if self.replaced >= self.period {
+                self.recompute();
+            }
+        }
+    }
+
+    fn recompute(&mut self) {
+        let count = self.values.len() as f64;
+        self.mean = self.values.iter().sum::<f64>() / count;
+        self.squared_deviations = self.values.iter().map(|value| (value - self.mean).powi(2)).sum();
+        self.replaced = 0;
+    }
+
+    fn is_full(&self) -> bool {
+        self.values.len() == self.period
+    }
+
+    fn mean(&self) -> Option<f64> {
+        self.is_full().then_some(self.mean)
+    }
+
+    // Population standard deviation; rounding can leave a tiny negative variance, so it is floored at zero
+    fn std_dev(&self) -> Option<f64> {
+        self.is_full()
+            .then(|| (self.squared_deviations / self.period as f64).max(0.0).sqrt())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Sma {
+    window: RollingWindow,
+}
+
+impl Sma {
+    pub fn new(period: usize) -> Self {
+        Sma { window: RollingWindow::new(period) }
+    }
+
+    pub fn update(&mut self, price: f64) -> Option<f64> {
+        self.window.push(price);
+        self.value()
+    }
+
+    pub fn value(&self) -> Option<f64> {
+        self.window.mean()
+    }
+}
+
+// Seeded with the first value, then smoothed with alpha = 2 / (period + 1)
+// (the same as pandas' ewm with adjust=False)
+#[derive(Debug, Clone)]
+pub struct Ema {
+    alpha: f64,
+    value: Option<f64>,
+}
+
+impl Ema {
+    pub fn new(period: usize) -> Self {
+        Ema {
+            alpha: 2.0 / (period.max(1) as f64 + 1.0),
+            value: None,
+        }
+    }
+
+    pub fn update(&mut self, price: f64) -> f64 {
+        let value = match self.value {
+            Some(previous) => previous + self.alpha * (price - previous),
+            None => price,
+        };
+        self.value = Some(value);
+        value
+    }
+
+    pub fn value(&self) -> Option<f64> {
+        self.value
+    }
+}
+
+// Wilder's smoothing, as used by RSI and ATR: the simple average of the first `period` values,
+// then an EMA with alpha = 1 / period
+#[derive(Debug, Clone)]
+struct WilderAverage {
+    period: usize,
+    count: usize,
+    value: f64,
+}
+
+impl WilderAverage {
+    fn new(period: usize) -> Self {
+        WilderAverage {
+            period: period.max(1),
+            count: 0,
+            value: 0.0,
+        }
+    }
+
+    fn update(&mut self, value: f64) {
+        self.count = (self.count + 1).min(self.period);
+        self.value += (value - self.value) / self.count as f64;
+    }
+
+    // None until a full period has been averaged
+    fn value(&self) -> Option<f64> {
+        (self.count == self.period).then_some(self.value)
+    }
+}
+
+// Volume-weighted average price since the session started
+#[derive(Debug, Clone, Default)]
+pub struct Vwap {
+    notional: f64,
+    volume: u64,
+}
+
+impl Vwap {
+    pub fn update(&mut self, price: f64, quantity: u64) -> Option<f64> {
+        self.notional += price * quantity as f64;
+        self.volume += quantity;
+        self.value()
+    }
+
+    pub fn value(&self) -> Option<f64> {
+        (self.volume > 0).then(|| self.notional / self.volume as f64)
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy)]
+pub struct BollingerBands {
+    pub lower: f64,
+    pub middle: f64,
+    pub upper: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Bollinger {
+    window: RollingWindow,
+    width: f64,
+}
+
+impl Bollinger {
+    pub fn new(period: usize, width: f64) -> Self {
+        Bollinger {
+            window: RollingWindow::new(period),
+            width,
+        }
+    }
+
+    pub fn update(&mut self, price: f64) -> Option<BollingerBands> {
+        self.window.push(price);
+        self.value()
+    }
+
+    pub fn value(&self) -> Option<BollingerBands> {
+        let middle = self.window.mean()?;
+        let band = self.width * self.window.std_dev()?;
+        Some(BollingerBands {
+            lower: middle - band,
+            middle,
+            upper: middle + band,
+        })
+    }
+}
+
+// Wilder's RSI; needs `period` price changes before it reports
+#[derive(Debug, Clone)]
+pub struct Rsi {
+    previous: Option<f64>,
+    average_gain: WilderAverage,
+    average_loss: WilderAverage,
+}
+
+impl Rsi {
+    pub fn new(period: usize) -> Self {
+        Rsi {
+            previous: None,
+            average_gain: WilderAverage::new(period),
+            average_loss: WilderAverage::new(period),
+        }
+    }
+
+    pub fn update(&mut self, price: f64) -> Option<f64> {
+        if let Some(previous) = self.previous.replace(price) {
+            let change = price - previous;
+            self.average_gain.update(change.max(0.0));
+            self.average_loss.update((-change).max(0.0));
+        }
+        self.value()
+    }
+
+    pub fn value(&self) -> Option<f64> {
+        let gain = self.average_gain.value()?;
+        let loss = self.average_loss.value()?;
+        Some(if loss == 0.0 { 100.0 } else { 100.0 - 100.0 / (1.0 + gain / loss) })
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy)]
+pub struct MacdValue {
+    pub macd: f64,
+    pub signal: f64,
+    pub histogram: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Macd {
+    fast: Ema,
+    slow: Ema,
+    signal: Ema,
+    slow_period: usize,
+    updates: usize,
+}
+
+impl Macd {
+    pub fn new(fast_period: usize, slow_period: usize, signal_period: usize) -> Self {
+        Macd {
+            fast: Ema::new(fast_period),
+            slow: Ema::new(slow_period),
+            signal: Ema::new(signal_period),
+            slow_period,
+            updates: 0,
+        }
+    }
+
+    pub fn update(&mut self, price: f64) -> Option<MacdValue> {
+        let macd = self.fast.update(price) - self.slow.update(price);
+        self.signal.update(macd);
+        self.updates += 1;
+        self.value()
+    }
+
+    // Reported once the slow average has seen a full period
+    pub fn value(&self) -> Option<MacdValue> {
+        if self.updates < self.slow_period {
+            return None;
+        }
+        let macd = self.fast.value()? - self.slow.value()?;
+        let signal = self.signal.value()?;
+        Some(MacdValue {
+            macd,
+            signal,
+            histogram: macd - signal,
+        })
+    }
+}
+
+// Wilder's average true range over bars
+#[derive(Debug, Clone)]
+pub struct Atr {
+    previous_close: Option<f64>,
+    average: WilderAverage,
+}
+
+impl Atr {
+    pub fn new(period: usize) -> Self {
+        Atr {
+            previous_close: None,
+            average: WilderAverage::new(period),
+        }
+    }
+
+    pub fn update(&mut self, high: f64, low: f64, close: f64) -> Option<f64> {
+        let true_range = match self.previous_close.replace(close) {
+            Some(previous_close) => (high - low).max((high - previous_close).abs()).max((low - previous_close).abs()),
+            None => high - low,
+        };
+        self.average.update(true_range);
+        self.value()
+    }
+
+    pub fn value(&self) -> Option<f64> {
+        self.average.value()
+    }
+}
+
+// Standard deviation of log returns over the last `window` ticks, in percent per tick
+#[derive(Debug, Clone)]
+pub struct RollingVolatility {
+    previous: Option<f64>,
+    returns: RollingWindow,
+}
+
+impl RollingVolatility {
+    pub fn new(window: usize) -> Self {
+        RollingVolatility {
+            previous: None,
+            returns: RollingWindow::new(window),
+        }
+    }
+
+    pub fn update(&mut self, price: f64) -> Option<f64> {
+        if let Some(previous) = self.previous.replace(price) {
+            if previous > 0.0 && price > 0.0 {
+                self.returns.push((price / previous).ln());
+            }
+        }
+        self.value()
+    }
+
+    pub fn value(&self) -> Option<f64> {
+        self.returns.std_dev().map(|std_dev| std_dev * 100.0)
+    }
+}
+
+// Everything a strategy can read for one symbol; None until an indicator has enough history
+#[derive(Serialize, Debug, Clone, Copy, Default)]
+pub struct IndicatorValues {
+    pub sma: Option<f64>,
+    pub ema: Option<f64>,
+    pub vwap: Option<f64>,
+    pub bollinger: Option<BollingerBands>,
+    pub rsi: Option<f64>,
+    pub macd: Option<MacdValue>,
+    pub atr: Option<f64>,
+    pub volatility: Option<f64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SymbolIndicators {
+    sma: Sma,
+    ema: Ema,
+    vwap: Vwap,
+    bollinger: Bollinger,
+    rsi: Rsi,
+    macd: Macd,
+    atr: Atr,
+    volatility: RollingVolatility,
+}
+
+impl SymbolIndicators {
+    pub fn new(config: &IndicatorConfig) -> Self {
+        SymbolIndicators {
+            sma: Sma::new(config.sma_period),
+            ema: Ema::new(config.ema_period),
+            vwap: Vwap::default(),
+            bollinger: Bollinger::new(config.bollinger_period, config.bollinger_width),
+            rsi: Rsi::new(config.rsi_period),
+            macd: Macd::new(config.macd_fast, config.macd_slow, config.macd_signal),
+            atr: Atr::new(config.atr_period),
+            volatility: RollingVolatility::new(config.volatility_window),
+        }
+    }
+
+    pub fn on_price(&mut self, price: f64) {
+        self.sma.update(price);
+        self.ema.update(price);
+        self.bollinger.update(price);
+        self.rsi.update(price);
+        self.macd.update(price);
+        self.volatility.update(price);
+    }
+
+    pub fn on_trade(&mut self, price: f64, quantity: u64) {
+        self.vwap.update(price, quantity);
+    }
+
+    pub fn on_bar(&mut self, bar: &Bar) {
+        self.atr.update(bar.high, bar.low, bar.close);
+    }
+
+    pub fn values(&self) -> IndicatorValues {
+        IndicatorValues {
+            sma: self.sma.value(),
+            ema: self.ema.value(),
+            vwap: self.vwap.value(),
+            bollinger: self.bollinger.value(),
+            rsi: self.rsi.value(),
+            macd: self.macd.value(),
+            atr: self.atr.value(),
+            volatility: self.volatility.value(),
+        }
+    }
+}
+
+// Indicators for every symbol seen on the feeds
+pub struct IndicatorSet {
+    config: IndicatorConfig,
+    atr_interval_secs: u64, // ATR reads one bar interval so bars of different lengths aren't mixed
+    symbols: HashMap<String, SymbolIndicators>,
+}
+
+impl IndicatorSet {
+    pub fn new(config: IndicatorConfig, atr_interval_secs: u64) -> Self {
+        IndicatorSet {
+            config,
+            atr_interval_secs,
+            symbols: HashMap::new(),
+        }
+    }
+
+    fn symbol(&mut self, stock_symbol: &str) -> &mut SymbolIndicators {
+        let config = &self.config;
+        self.symbols
+            .entry(stock_symbol.to_string())
+            .or_insert_with(|| SymbolIndicators::new(config))
+    }
+
+    pub fn on_price(&mut self, update: &PriceUpdate) {
+        self.symbol(&update.name).on_price(update.price);
+    }
+
+    pub fn on_trade(&mut self, trade: &Trade) {
+        self.symbol(&trade.stock_symbol).on_trade(trade.price, trade.quantity);
+    }
+
+    pub fn on_bar(&mut self, bar: &Bar) {
+        if bar.interval_secs == self.atr_interval_secs {
+            self.symbol(&bar.stock_symbol).on_bar(bar);
+        }
+    }
+
+    pub fn values(&self, stock_symbol: &str) -> Option<IndicatorValues> {
+        self.symbols.get(stock_symbol).map(SymbolIndicators::values)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-9, "expected {}, got {}", expected, actual);
+    }
+
+    #[test]
+    fn sma_reports_once_the_window_is_full() {
+        let mut sma = Sma::new(3);
+        assert_eq!(sma.update(1.0), None);
+        assert_eq!(sma.update(2.0), None);
+        assert_close(sma.update(3.0).unwrap(), 2.0);
+        assert_close(sma.update(4.0).unwrap(), 3.0);
+        assert_close(sma.update(8.0).unwrap(), 5.0);
+    }
+
+    #[test]
+    fn ema_starts_from_the_first_price() {
+        let mut ema = Ema::new(3);
+        assert_eq!(ema.value(), None);
+        assert_close(ema.update(1.0), 1.0);
+        assert_close(ema.update(2.0), 1.5);
+        assert_close(ema.update(3.0), 2.25);
+        assert_close(ema.update(4.0), 3.125);
+    }
+
+    #[test]
+    fn rsi_uses_wilders_smoothing() {
+        let mut rsi = Rsi::new(3);
+        assert_eq!(rsi.update(10.0), None);
+        assert_eq!(rsi.update(11.0), None);
+        assert_eq!(rsi.update(10.5), None);
+        // Seeded with the average gain of 5/6 and loss of 1/6 over the first three changes
+        assert_close(rsi.update(12.0).unwrap(), 100.0 - 100.0 / 6.0);
+        // Gain (5/6 * 2 + 0) / 3 = 5/9, loss (1/6 * 2 + 1) / 3 = 4/9
+        assert_close(rsi.update(11.0).unwrap(), 100.0 - 100.0 / 2.25);
+    }
+
+    #[test]
+    fn rsi_is_100_without_losses() {
+        let mut rsi = Rsi::new(2);
+        for price in [1.0, 2.0, 3.0] {
+            rsi.update(price);
+        }
+        assert_close(rsi.value().unwrap(), 100.0);
+    }
+
+    #[test]
+    fn macd_reports_after_the_slow_period() {
+        let mut macd = Macd::new(2, 3, 2);
+        assert!(macd.update(1.0).is_none());
+        assert!(macd.update(2.0).is_none());
+        let value = macd.update(3.0).unwrap();
+        // Fast EMA 23/9, slow EMA 9/4, signal EMA of the MACD line 13/54
+        assert_close(value.macd, 23.0 / 9.0 - 9.0 / 4.0);
+        assert_close(value.signal, 13.0 / 54.0);
+        assert_close(value.histogram, 23.0 / 9.0 - 9.0 / 4.0 - 13.0 / 54.0);
+    }
+
+    #[test]
+    fn atr_averages_true_ranges_including_gaps() {
+        let mut atr = Atr::new(3);
+        assert_eq!(atr.update(10.0, 8.0, 9.0), None);
+        assert_eq!(atr.update(11.0, 9.0, 10.0), None);
+        assert_close(atr.update(13.0, 10.0, 12.0).unwrap(), 7.0 / 3.0);
+        // Gap down: the true range runs from the previous close of 12 to the low of 8
+        assert_close(atr.update(9.0, 8.0, 8.5).unwrap(), 26.0 / 9.0);
+    }
+
+    #[test]
+    fn bollinger_bands_use_the_population_deviation() {
+        let mut bollinger = Bollinger::new(8, 2.0);
+        let mut bands = None;
+        for price in [2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0] {
+            assert!(bands.is_none());
+            bands = bollinger.update(price);
+        }
+        let bands = bands.unwrap();
+        assert_close(bands.middle, 5.0);
+        assert_close(bands.lower, 1.0);
+        assert_close(bands.upper, 9.0);
+    }
+
+    #[test]
+    fn volatility_is_the_deviation_of_log_returns() {
+        let mut volatility = RollingVolatility::new(2);
+        assert_eq!(volatility.update(100.0), None);
+        assert_eq!(volatility.update(110.0), None);
+        let expected = ((1.1f64).ln() - (0.9f64).ln()) / 2.0 * 100.0;
+        assert_close(volatility.update(99.0).unwrap(), expected);
+    }
+
+    #[test]
+    fn rolling_deviation_holds_at_high_price_levels() {
+        let mut window = RollingWindow::new(20);
+        let prices: Vec<f64> = (0..10_007).map(|i| 1.0e8 + (i % 7) as f64 * 0.01).collect();
+        for &price in &prices {
+            window.push(price);
+        }
+        let last = &prices[prices.len() - 20..];
+        let mean = last.iter().sum::<f64>() / 20.0;
+        let expected = (last.iter().map(|price| (price - mean).powi(2)).sum::<f64>() / 20.0).sqrt();
+        assert!((window.std_dev().unwrap() - expected).abs() < 1e-6);
+        assert!((window.mean().unwrap() - mean).abs() < 1e-6);
+    }
+}
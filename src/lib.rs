@@ -0,0 +1,30 @@
+// lib.rs
+//
+// Every module of the simulator, shared by the TradingSide binary and the tools under src/bin
+
+pub mod stock_updater;
+pub mod stock_price_consumer;
+pub mod broker;
+pub mod models;
+pub mod performance;
+pub mod order_matcher;
+pub mod order_status_receiver;
+pub mod order_book;
+pub mod market_data;
+pub mod trade_tape;
+pub mod bars;
+pub mod tick_store;
+pub mod indicators;
+pub mod auction;
+pub mod circuit_breaker;
+pub mod fees;
+pub mod execution_model;
+pub mod corporate_actions;
+pub mod currency;
+pub mod money;
+pub mod reconciliation;
+pub mod dedup;
+pub mod dead_letter;
+pub mod wire;
+pub mod fix;
+pub mod api;
@@ -1,48 +1,59 @@
-
 // main.rs
 
-mod stock_updater;
-
-mod stock_price_consumer;
-mod broker;
-mod models;
-mod performance;
-mod order_matcher;
-mod order_status_receiver;
-
-use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-pub use broker::initialize_brokers; 
-
-use crate::broker::data::reset_client_holdings_json;
+use trading_side::broker::initialize_brokers;
+use trading_side::{api, bars, fix, order_matcher, order_status_receiver, performance, reconciliation, stock_price_consumer, stock_updater};
+use trading_side::broker::borrow::{BorrowDesk, BORROW_INVENTORY_PATH};
+use trading_side::broker::data::charge_borrow_fees_in_json;
 use rdkafka::producer::FutureProducer;
 use rdkafka::ClientConfig;
 
 #[tokio::main]
 async fn main() {
     println!("MARKET OPEN");
-    // 1. Global order counter for unique order IDs across all brokers
-    let global_order_counter = Arc::new(AtomicU64::new(1)); // Start from Order 1
-    
+    // The tick store keeps every session; the report only looks at this one
+    let session_start_ms = chrono::Utc::now().timestamp_millis();
     // 2. Broadcast channel for stock price updates
     let buffer_size = 1000; // Buffer size for the broadcast channel
     let (price_tx, _) = tokio::sync::broadcast::channel(buffer_size); // Price update broadcast channel
+    let (halt_tx, _) = tokio::sync::broadcast::channel(buffer_size); // Circuit breaker halt broadcast channel
+    let (exec_tx, _) = tokio::sync::broadcast::channel(buffer_size); // Execution report broadcast channel
+    let (quote_tx, _) = tokio::sync::broadcast::channel(buffer_size); // Top-of-book quote broadcast channel
+    let (trade_tx, _) = tokio::sync::broadcast::channel(buffer_size); // Public trade broadcast channel
+    let (bar_tx, _) = tokio::sync::broadcast::channel(buffer_size); // Closed OHLCV bar broadcast channel
 
     // 3. Number of brokers
     let total_brokers = 5; // Total number of brokers
     
     // 4. Initialize brokers
+    // Stock loan desk shared by all brokers for short sale locates
+    let borrow_desk = Arc::new(std::sync::Mutex::new(BorrowDesk::load(BORROW_INVENTORY_PATH)));
     // Initialize brokers using the helper function
-    let brokers = initialize_brokers(total_brokers, price_tx.clone(), global_order_counter.clone());
+    let brokers = initialize_brokers(total_brokers, price_tx.clone(), halt_tx.clone(), quote_tx.clone(), trade_tx.clone(), bar_tx.clone(), borrow_desk.clone());
+
+    // Each broker's blotter, updated from acks and fills and checked for open orders at the close
+    let mut blotters = order_status_receiver::Blotters::new();
+    for broker in &brokers {
+        let broker = broker.lock().await;
+        blotters.insert(broker.id, broker.blotter());
+    }
+
+    // Latest prices for market orders entered over FIX
+    let fix_price_rx = price_tx.subscribe();
+    // Price feed for the API's price cache and WebSocket stream
+    let api_price_tx = price_tx.clone();
 
     // Start all brokers
     let mut broker_handles = Vec::new();
     for broker in &brokers {
         let producer = create_producer();
         let broker_clone = broker.clone();
+        // Fills tell the broker's margin monitor to reload its accounts
+        let exec_rx = exec_tx.subscribe();
         let handle = tokio::spawn(async move {
             let mut broker = broker_clone.lock().await;
-            broker.start_broker_task(producer).await;
+            broker.start_broker_task(producer, exec_rx).await;
         });
 
         broker_handles.push(handle);
@@ -50,9 +61,10 @@ async fn main() {
 
     // 5. Reset client holdings and broker records
     let json_file_path = "src/data/client_holdings.json"; // Path to the JSON file
-    let clients_per_broker = 3;
-    // Reset all client portfolios to empty
-    //reset_client_holdings_json(json_file_path, total_brokers, clients_per_broker);
+    // Reset all client portfolios to empty (three clients per broker)
+    //trading_side::broker::data::reset_client_holdings_json(json_file_path, total_brokers, 3);
+    // Positions before any of today's fills, for the end-of-day reconciliation
+    reconciliation::snapshot_positions(json_file_path);
 
     // 6. Create the Kafka producer
     // Start the order processor, to send order to kafka i think
@@ -72,11 +84,51 @@ async fn main() {
         stock_updater::start_price_updater().await;
     });
 
+    // Simulated FX rates for the non-USD listings
+    let fx_updater_handle = tokio::spawn(async move {
+        stock_updater::start_fx_updater().await;
+    });
+
     // 9. Start the Kafka consumer (i receive stock prices from kafka)
     let stock_price_consumer_handle = tokio::spawn(async move {
         stock_price_consumer::run_consumer(price_tx.clone()).await;
     });
 
+    // Forward circuit breaker halts to the brokers
+    let halt_consumer_handle = tokio::spawn(async move {
+        stock_price_consumer::run_halt_consumer(halt_tx.clone()).await;
+    });
+
+    // Forward the matcher's quotes to the brokers' quote caches
+    let quote_consumer_handle = tokio::spawn(async move {
+        stock_price_consumer::run_quote_consumer(quote_tx).await;
+    });
+
+    // Record the trade tape and update the brokers' last prices from it
+    let trade_consumer_handle = tokio::spawn(async move {
+        stock_price_consumer::run_trade_consumer(trade_tx).await;
+    });
+
+    // Aggregate prices and trades into OHLCV bars for the strategies and the report
+    let bar_aggregator_handle = tokio::spawn(async move {
+        bars::run_bar_aggregator(bar_tx).await;
+    });
+
+    // Persist prices, quotes and trades to the tick store
+    let tick_recorder_handle = tokio::spawn(async move {
+        stock_price_consumer::run_tick_recorder().await;
+    });
+
+    // Keep FX rates for currency conversion on the trading side
+    let fx_consumer_handle = tokio::spawn(async move {
+        stock_price_consumer::run_fx_consumer().await;
+    });
+
+    // Apply corporate actions to client holdings
+    let corporate_action_consumer_handle = tokio::spawn(async move {
+        stock_price_consumer::run_corporate_action_consumer("src/data/client_holdings.json").await;
+    });
+
     //consumer_handle.await.unwrap();//过后用这个 不要order handle
     // 10. Start the order consumer (yikai side, reject or complete the orders and send to kafka)
     let order_matcher_handle = tokio::spawn(async move {
@@ -84,18 +136,55 @@ async fn main() {
     });
 
     // 11. Start the order processor (receive completed and rejected orders)
+    let receiver_blotters = blotters.clone();
+    let receiver_exec_tx = exec_tx.clone();
+    let receiver_borrow_desk = borrow_desk.clone();
     let order_status_receiver_handle = tokio::spawn(async move {
-        order_status_receiver::order_status_receiver().await;
+        order_status_receiver::order_status_receiver(receiver_blotters, receiver_borrow_desk, receiver_exec_tx).await;
+    });
+    
+    // FIX order-entry gateway for external clients, trading through their broker's order path
+    let fix_blotters = blotters.clone();
+    let fix_borrow_desk = borrow_desk.clone();
+    let fix_gateway_handle = tokio::spawn(async move {
+        fix::run_fix_gateway(fix_blotters, fix_borrow_desk, fix_price_rx).await;
+    });
+
+    // HTTP and WebSocket API for order entry, positions and prices
+    let api_blotters = blotters.clone();
+    let api_borrow_desk = borrow_desk.clone();
+    let api_handle = tokio::spawn(async move {
+        api::run_api_server(api_blotters, api_borrow_desk, api_price_tx, exec_tx).await;
     });
     
     stop_signal.store(true, Ordering::SeqCst);  
 
     // 12. Wait for all broker tasks to complete
-    let _ = tokio::join!(stock_updater_handle, stock_price_consumer_handle, order_matcher_handle, order_status_receiver_handle);
+    let _ = tokio::join!(stock_updater_handle, fx_updater_handle, stock_price_consumer_handle, fx_consumer_handle, halt_consumer_handle, quote_consumer_handle, trade_consumer_handle, bar_aggregator_handle, tick_recorder_handle, corporate_action_consumer_handle, order_matcher_handle, order_status_receiver_handle, fix_gateway_handle, api_handle);
     //broker.stop();
     
     // 13. Generate client performance report
     println!("MARKET CLOSED");
-    let json_file_path = "src/data/client_holdings.json";
-    performance::generate_client_report(json_file_path);
+    reconciliation::run_end_of_day_reconciliation(json_file_path);
+    // Orders still working at the close expire
+    for blotter in blotters.values() {
+        let mut blotter = blotter.lock().unwrap();
+        for client_id in blotter.client_ids() {
+            let open_orders = blotter.open_orders(client_id);
+            if !open_orders.is_empty() {
+                println!("Client {}: {} open orders at the close", client_id, open_orders.len());
+            }
+        }
+        let expired = blotter.expire_open_orders();
+        println!(
+            "Broker {}: {} orders expired, {} illegal transitions flagged",
+            blotter.broker_id(),
+            expired,
+            blotter.illegal_transitions().len()
+        );
+    }
+    // Charge the day's borrow fees on short positions
+    charge_borrow_fees_in_json(json_file_path, &borrow_desk.lock().unwrap());
+    performance::generate_client_report(json_file_path, session_start_ms);
+    performance::export_closed_lots(json_file_path, performance::CLOSED_LOTS_PATH);
 }
\ No newline at end of file
@@ -0,0 +1,186 @@
+// market_data.rs
+
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+use rdkafka::producer::{FutureProducer, FutureRecord};
+use crate::models::{DepthLevel, DepthUpdate, DepthUpdateKind, OrderAction, Quote};
+use crate::order_book::OrderBook;
+use crate::wire::{self, WireMessage};
+
+pub const QUOTE_TOPIC: &str = "quotes";
+pub const DEPTH_TOPIC: &str = "depth";
+// Levels per side carried in depth updates
+pub const DEPTH_LEVELS: usize = 10;
+// How often every book is republished in full for readers that joined late
+pub const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(5);
+
+// What was last published per symbol, so only changes go out between snapshots
+#[derive(Default)]
+struct PublishedBook {
+    quote: Option<Quote>,
+    bids: Vec<DepthLevel>,
+    asks: Vec<DepthLevel>,
+    sequence: u64,
+}
+
+#[derive(Default)]
+pub struct MarketDataPublisher {
+    published: HashMap<String, PublishedBook>,
+}
+
+impl MarketDataPublisher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Quotes and depth increments for every book that changed since the last call.
+    // A book that has gone (emptied by an auction) is published as empty.
+    pub fn changes(&mut self, books: &HashMap<String, OrderBook>) -> (Vec<Quote>, Vec<DepthUpdate>) {
+        let symbols: HashSet<String> = books.keys().chain(self.published.keys()).cloned().collect();
+        let mut quotes = Vec::new();
+        let mut updates = Vec::new();
+        for symbol in symbols {
+            let empty_book = OrderBook::new(&symbol);
+            let book = books.get(&symbol).unwrap_or(&empty_book);
+            let published = self.published.entry(symbol.clone()).or_default();
+
+            let quote = book.quote();
+            if published.quote.as_ref() != Some(&quote) {
+                published.quote = Some(quote.clone());
+                quotes.push(quote);
+            }
+
+            let bids = book.depth(OrderAction::Buy, DEPTH_LEVELS);
+            let asks = book.depth(OrderAction::Sell, DEPTH_LEVELS);
+            let changed_bids = changed_levels(&published.bids, &bids);
+            let changed_asks = changed_levels(&published.asks, &asks);
+            if changed_bids.is_empty() && changed_asks.is_empty() {
+                continue;
+            }
+            published.sequence += 1;
+            published.bids = bids;
+            published.asks = asks;
+            updates.push(DepthUpdate {
+                stock_symbol: symbol,
+                kind: DepthUpdateKind::Increment,
+                sequence: published.sequence,
+                bids: changed_bids,
+                asks: changed_asks,
+            });
+        }
+        (quotes, updates)
+    }
+
+    // Current quote and full depth for every symbol published so far. Snapshots keep the
+    // sequence of the last increment, which readers have then already accounted for.
+    pub fn snapshots(&mut self, books: &HashMap<String, OrderBook>) -> (Vec<Quote>, Vec<DepthUpdate>) {
+        // Anything not yet published goes out as increments first, so snapshots and increments agree
+        let (mut quotes, mut updates) = self.changes(books);
+        for (symbol, published) in &self.published {
+            if let Some(quote) = &published.quote {
+                quotes.push(quote.clone());
+            }
+            updates.push(DepthUpdate {
+                stock_symbol: symbol.clone(),
+                kind: DepthUpdateKind::Snapshot,
+                sequence: published.sequence,
+                bids: published.bids.clone(),
+                asks: published.asks.clone(),
+            });
+        }
+        (quotes, updates)
+    }
+}
+
+// Levels that are new or resized, plus removed levels with zero quantity
+fn changed_levels(old: &[DepthLevel], new: &[DepthLevel]) -> Vec<DepthLevel> {
+    let mut changed: Vec<DepthLevel> = new.iter().filter(|level| !old.contains(level)).cloned().collect();
+    for level in old.iter().filter(|level| !new.iter().any(|n| n.price == level.price)) {
+        changed.push(DepthLevel {
+            price: level.price,
+            quantity: 0,
+            orders: 0,
+        });
+    }
+    changed
+}
+
+// Market data is informational and goes out without a transaction, keyed by symbol so each
+// symbol's updates stay in order on one partition
+pub async fn publish(producer: &FutureProducer, quotes: &[Quote], updates: &[DepthUpdate]) {
+    for quote in quotes {
+        send(producer, QUOTE_TOPIC, &quote.stock_symbol, quote).await;
+    }
+    for update in updates {
+        send(producer, DEPTH_TOPIC, &update.stock_symbol, update).await;
+    }
+}
+
+async fn send<T: WireMessage>(producer: &FutureProducer, topic: &str, key: &str, value: &T) {
+    let payload = wire::encode(value);
+    if let Err((err, _)) = producer
+        .send(FutureRecord::to(topic).key(key).payload(&payload), rdkafka::util::Timeout::Never)
+        .await
+    {
+        println!("Failed to send market data to {}: {}", topic, err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::test_order;
+    use crate::money::Money;
+
+    fn level(price: f64, quantity: u64, orders: usize) -> DepthLevel {
+        DepthLevel { price: Money::from_f64(price), quantity, orders }
+    }
+
+    #[test]
+    fn changed_levels_covers_new_resized_and_removed() {
+        let old = vec![level(61.0, 50, 1), level(60.0, 100, 2), level(59.0, 10, 1)];
+        let new = vec![level(61.0, 50, 1), level(60.0, 80, 1), level(58.0, 5, 1)];
+        assert_eq!(
+            changed_levels(&old, &new),
+            vec![level(60.0, 80, 1), level(58.0, 5, 1), level(59.0, 0, 0)]
+        );
+        assert!(changed_levels(&new, &new).is_empty());
+    }
+
+    #[test]
+    fn changes_publish_only_what_moved() {
+        let mut publisher = MarketDataPublisher::new();
+        let mut books = HashMap::new();
+        let mut book = OrderBook::new("KO");
+        book.add_order(test_order("a", OrderAction::Buy, 60.0, 100));
+        books.insert("KO".to_string(), book);
+
+        let (quotes, updates) = publisher.changes(&books);
+        assert_eq!(quotes.len(), 1);
+        assert_eq!((updates[0].sequence, updates[0].bids.clone()), (1, vec![level(60.0, 100, 1)]));
+
+        // Nothing changed, nothing published
+        let (quotes, updates) = publisher.changes(&books);
+        assert!(quotes.is_empty() && updates.is_empty());
+
+        // An emptied book removes its level and quotes an empty bid
+        books.clear();
+        let (quotes, updates) = publisher.changes(&books);
+        assert_eq!((quotes[0].bid, quotes[0].bid_size), (None, 0));
+        assert_eq!((updates[0].sequence, updates[0].bids.clone()), (2, vec![level(60.0, 0, 0)]));
+    }
+
+    #[test]
+    fn snapshots_carry_full_depth_at_the_last_sequence() {
+        let mut publisher = MarketDataPublisher::new();
+        let mut book = OrderBook::new("KO");
+        book.add_order(test_order("a", OrderAction::Buy, 60.0, 100));
+        book.add_order(test_order("b", OrderAction::Buy, 59.5, 20));
+        let books = HashMap::from([("KO".to_string(), book)]);
+
+        let (_, updates) = publisher.snapshots(&books);
+        let snapshot = updates.iter().find(|update| update.kind == DepthUpdateKind::Snapshot).unwrap();
+        assert_eq!(snapshot.sequence, 1);
+        assert_eq!(snapshot.bids, vec![level(60.0, 100, 1), level(59.5, 20, 1)]);
+    }
+}
@@ -1,11 +1,26 @@
 // // models.rs
 
 use serde::{Serialize, Deserialize};
+use crate::money::Money;
+use uuid::Uuid;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct PriceUpdate {
     pub name: String,
     pub price: f64,
+    #[serde(default = "default_currency")]
+    pub currency: String, // Listing currency of the instrument
+}
+
+fn default_currency() -> String {
+    crate::currency::HOME_CURRENCY.to_string()
+}
+
+// Published on the fx topic: USD value of one unit of `currency`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FxRate {
+    pub currency: String,
+    pub usd_rate: f64,
 }
 
 #[derive(Serialize, Debug, Deserialize, Clone)]
@@ -28,6 +43,28 @@ pub enum OrderStatus {
     Rejected,
 }
 
+#[derive(Serialize, Debug, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RejectReason {
+    InsufficientFunds,
+    UnknownSymbol,
+    PriceOutOfBand,
+    Halted,
+    DuplicateId,
+    InvalidQuantity,
+    MarketClosed,
+    RiskLimit,
+    LocateUnavailable,
+    Unknown, // A reject that arrived without a reason
+}
+
+// Whether a fill added or removed liquidity, used for exchange fees
+#[derive(Serialize, Debug, Deserialize, Clone, Copy, PartialEq)]
+pub enum Liquidity {
+    Maker,
+    Taker,
+    Auction,
+}
+
 #[derive(Serialize, Debug, Deserialize, Clone)]
 pub struct Order {
     pub broker_id: u64,
@@ -36,7 +73,196 @@ pub struct Order {
     pub stock_symbol: String,
     pub order_type: OrderType,
     pub order_action: OrderAction,
-    pub price: f64,
+    pub price: Money,
     pub quantity: u64,
     pub status: OrderStatus,
-}
\ No newline at end of file
+    #[serde(default)]
+    pub reject_reason: Option<RejectReason>, // Set by the matcher on rejected orders
+    #[serde(default)]
+    pub reject_text: Option<String>, // Free-text detail for the reject reason
+    #[serde(default)]
+    pub liquidity: Option<Liquidity>, // Set by the matcher on completed orders
+    #[serde(default)]
+    pub exec_id: Option<String>, // Unique per fill, assigned by the matcher
+    #[serde(default)]
+    pub liquidation: bool, // Sent by a broker's margin monitor to close out an account
+    #[serde(default)]
+    pub lot_ids: Vec<String>, // Lots a closing fill relieves, for clients on specific identification
+}
+
+// Client order IDs and execution IDs are random UUIDs so they never repeat across runs
+pub fn new_unique_id() -> String {
+    Uuid::new_v4().to_string()
+}
+
+// A pending KO limit order from client 1 of broker 1, for tests; override fields with struct update syntax
+#[cfg(test)]
+pub fn test_order(order_id: &str, order_action: OrderAction, price: f64, quantity: u64) -> Order {
+    Order {
+        broker_id: 1,
+        client_id: 1,
+        order_id: order_id.to_string(),
+        stock_symbol: "KO".to_string(),
+        order_type: OrderType::Limit,
+        order_action,
+        price: Money::from_f64(price),
+        quantity,
+        status: OrderStatus::Pending,
+        reject_reason: None,
+        reject_text: None,
+        liquidity: None,
+        exec_id: None,
+        liquidation: false,
+        lot_ids: Vec::new(),
+    }
+}
+
+#[derive(Serialize, Debug, Deserialize, Clone, Copy, PartialEq)]
+pub enum MarketPhase {
+    OpeningCall,
+    Continuous,
+    ClosingCall,
+    Closed,
+}
+
+// Published on the auction topic while orders accumulate in a call phase
+#[derive(Serialize, Debug, Deserialize, Clone)]
+pub struct AuctionIndicative {
+    pub stock_symbol: String,
+    pub phase: MarketPhase,
+    pub indicative_price: Option<f64>,
+    pub matched_volume: u64,
+    pub imbalance: u64,
+    pub imbalance_side: Option<OrderAction>,
+}
+
+// Published on the quotes topic: best bid and ask resting in the matcher's book.
+// A side with no resting limit orders has no price and zero size.
+#[derive(Serialize, Debug, Deserialize, Clone, PartialEq)]
+pub struct Quote {
+    pub stock_symbol: String,
+    pub bid: Option<Money>,
+    pub bid_size: u64,
+    pub ask: Option<Money>,
+    pub ask_size: u64,
+    #[serde(default)]
+    pub crossed: bool, // Bid above ask: orders collected for an auction, or resting away from the last price
+}
+
+// Resting limit orders at one price
+#[derive(Serialize, Debug, Deserialize, Clone, PartialEq)]
+pub struct DepthLevel {
+    pub price: Money,
+    pub quantity: u64, // Zero in an increment removes the level
+    pub orders: usize,
+}
+
+#[derive(Serialize, Debug, Deserialize, Clone, Copy, PartialEq)]
+pub enum DepthUpdateKind {
+    Snapshot,  // Every level; replaces whatever the reader holds
+    Increment, // Only the levels that changed since the previous update
+}
+
+// Published on the depth topic. Sequence numbers are per symbol: a reader that starts from a
+// snapshot applies only increments with a higher sequence.
+#[derive(Serialize, Debug, Deserialize, Clone)]
+pub struct DepthUpdate {
+    pub stock_symbol: String,
+    pub kind: DepthUpdateKind,
+    pub sequence: u64,
+    pub bids: Vec<DepthLevel>, // Best first
+    pub asks: Vec<DepthLevel>, // Best first
+}
+
+// Published on the trades topic for every execution: the public, anonymised record of a fill.
+// Auction uncrosses have no aggressor.
+#[derive(Serialize, Debug, Deserialize, Clone)]
+pub struct Trade {
+    pub trade_id: String,
+    pub stock_symbol: String,
+    pub price: f64,
+    pub quantity: u64,
+    pub aggressor: Option<OrderAction>,
+    pub timestamp_ms: i64, // Milliseconds since the Unix epoch
+}
+
+// Published on the bars topic when an OHLCV bar closes. Ticks from the price feed move the
+// prices only; volume and trade count come from the trade tape.
+#[derive(Serialize, Debug, Deserialize, Clone)]
+pub struct Bar {
+    pub stock_symbol: String,
+    pub interval_secs: u64,
+    pub start_ms: i64, // Milliseconds since the Unix epoch, aligned to the interval
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    pub volume: u64,
+    pub trades: u64,
+}
+
+// Official open/close prices set by the opening and closing auctions
+#[derive(Serialize, Debug, Deserialize, Clone, Default)]
+pub struct OfficialPrice {
+    pub open: Option<f64>,
+    pub close: Option<f64>,
+}
+
+#[derive(Serialize, Debug, Deserialize, Clone, PartialEq)]
+pub enum TradingStatus {
+    Halted,
+    Resumed,
+}
+
+// Published on the order_acks topic for every order the matcher receives
+#[derive(Serialize, Debug, Deserialize, Clone)]
+pub struct OrderAck {
+    pub broker_id: u64,
+    pub client_id: u64,
+    pub order_id: String,
+    pub accepted: bool, // False for a nack
+    #[serde(default)]
+    pub exchange_order_id: Option<String>, // Assigned by the matcher on acceptance
+    #[serde(default)]
+    pub reject_reason: Option<RejectReason>,
+    #[serde(default)]
+    pub reject_text: Option<String>,
+}
+
+// Published on the cancel_acks topic for every cancel request the matcher receives.
+// Only orders still resting in the book can be cancelled.
+#[derive(Serialize, Debug, Deserialize, Clone)]
+pub struct CancelAck {
+    pub broker_id: u64,
+    pub client_id: u64,
+    pub order_id: String, // The order being cancelled
+    pub cancelled: bool, // False when the order was not resting (filled, rejected or unknown)
+    #[serde(default)]
+    pub cancelled_quantity: u64,
+    #[serde(default)]
+    pub reject_text: Option<String>,
+}
+
+// Published on the halts topic when a circuit breaker trips or trading resumes
+#[derive(Serialize, Debug, Deserialize, Clone)]
+pub struct HaltEvent {
+    pub stock_symbol: String,
+    pub status: TradingStatus,
+    pub reason: String,
+}
+
+#[derive(Serialize, Debug, Deserialize, Clone, PartialEq)]
+pub enum CorporateActionKind {
+    Split { ratio: f64 },          // New shares per old share, e.g. 2.0 for a 2-for-1
+    CashDividend { amount: f64 },  // Cash per share
+}
+
+// Scheduled in the corporate actions feed and published on the ex-date
+#[derive(Serialize, Debug, Deserialize, Clone)]
+pub struct CorporateAction {
+    pub stock_symbol: String,
+    pub kind: CorporateActionKind,
+    pub ex_time_secs: u64, // Simulated time: seconds after the session starts
+    #[serde(default)]
+    pub adjusted_price: Option<f64>, // Price after adjustment, filled in when published
+}
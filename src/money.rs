@@ -0,0 +1,240 @@
+// money.rs
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::ops::{Add, AddAssign, Sub, SubAssign};
+use crate::currency::instrument_currency;
+
+// Number of fixed-point units in one whole currency unit (six decimal places)
+const SCALE: i64 = 1_000_000;
+
+// Fixed-point monetary amount. Serialised as a plain JSON number so existing
+// data files and Kafka messages keep their format.
+//
+// Money is used wherever an amount is booked or settled: order and fill prices, quotes and depth,
+// cash, fees, cost basis and realised gains. Market data that only drives decisions stays f64:
+// the price feed (PriceUpdate), trades, bars, indicators and the matcher's reference prices.
+// Those cross into Money through round_price or from_f64 when an order is priced from them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Money(i64);
+
+// How to round onto a price or cash increment
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rounding {
+    Nearest, // Half away from zero
+    Down,    // Toward negative infinity
+    Up,      // Toward positive infinity
+}
+
+pub const CENT: Money = Money(SCALE / 100);
+
+impl Money {
+    pub const ZERO: Money = Money(0);
+
+    pub fn from_f64(amount: f64) -> Self {
+        Money((amount * SCALE as f64).round() as i64)
+    }
+
+    pub fn to_f64(self) -> f64 {
+        self.0 as f64 / SCALE as f64
+    }
+
+    // Amount for a number of shares at this price
+    pub fn times(self, quantity: i64) -> Self {
+        Money(self.0 * quantity)
+    }
+
+    // Per-share amount for a total spread over a number of shares
+    pub fn per_share(self, quantity: i64) -> Self {
+        Money(divide(self.0 as i128, quantity as i128, Rounding::Nearest) as i64)
+    }
+
+    pub fn round_to(self, increment: Money, rounding: Rounding) -> Self {
+        if increment.0 <= 0 {
+            return self;
+        }
+        Money(divide(self.0 as i128, increment.0 as i128, rounding) as i64 * increment.0)
+    }
+
+    // Cash amounts settle in whole cents
+    pub fn round_cents(self) -> Self {
+        self.round_to(CENT, Rounding::Nearest)
+    }
+
+    // Amount times a factor such as a fraction of a share, the factor taken to six decimal places
+    pub fn scale(self, factor: f64) -> Self {
+        Money(divide(self.0 as i128 * fixed_factor(factor), SCALE as i128, Rounding::Nearest) as i64)
+    }
+
+    // Price per new share after a split giving `ratio` new shares for each old one
+    pub fn split_adjusted(self, ratio: f64) -> Self {
+        Money(divide(self.0 as i128 * SCALE as i128, fixed_factor(ratio), Rounding::Nearest) as i64)
+    }
+}
+
+// A split ratio split_adjusted can divide by: positive and still non-zero at six decimal places
+pub fn is_valid_split_ratio(ratio: f64) -> bool {
+    ratio.is_finite() && fixed_factor(ratio) > 0
+}
+
+fn fixed_factor(factor: f64) -> i128 {
+    (factor * SCALE as f64).round() as i128
+}
+
+// Integer division with an explicit rounding rule
+fn divide(numerator: i128, denominator: i128, rounding: Rounding) -> i128 {
+    let (numerator, denominator) = if denominator < 0 { (-numerator, -denominator) } else { (numerator, denominator) };
+    let floor = numerator.div_euclid(denominator);
+    let remainder = numerator.rem_euclid(denominator);
+    match rounding {
+        Rounding::Down => floor,
+        Rounding::Up if remainder > 0 => floor + 1,
+        Rounding::Up => floor,
+        Rounding::Nearest => {
+            let twice = remainder * 2;
+            if twice > denominator || (twice == denominator && numerator > 0) {
+                floor + 1
+            } else {
+                floor
+            }
+        }
+    }
+}
+
+// Minimum price increment for a symbol at a given price level
+pub fn tick_size(stock_symbol: &str, price: f64) -> Money {
+    if price < 1.0 {
+        return Money(SCALE / 10_000); // Sub-dollar quotes trade in 0.0001
+    }
+    match instrument_currency(stock_symbol) {
+        "GBP" => Money(SCALE / 2_000), // Half-penny ticks on London listings
+        "EUR" => Money(SCALE / 1_000),
+        _ => CENT,
+    }
+}
+
+// Snap a raw price onto the symbol's tick grid
+pub fn round_price(stock_symbol: &str, price: f64, rounding: Rounding) -> Money {
+    Money::from_f64(price).round_to(tick_size(stock_symbol, price), rounding)
+}
+
+impl Add for Money {
+    type Output = Money;
+    fn add(self, other: Money) -> Money {
+        Money(self.0 + other.0)
+    }
+}
+
+impl Sub for Money {
+    type Output = Money;
+    fn sub(self, other: Money) -> Money {
+        Money(self.0 - other.0)
+    }
+}
+
+impl AddAssign for Money {
+    fn add_assign(&mut self, other: Money) {
+        self.0 += other.0;
+    }
+}
+
+impl SubAssign for Money {
+    fn sub_assign(&mut self, other: Money) {
+        self.0 -= other.0;
+    }
+}
+
+// Formats like an f64 so `{:.2}` keeps working in log lines
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.to_f64(), f)
+    }
+}
+
+impl Serialize for Money {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f64(self.to_f64())
+    }
+}
+
+impl<'de> Deserialize<'de> for Money {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        f64::deserialize(deserializer).map(Money::from_f64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::CorporateActionKind;
+    use crate::corporate_actions::adjust_price;
+
+    fn units(amount: i64) -> Money {
+        Money(amount)
+    }
+
+    #[test]
+    fn rounding_modes_handle_negative_amounts() {
+        let increment = units(10);
+        assert_eq!(units(15).round_to(increment, Rounding::Nearest), units(20));
+        assert_eq!(units(-15).round_to(increment, Rounding::Nearest), units(-20));
+        assert_eq!(units(-14).round_to(increment, Rounding::Nearest), units(-10));
+        assert_eq!(units(-11).round_to(increment, Rounding::Down), units(-20));
+        assert_eq!(units(-19).round_to(increment, Rounding::Up), units(-10));
+        assert_eq!(units(11).round_to(increment, Rounding::Down), units(10));
+        assert_eq!(units(11).round_to(increment, Rounding::Up), units(20));
+        assert_eq!(units(-20).round_to(increment, Rounding::Up), units(-20));
+    }
+
+    #[test]
+    fn per_share_rounds_to_nearest_unit() {
+        assert_eq!(Money::from_f64(10.0).per_share(3), units(3_333_333));
+        assert_eq!(Money::from_f64(20.0).per_share(3), units(6_666_667));
+        assert_eq!(Money::from_f64(-20.0).per_share(3), units(-6_666_667));
+        assert_eq!(Money::from_f64(1.0).per_share(-4), Money::from_f64(-0.25));
+    }
+
+    #[test]
+    fn round_cents_goes_half_away_from_zero() {
+        assert_eq!(Money::from_f64(1.005).round_cents(), Money::from_f64(1.01));
+        assert_eq!(Money::from_f64(-1.005).round_cents(), Money::from_f64(-1.01));
+        assert_eq!(Money::from_f64(1.004999).round_cents(), Money::from_f64(1.0));
+    }
+
+    #[test]
+    fn round_price_uses_the_symbols_tick() {
+        assert_eq!(round_price("AAPL", 187.256, Rounding::Nearest), Money::from_f64(187.26));
+        assert_eq!(round_price("AAPL", 187.251, Rounding::Up), Money::from_f64(187.26));
+        assert_eq!(round_price("BP", 4.8127, Rounding::Nearest), Money::from_f64(4.8125));
+        assert_eq!(round_price("BP", 4.8124, Rounding::Down), Money::from_f64(4.812));
+        assert_eq!(round_price("TOT", 55.1234, Rounding::Nearest), Money::from_f64(55.123));
+        assert_eq!(round_price("AAPL", 0.123456, Rounding::Nearest), Money::from_f64(0.1235));
+    }
+
+    #[test]
+    fn split_adjusted_price_is_exact() {
+        assert_eq!(Money::from_f64(300.0).split_adjusted(3.0), Money::from_f64(100.0));
+        assert_eq!(Money::from_f64(100.0).split_adjusted(3.0), units(33_333_333));
+        assert_eq!(Money::from_f64(10.0).split_adjusted(0.5), Money::from_f64(20.0));
+        assert_eq!(Money::from_f64(150.0).split_adjusted(1.5), Money::from_f64(100.0));
+        let kind = CorporateActionKind::Split { ratio: 3.0 };
+        assert_eq!(adjust_price("AAPL", Money::from_f64(100.0), &kind), Money::from_f64(33.33));
+    }
+
+    #[test]
+    fn scale_takes_a_fraction_of_an_amount() {
+        assert_eq!(Money::from_f64(33.33).scale(0.5), units(16_665_000));
+        assert_eq!(Money::from_f64(33.33).scale(0.5).round_cents(), Money::from_f64(16.67));
+        assert_eq!(Money::from_f64(-2.0).scale(0.25), Money::from_f64(-0.5));
+    }
+
+    #[test]
+    fn serializes_as_a_plain_number() {
+        let price = Money::from_f64(187.25);
+        assert_eq!(serde_json::to_string(&price).unwrap(), "187.25");
+        let decoded: Money = serde_json::from_str("187.25").unwrap();
+        assert_eq!(decoded, price);
+        let decoded: Money = serde_json::from_str("0.1").unwrap();
+        assert_eq!(decoded, units(100_000));
+    }
+}
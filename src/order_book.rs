@@ -0,0 +1,182 @@
+// order_book.rs
+
+use crate::corporate_actions::{adjust_price, split_quantity};
+use crate::models::{CorporateActionKind, DepthLevel, Order, OrderAction, OrderType, Quote};
+
+// Resting orders for a single symbol, kept in price-time priority
+#[derive(Clone)]
+pub struct OrderBook {
+    pub symbol: String,
+    pub bids: Vec<Order>, // Best (highest) bid first
+    pub asks: Vec<Order>, // Best (lowest) ask first
+}
+
+impl OrderBook {
+    pub fn new(symbol: &str) -> Self {
+        Self {
+            symbol: symbol.to_string(),
+            bids: Vec::new(),
+            asks: Vec::new(),
+        }
+    }
+
+    pub fn add_order(&mut self, order: Order) {
+        let limit = limit_price(&order);
+        match order.order_action {
+            OrderAction::Buy => {
+                // Insert behind every bid at the same or a better price (time priority)
+                let position = self.bids.iter().position(|o| limit_price(o) < limit).unwrap_or(self.bids.len());
+                self.bids.insert(position, order);
+            }
+            OrderAction::Sell => {
+                let position = self.asks.iter().position(|o| limit_price(o) > limit).unwrap_or(self.asks.len());
+                self.asks.insert(position, order);
+            }
+            OrderAction::Cancel => {
+                self.cancel(&order.order_id);
+            }
+        }
+    }
+
+    pub fn resting_order(&self, order_id: &str) -> Option<&Order> {
+        self.bids.iter().chain(self.asks.iter()).find(|o| o.order_id == order_id)
+    }
+
+    // Take a resting order out of the book, returning it if it was there
+    pub fn cancel(&mut self, order_id: &str) -> Option<Order> {
+        for side in [&mut self.bids, &mut self.asks] {
+            if let Some(position) = side.iter().position(|o| o.order_id == order_id) {
+                return Some(side.remove(position));
+            }
+        }
+        None
+    }
+
+    // Adjust resting orders on the ex-date. Splits rescale price and quantity on both sides;
+    // dividends reduce buy limits by the dividend amount.
+    pub fn apply_corporate_action(&mut self, kind: &CorporateActionKind) {
+        match kind {
+            CorporateActionKind::Split { ratio } => {
+                for order in self.bids.iter_mut().chain(self.asks.iter_mut()) {
+                    order.price = adjust_price(&self.symbol, order.price, kind);
+                    order.quantity = split_quantity(order.quantity as i64, *ratio).0 as u64;
+                }
+            }
+            CorporateActionKind::CashDividend { .. } => {
+                for order in self.bids.iter_mut() {
+                    order.price = adjust_price(&self.symbol, order.price, kind);
+                }
+            }
+        }
+        self.bids.retain(|o| o.quantity > 0);
+        self.asks.retain(|o| o.quantity > 0);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bids.is_empty() && self.asks.is_empty()
+    }
+
+    // Aggregated price levels for one side, best first. Market orders have no price and are left out.
+    pub fn depth(&self, side: OrderAction, max_levels: usize) -> Vec<DepthLevel> {
+        let orders = if side == OrderAction::Buy { &self.bids } else { &self.asks };
+        let mut levels: Vec<DepthLevel> = Vec::new();
+        for order in orders.iter().filter(|o| matches!(o.order_type, OrderType::Limit)) {
+            let price = order.price;
+            if let Some(level) = levels.last_mut().filter(|level| level.price == price) {
+                level.quantity += order.quantity;
+                level.orders += 1;
+            } else if levels.len() == max_levels {
+                break;
+            } else {
+                levels.push(DepthLevel {
+                    price,
+                    quantity: order.quantity,
+                    orders: 1,
+                });
+            }
+        }
+        levels
+    }
+
+    pub fn quote(&self) -> Quote {
+        let best_bid = self.depth(OrderAction::Buy, 1).pop();
+        let best_ask = self.depth(OrderAction::Sell, 1).pop();
+        let bid = best_bid.as_ref().map(|level| level.price);
+        let ask = best_ask.as_ref().map(|level| level.price);
+        Quote {
+            stock_symbol: self.symbol.clone(),
+            bid,
+            bid_size: best_bid.map_or(0, |level| level.quantity),
+            ask,
+            ask_size: best_ask.map_or(0, |level| level.quantity),
+            crossed: matches!((bid, ask), (Some(bid), Some(ask)) if bid > ask),
+        }
+    }
+}
+
+// Market orders are treated as willing to trade at any price
+pub fn limit_price(order: &Order) -> f64 {
+    match (&order.order_type, &order.order_action) {
+        (OrderType::Market, OrderAction::Buy) => f64::INFINITY,
+        (OrderType::Market, _) => 0.0,
+        (OrderType::Limit, _) => order.price.to_f64(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::test_order;
+    use crate::money::Money;
+
+    fn order(order_id: &str, action: OrderAction, order_type: OrderType, price: f64, quantity: u64) -> Order {
+        Order { order_type, ..test_order(order_id, action, price, quantity) }
+    }
+
+    fn level(price: f64, quantity: u64, orders: usize) -> DepthLevel {
+        DepthLevel { price: Money::from_f64(price), quantity, orders }
+    }
+
+    #[test]
+    fn depth_aggregates_levels_best_first() {
+        let mut book = OrderBook::new("KO");
+        book.add_order(order("a", OrderAction::Buy, OrderType::Limit, 60.0, 100));
+        book.add_order(order("b", OrderAction::Buy, OrderType::Limit, 61.0, 50));
+        book.add_order(order("c", OrderAction::Buy, OrderType::Limit, 60.0, 25));
+        book.add_order(order("d", OrderAction::Buy, OrderType::Market, 0.0, 10));
+        book.add_order(order("e", OrderAction::Sell, OrderType::Limit, 62.5, 40));
+        book.add_order(order("f", OrderAction::Sell, OrderType::Limit, 62.0, 30));
+
+        // The market order has no price and is left out
+        assert_eq!(book.depth(OrderAction::Buy, 10), vec![level(61.0, 50, 1), level(60.0, 125, 2)]);
+        assert_eq!(book.depth(OrderAction::Sell, 10), vec![level(62.0, 30, 1), level(62.5, 40, 1)]);
+        assert_eq!(book.depth(OrderAction::Buy, 1), vec![level(61.0, 50, 1)]);
+
+        let quote = book.quote();
+        assert_eq!((quote.bid, quote.bid_size), (Some(Money::from_f64(61.0)), 50));
+        assert_eq!((quote.ask, quote.ask_size), (Some(Money::from_f64(62.0)), 30));
+        assert!(!quote.crossed);
+    }
+
+    #[test]
+    fn quote_flags_a_crossed_book() {
+        let mut book = OrderBook::new("KO");
+        book.add_order(order("a", OrderAction::Buy, OrderType::Limit, 63.0, 100));
+        book.add_order(order("b", OrderAction::Sell, OrderType::Limit, 62.0, 100));
+        assert!(book.quote().crossed);
+
+        // Locked is not crossed
+        book.cancel("a");
+        book.add_order(order("c", OrderAction::Buy, OrderType::Limit, 62.0, 100));
+        assert!(!book.quote().crossed);
+    }
+
+    #[test]
+    fn empty_side_has_no_price_or_size() {
+        let mut book = OrderBook::new("KO");
+        book.add_order(order("a", OrderAction::Sell, OrderType::Limit, 62.0, 30));
+        let quote = book.quote();
+        assert_eq!((quote.bid, quote.bid_size), (None, 0));
+        assert!(!quote.crossed);
+    }
+}
@@ -1,14 +1,40 @@
-use std::time::Duration;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use rand::rngs::StdRng;
 use rdkafka::config::ClientConfig;
-use rdkafka::consumer::{CommitMode, Consumer, StreamConsumer};
-use rdkafka::message::Message;
-use rdkafka::producer::{FutureProducer, FutureRecord};
+use rdkafka::consumer::{Consumer, ConsumerGroupMetadata, StreamConsumer};
+use rdkafka::error::KafkaResult;
+use rdkafka::message::{BorrowedMessage, Message, OwnedHeaders};
+use rdkafka::producer::{FutureProducer, FutureRecord, Producer};
+use rdkafka::{Offset, TopicPartitionList};
 use tokio::time::timeout;
-use crate::models::{Order, OrderStatus};
+use crate::execution_model::{ExecutionModel, EXECUTION_MODEL_PATH};
+use crate::auction::{market_phase, record_official_price, reset_official_prices, uncross, find_uncross_point};
+use crate::models::{new_unique_id, AuctionIndicative, CancelAck, CorporateAction, HaltEvent, Liquidity, MarketPhase, Order, OrderAck, OrderAction, OrderStatus, OrderType, PriceUpdate, RejectReason, TradingStatus};
+use crate::stock_updater::LISTED_SYMBOLS;
+use crate::order_book::OrderBook;
+use crate::market_data::{self, MarketDataPublisher, SNAPSHOT_INTERVAL};
+use crate::money::{round_price, Rounding};
+use crate::dedup::RecentIds;
+use crate::dead_letter::DeadLetter;
+use crate::wire::{self, WireMessage};
+use crate::reconciliation::{append_fill, reset_fill_journal, EXCHANGE_FILLS_PATH};
+use crate::trade_tape::{auction_trade, trade_from_fill, TRADE_TOPIC};
 use rand::{Rng, SeedableRng};
 
+// Limit prices further than this from the last price are rejected
+const PRICE_BAND_PERCENT: f64 = 20.0;
+// Largest notional a single order may carry
+const MAX_ORDER_NOTIONAL: f64 = 5_000.0;
+// How many recent order IDs are remembered for duplicate detection
+const SEEN_ORDER_ID_CAPACITY: usize = 100_000;
+// Fixed so a restarted matcher fences off any zombie instance and resolves its open transaction
+const MATCHER_TRANSACTIONAL_ID: &str = "order-matcher";
+const TRANSACTION_TIMEOUT: Duration = Duration::from_secs(10);
+const AUCTION_PUBLISH_ATTEMPTS: u32 = 3;
+
 //yikai side
 pub async fn consume_and_route_orders() {
     let brokers = "localhost:9092"; // Kafka brokers
@@ -17,6 +43,9 @@ pub async fn consume_and_route_orders() {
     // Topics for processed orders
     let completed_topic = "completed_order";
     let rejected_topic = "rejected_order";
+    let auction_topic = "auction";
+    let ack_topic = "order_acks";
+    let cancel_ack_topic = "cancel_acks";
 
     // Create Kafka consumer
     let consumer: StreamConsumer = ClientConfig::new()
@@ -25,71 +54,295 @@ pub async fn consume_and_route_orders() {
         //.set("enable.partition.eof", "false")
         //.set("session.timeout.ms", "6000")
         .set("enable.auto.commit", "false")
+        .set("isolation.level", "read_committed")
         .create()
         .expect("Failed to create Kafka consumer");
 
-    // Create Kafka producer
+    // Transactional producer for everything derived from an inbound order: acks, fills and rejects
     let producer: FutureProducer = ClientConfig::new()
         .set("bootstrap.servers", brokers)
+        .set("transactional.id", MATCHER_TRANSACTIONAL_ID)
         .create()
         .expect("Failed to create Kafka producer");
+    blocking_transaction_call(&producer, |producer| producer.init_transactions(TRANSACTION_TIMEOUT))
+        .await
+        .expect("Failed to initialise Kafka transactions");
+
+    // Auction indicatives and market data are informational and go out without a transaction
+    let market_data_producer: FutureProducer = ClientConfig::new()
+        .set("bootstrap.servers", brokers)
+        .create()
+        .expect("Failed to create Kafka market data producer");
+
+    // Reference prices for the auctions come from the stock price feed
+    let price_consumer: StreamConsumer = ClientConfig::new()
+        .set("bootstrap.servers", brokers)
+        .set("group.id", "order-matcher-price-group")
+        .set("auto.offset.reset", "latest")
+        .set("enable.auto.commit", "true")
+        .create()
+        .expect("Failed to create Kafka price consumer");
+
+    // Circuit breaker halts and resumes from the stock side
+    let halt_consumer: StreamConsumer = ClientConfig::new()
+        .set("bootstrap.servers", brokers)
+        .set("group.id", "order-matcher-halt-group")
+        .set("auto.offset.reset", "latest")
+        .set("enable.auto.commit", "true")
+        .create()
+        .expect("Failed to create Kafka halt consumer");
+
+    // Corporate actions adjust the resting orders on the ex-date
+    let corporate_action_consumer: StreamConsumer = ClientConfig::new()
+        .set("bootstrap.servers", brokers)
+        .set("group.id", "order-matcher-corporate-action-group")
+        .set("auto.offset.reset", "latest")
+        .set("enable.auto.commit", "true")
+        .create()
+        .expect("Failed to create Kafka corporate action consumer");
 
     // Subscribe to the topic
     consumer.subscribe(&topics).expect("Failed to subscribe to topics");
+    // Consumed offsets are committed through the producer's transactions
+    let group_metadata = Arc::new(consumer.group_metadata().expect("Order consumer has no group metadata"));
+    corporate_action_consumer.subscribe(&["corporate_actions"]).expect("Failed to subscribe to corporate actions topic");
+    price_consumer.subscribe(&["stock"]).expect("Failed to subscribe to stock topic");
+    halt_consumer.subscribe(&["halts"]).expect("Failed to subscribe to halts topic");
 
     //println!("Order consumer started, waiting for messages...");
 
     let mut rng = StdRng::from_entropy();
+    let execution_model = ExecutionModel::load(EXECUTION_MODEL_PATH);
+
+    // Resting orders per symbol: everything entered during the calls plus pending continuous orders
+    let mut books: HashMap<String, OrderBook> = HashMap::new();
+    let mut reference_prices: HashMap<String, f64> = HashMap::new();
+    let mut halted_symbols: HashSet<String> = HashSet::new();
+    let mut seen_order_ids = RecentIds::new(SEEN_ORDER_ID_CAPACITY);
+    let mut next_exchange_order_id: u64 = 1;
+
+    reset_official_prices();
+    reset_fill_journal(EXCHANGE_FILLS_PATH);
+    let session_start = Instant::now();
+    let mut phase = MarketPhase::OpeningCall;
+    let mut phase_ticker = tokio::time::interval(Duration::from_secs(1));
+    // Quotes and depth for the resting books
+    let mut market_data_publisher = MarketDataPublisher::new();
+    let mut snapshot_ticker = tokio::time::interval(SNAPSHOT_INTERVAL);
 
     let result = timeout(Duration::from_secs(50), async {
     loop {
-        match consumer.recv().await {
+        tokio::select! {
+        _ = phase_ticker.tick() => {
+            let current_phase = market_phase(session_start.elapsed());
+            if current_phase != phase {
+                // Leaving a call phase uncrosses every book at a single price
+                if matches!(phase, MarketPhase::OpeningCall | MarketPhase::ClosingCall) {
+                    run_auction(&producer, &mut books, &reference_prices, phase, completed_topic).await;
+                    let (quotes, updates) = market_data_publisher.changes(&books);
+                    market_data::publish(&market_data_producer, &quotes, &updates).await;
+                }
+                println!("Market phase: {:?}", current_phase);
+                phase = current_phase;
+            } else if matches!(phase, MarketPhase::OpeningCall | MarketPhase::ClosingCall) {
+                publish_indicatives(&market_data_producer, &books, &reference_prices, phase, auction_topic).await;
+            }
+        }
+        _ = snapshot_ticker.tick() => {
+            let (quotes, updates) = market_data_publisher.snapshots(&books);
+            market_data::publish(&market_data_producer, &quotes, &updates).await;
+        }
+        message = price_consumer.recv() => {
+            if let Ok(m) = message {
+                if let Some(Ok(price_update)) = m.payload().map(wire::decode::<PriceUpdate>) {
+                    reference_prices.insert(price_update.name, price_update.price);
+                }
+            }
+        }
+        message = halt_consumer.recv() => {
+            if let Ok(m) = message {
+                if let Some(Ok(event)) = m.payload().map(wire::decode::<HaltEvent>) {
+                    println!("Trading {:?} for {}: {}", event.status, event.stock_symbol, event.reason);
+                    match event.status {
+                        TradingStatus::Halted => halted_symbols.insert(event.stock_symbol),
+                        TradingStatus::Resumed => halted_symbols.remove(&event.stock_symbol),
+                    };
+                }
+            }
+        }
+        message = corporate_action_consumer.recv() => {
+            if let Ok(m) = message {
+                if let Some(Ok(action)) = m.payload().map(wire::decode::<CorporateAction>) {
+                    println!("Corporate action for {}: {:?}", action.stock_symbol, action.kind);
+                    if let Some(book) = books.get_mut(&action.stock_symbol) {
+                        book.apply_corporate_action(&action.kind);
+                        let (quotes, updates) = market_data_publisher.changes(&books);
+                        market_data::publish(&market_data_producer, &quotes, &updates).await;
+                    }
+                    if let Some(adjusted_price) = action.adjusted_price {
+                        reference_prices.insert(action.stock_symbol, adjusted_price);
+                    }
+                }
+            }
+        }
+        message = consumer.recv() => match message {
             Ok(m) => {
-                if let Some(payload) = m.payload() {
-                    let message = String::from_utf8_lossy(payload);
-                    //println!("Received order message: {}", message);
-
-                    // Deserialize the JSON payload into Order
-                    match serde_json::from_str::<Order>(&message) {
-                        Ok(mut order) => {
-                            // println!("Processing order: {:?}", order);
-
-                            let outcome: f64 = rng.gen_range(0.0..1.0);
-                            let target_topic = if outcome <= 0.4 {
-                                // 40% chance: Completed
-                                order.status = OrderStatus::Completed;
-                                // Adjust price by 2% for completed orders
-                                order.price = (order.price * 1.02 * 100.0).round() / 100.0;
-                                completed_topic
-                            } else if outcome <= 0.6 {
-                                // Next 20% chance: Rejected
-                                order.status = OrderStatus::Rejected;
-                                rejected_topic
-                            } else {
-                                // Remaining 40%: Keep as Pending
-                                continue; // Skip further processing for pending orders
-                            };
-
-                            //println!("Updated order: {:?}", order);
-
-                            // Send the updated order back to the appropriate Kafka topic
-                            if let Err(err) = send_updated_order_to_kafka(&producer, &order, target_topic).await {
-                                println!("Failed to send updated order to Kafka: {}", err);
-                            }
-                        }
+                // Everything produced for this order goes out in one transaction with its offset
+                let mut outbox: Vec<OutboundMessage> = Vec::new();
+                // State changes held back until the transaction commits
+                let mut received_order_id: Option<String> = None;
+                let mut assigned_exchange_order_id = false;
+                let mut resting_order: Option<Order> = None;
+                let mut cancelled_order: Option<(String, String)> = None; // Symbol, order ID
+                let mut fill: Option<Order> = None;
+
+                'route: {
+                    let Some(payload) = m.payload() else {
+                        println!("Order message at offset {} has no payload", m.offset());
+                        outbox.push(OutboundMessage::dead_letter(&m, "Order message has no payload"));
+                        break 'route;
+                    };
+                    //println!("Received order message: {}", String::from_utf8_lossy(payload));
+
+                    // Unwrap the versioned envelope into an Order
+                    let mut order = match wire::decode::<Order>(payload) {
+                        Ok(order) => order,
                         Err(err) => {
                             println!("Failed to deserialize order: {}", err);
+                            outbox.push(OutboundMessage::dead_letter(&m, &format!("Failed to deserialize order: {}", err)));
+                            break 'route;
+                        }
+                    };
+                    // println!("Processing order: {:?}", order);
+
+                    // A cancel carries the ID of the order it cancels, so it skips the new-order checks
+                    if order.order_action == OrderAction::Cancel {
+                        let cancel_ack = cancel_ack(&order, books.get(&order.stock_symbol));
+                        if cancel_ack.cancelled {
+                            cancelled_order = Some((order.stock_symbol.clone(), order.order_id.clone()));
+                        }
+                        outbox.push(OutboundMessage::new(cancel_ack_topic, &cancel_ack.order_id, &cancel_ack));
+                        break 'route;
+                    }
+
+                    let validation = validate_order(&order, phase, &reference_prices, &halted_symbols, &seen_order_ids);
+
+                    // A redelivered order was already acked and routed the first time; drop it quietly
+                    if let Err((RejectReason::DuplicateId, text)) = &validation {
+                        println!("Dropping duplicate order: {}", text);
+                        break 'route;
+                    }
+                    received_order_id = Some(order.order_id.clone());
+
+                    // Every inbound order is acked with an exchange order ID or nacked with the reason
+                    let ack = OrderAck {
+                        broker_id: order.broker_id,
+                        client_id: order.client_id,
+                        order_id: order.order_id.clone(),
+                        accepted: validation.is_ok(),
+                        exchange_order_id: validation.is_ok().then(|| format!("EX-{}", next_exchange_order_id)),
+                        reject_reason: validation.as_ref().err().map(|(reason, _)| *reason),
+                        reject_text: validation.as_ref().err().map(|(_, text)| text.clone()),
+                    };
+                    assigned_exchange_order_id = ack.accepted;
+                    outbox.push(OutboundMessage::new(ack_topic, &ack.order_id, &ack));
+
+                    if let Err((reason, text)) = validation {
+                        order.status = OrderStatus::Rejected;
+                        order.reject_reason = Some(reason);
+                        order.reject_text = Some(text);
+                        outbox.push(OutboundMessage::new(rejected_topic, &order.order_id, &order));
+                        break 'route;
+                    }
+
+                    if matches!(phase, MarketPhase::OpeningCall | MarketPhase::ClosingCall) {
+                        // Call phase: accumulate without matching
+                        resting_order = Some(order);
+                        break 'route;
+                    }
+
+                    let outcome: f64 = rng.gen_range(0.0..1.0);
+                    // Price the fill off the last traded price using the execution model
+                    let reference_price = reference_prices.get(&order.stock_symbol).copied().unwrap_or(order.price.to_f64());
+                    let fill_price = execution_model.fill_price(&order, reference_price);
+                    match fill_price {
+                        Some(fill_price) if outcome <= 0.4 || order.liquidation => {
+                            // 40% chance: Completed, if the order is marketable. Liquidations
+                            // always fill when marketable, so a margin call isn't left open.
+                            order.status = OrderStatus::Completed;
+                            // Market orders take liquidity, limit orders are treated as resting
+                            order.liquidity = Some(match order.order_type {
+                                OrderType::Market => Liquidity::Taker,
+                                OrderType::Limit => Liquidity::Maker,
+                            });
+                            order.price = fill_price;
+                            order.exec_id = Some(new_unique_id());
+                            outbox.push(OutboundMessage::new(completed_topic, &order.order_id, &order));
+                            // Public print on the tape, committed together with the fill
+                            outbox.push(OutboundMessage::new(TRADE_TOPIC, &order.stock_symbol, &trade_from_fill(&order)));
+                            fill = Some(order);
+                        }
+                        _ if outcome > 0.4 && outcome <= 0.6 && !order.liquidation => {
+                            // Next 20% chance: Rejected. Liquidations are never randomly rejected,
+                            // marketable or not; one that can't fill yet rests below.
+                            order.status = OrderStatus::Rejected;
+                            order.reject_reason = Some(RejectReason::RiskLimit);
+                            order.reject_text = Some("Simulated exchange risk check".to_string());
+                            outbox.push(OutboundMessage::new(rejected_topic, &order.order_id, &order));
+                        }
+                        _ => {
+                            // Remaining 40%, and limits away from the market: Pending, resting in the book
+                            // until it is cancelled, uncrossed in the closing auction or expires
+                            resting_order = Some(order);
                         }
                     }
                 }
 
-                // Commit the message offset after processing
-                consumer.commit_message(&m, CommitMode::Async).unwrap();
+                let offsets = consumed_offsets(&m);
+                match produce_in_transaction(&producer, outbox, Some((offsets, group_metadata.clone()))).await {
+                    Ok(()) => {
+                        if let Some(order_id) = received_order_id {
+                            seen_order_ids.insert(&order_id);
+                        }
+                        if assigned_exchange_order_id {
+                            next_exchange_order_id += 1;
+                        }
+                        if let Some(order) = resting_order {
+                            books
+                                .entry(order.stock_symbol.clone())
+                                .or_insert_with(|| OrderBook::new(&order.stock_symbol))
+                                .add_order(order);
+                            let (quotes, updates) = market_data_publisher.changes(&books);
+                            market_data::publish(&market_data_producer, &quotes, &updates).await;
+                        }
+                        if let Some((stock_symbol, order_id)) = cancelled_order {
+                            if let Some(book) = books.get_mut(&stock_symbol) {
+                                book.cancel(&order_id);
+                            }
+                            let (quotes, updates) = market_data_publisher.changes(&books);
+                            market_data::publish(&market_data_producer, &quotes, &updates).await;
+                        }
+                        // Journal the fill for end-of-day reconciliation
+                        if let Some(fill) = fill {
+                            append_fill(EXCHANGE_FILLS_PATH, &fill);
+                            // The last trade is the best reference for the next order's price band
+                            reference_prices.insert(fill.stock_symbol.clone(), fill.price.to_f64());
+                        }
+                    }
+                    Err(err) => {
+                        // Nothing from this order was published; rewind so it is processed again
+                        println!("Order transaction aborted, retrying: {}", err);
+                        if let Err(err) = consumer.seek(m.topic(), m.partition(), Offset::Offset(m.offset()), Duration::from_secs(5)) {
+                            println!("Failed to rewind order consumer: {}", err);
+                        }
+                    }
+                }
             }
             Err(err) => {
                 println!("Kafka error: {}", err);
             }
         }
+        }
     }
 }).await;
     // Handle timeout
@@ -98,22 +351,245 @@ pub async fn consume_and_route_orders() {
     }
 }
 
-// Send Updated Orders Back to Kafka
-pub async fn send_updated_order_to_kafka(
-    producer: &FutureProducer,
+// Exchange-side checks applied to every inbound order before it can trade or rest
+fn validate_order(
     order: &Order,
-    topic: &str,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let payload = serde_json::to_string(order)?;
-
-    producer
-        .send(
-            FutureRecord::to(topic)
-                .key(&order.order_id)
-                .payload(&payload),
-            rdkafka::util::Timeout::Never,
-        )
-        .await
-        .map_err(|(err, _)| err)?;
+    phase: MarketPhase,
+    reference_prices: &HashMap<String, f64>,
+    halted_symbols: &HashSet<String>,
+    seen_order_ids: &RecentIds,
+) -> Result<(), (RejectReason, String)> {
+    if seen_order_ids.contains(&order.order_id) {
+        return Err((RejectReason::DuplicateId, format!("{} was already received", order.order_id)));
+    }
+    if phase == MarketPhase::Closed {
+        return Err((RejectReason::MarketClosed, "Closing auction has completed".to_string()));
+    }
+    if !LISTED_SYMBOLS.contains(&order.stock_symbol.as_str()) {
+        return Err((RejectReason::UnknownSymbol, format!("{} is not listed", order.stock_symbol)));
+    }
+    if halted_symbols.contains(&order.stock_symbol) {
+        return Err((RejectReason::Halted, format!("Trading in {} is halted", order.stock_symbol)));
+    }
+    if order.quantity == 0 {
+        return Err((RejectReason::InvalidQuantity, format!("Cannot {:?} {} shares", order.order_action, order.quantity)));
+    }
+    if let (OrderType::Limit, Some(&last_price)) = (&order.order_type, reference_prices.get(&order.stock_symbol)) {
+        let deviation = (order.price.to_f64() - last_price).abs() / last_price * 100.0;
+        if deviation > PRICE_BAND_PERCENT {
+            return Err((
+                RejectReason::PriceOutOfBand,
+                format!("Limit {:.2} is {:.1}% away from last price {:.2}", order.price, deviation, last_price),
+            ));
+        }
+    }
+    // A margin call has to close the whole position, however large
+    let notional = order.price.times(order.quantity as i64).to_f64();
+    if notional > MAX_ORDER_NOTIONAL && !order.liquidation {
+        return Err((
+            RejectReason::RiskLimit,
+            format!("Order notional {:.2} exceeds limit {:.2}", notional, MAX_ORDER_NOTIONAL),
+        ));
+    }
     Ok(())
-}
\ No newline at end of file
+}
+
+// Answer a cancel request. Only the owning client's orders still resting in the book can be cancelled;
+// the book itself is changed once the ack has been committed.
+fn cancel_ack(request: &Order, book: Option<&OrderBook>) -> CancelAck {
+    let resting = book.and_then(|book| book.resting_order(&request.order_id));
+    let reject_text = match resting {
+        None => Some(format!("{} is not resting in the book", request.order_id)),
+        Some(order) if order.broker_id != request.broker_id || order.client_id != request.client_id => {
+            Some(format!("{} belongs to another client", request.order_id))
+        }
+        Some(_) => None,
+    };
+    CancelAck {
+        broker_id: request.broker_id,
+        client_id: request.client_id,
+        order_id: request.order_id.clone(),
+        cancelled: reject_text.is_none(),
+        cancelled_quantity: resting.filter(|_| reject_text.is_none()).map_or(0, |order| order.quantity),
+        reject_text,
+    }
+}
+
+// Uncross every book and publish the fills; the auction price becomes the official open/close.
+// A book only takes its uncross once the fills are committed, so an auction that can't be published
+// leaves the orders resting as they were.
+async fn run_auction(
+    producer: &FutureProducer,
+    books: &mut HashMap<String, OrderBook>,
+    reference_prices: &HashMap<String, f64>,
+    phase: MarketPhase,
+    completed_topic: &'static str,
+) {
+    for book in books.values_mut() {
+        let symbol = book.symbol.clone();
+        let reference_price = reference_prices.get(&symbol).copied();
+        let mut uncrossed = book.clone();
+        if let Some((point, fills)) = uncross(&mut uncrossed, reference_price) {
+            // Each attempt resends the same fills (same exec IDs) in a fresh transaction
+            let mut published = false;
+            let trade = auction_trade(&symbol, round_price(&symbol, point.price, Rounding::Nearest).to_f64(), point.volume);
+            for attempt in 1..=AUCTION_PUBLISH_ATTEMPTS {
+                let mut outbox: Vec<OutboundMessage> = fills
+                    .iter()
+                    .map(|fill| OutboundMessage::new(completed_topic, &fill.order_id, fill))
+                    .collect();
+                outbox.push(OutboundMessage::new(TRADE_TOPIC, &symbol, &trade));
+                match produce_in_transaction(producer, outbox, None).await {
+                    Ok(()) => {
+                        published = true;
+                        break;
+                    }
+                    Err(err) => println!("Auction fill transaction for {} failed (attempt {}): {}", symbol, attempt, err),
+                }
+            }
+            if !published {
+                println!("{:?} auction for {} not published, book left as it was", phase, symbol);
+                continue;
+            }
+            println!(
+                "{:?} auction for {}: {} shares at {:.2} (imbalance {})",
+                phase, symbol, point.volume, point.price, point.imbalance()
+            );
+            *book = uncrossed;
+            record_official_price(&symbol, phase, point.price);
+            for fill in &fills {
+                append_fill(EXCHANGE_FILLS_PATH, fill);
+            }
+        }
+    }
+    books.retain(|_, book| !book.is_empty());
+}
+
+// Publish the indicative uncrossing price and imbalance for each book in the call
+async fn publish_indicatives(
+    producer: &FutureProducer,
+    books: &HashMap<String, OrderBook>,
+    reference_prices: &HashMap<String, f64>,
+    phase: MarketPhase,
+    auction_topic: &str,
+) {
+    for (symbol, book) in books {
+        let point = find_uncross_point(book, reference_prices.get(symbol).copied());
+        let indicative = AuctionIndicative {
+            stock_symbol: symbol.clone(),
+            phase,
+            indicative_price: point.as_ref().map(|p| round_price(symbol, p.price, Rounding::Nearest).to_f64()),
+            matched_volume: point.as_ref().map_or(0, |p| p.volume),
+            imbalance: point.as_ref().map_or(0, |p| p.imbalance()),
+            imbalance_side: point.as_ref().and_then(|p| p.imbalance_side()),
+        };
+        let payload = wire::encode(&indicative);
+        if let Err((err, _)) = producer
+            .send(
+                FutureRecord::to(auction_topic).key(symbol).payload(&payload),
+                rdkafka::util::Timeout::Never,
+            )
+            .await
+        {
+            println!("Failed to send auction indicative to Kafka: {}", err);
+        }
+    }
+}
+
+// A message waiting to be produced inside a transaction
+struct OutboundMessage {
+    topic: String,
+    key: Option<Vec<u8>>,
+    payload: Option<Vec<u8>>,
+    headers: Option<OwnedHeaders>,
+}
+
+impl OutboundMessage {
+    fn new<T: WireMessage>(topic: &'static str, key: &str, value: &T) -> Self {
+        OutboundMessage {
+            topic: topic.to_string(),
+            key: Some(key.as_bytes().to_vec()),
+            payload: Some(wire::encode(value)),
+            headers: None,
+        }
+    }
+
+    // Dead letters for the order topic commit with its offset like any other outcome
+    fn dead_letter(m: &BorrowedMessage, error: &str) -> Self {
+        let dead_letter = DeadLetter::from_message(m, error);
+        OutboundMessage {
+            topic: dead_letter.topic,
+            key: dead_letter.key,
+            payload: dead_letter.payload,
+            headers: Some(dead_letter.headers),
+        }
+    }
+
+    fn record(&self) -> FutureRecord<'_, [u8], [u8]> {
+        let mut record = FutureRecord::to(&self.topic);
+        if let Some(key) = &self.key {
+            record = record.key(key.as_slice());
+        }
+        if let Some(payload) = &self.payload {
+            record = record.payload(payload.as_slice());
+        }
+        if let Some(headers) = &self.headers {
+            record = record.headers(headers.clone());
+        }
+        record
+    }
+}
+
+// Offset to commit once a message has been processed: the next one on its partition
+fn consumed_offsets(m: &BorrowedMessage) -> TopicPartitionList {
+    let mut offsets = TopicPartitionList::new();
+    offsets
+        .add_partition_offset(m.topic(), m.partition(), Offset::Offset(m.offset() + 1))
+        .expect("Failed to build consumed offsets");
+    offsets
+}
+
+// Produce the messages, and commit the consumed offsets if given, as a single transaction.
+// On any failure the transaction is aborted and none of it becomes visible to read_committed consumers.
+async fn produce_in_transaction(
+    producer: &FutureProducer,
+    outbox: Vec<OutboundMessage>,
+    consumed: Option<(TopicPartitionList, Arc<ConsumerGroupMetadata>)>,
+) -> KafkaResult<()> {
+    blocking_transaction_call(producer, |producer| producer.begin_transaction()).await?;
+    let result: KafkaResult<()> = async {
+        for message in &outbox {
+            producer
+                .send(message.record(), rdkafka::util::Timeout::Never)
+                .await
+                .map_err(|(err, _)| err)?;
+        }
+        if let Some((offsets, group_metadata)) = consumed {
+            blocking_transaction_call(producer, move |producer| {
+                producer.send_offsets_to_transaction(&offsets, &group_metadata, TRANSACTION_TIMEOUT)
+            })
+            .await?;
+        }
+        blocking_transaction_call(producer, |producer| producer.commit_transaction(TRANSACTION_TIMEOUT)).await
+    }
+    .await;
+
+    if result.is_err() {
+        if let Err(err) = blocking_transaction_call(producer, |producer| producer.abort_transaction(TRANSACTION_TIMEOUT)).await {
+            println!("Failed to abort transaction: {}", err);
+        }
+    }
+    result
+}
+
+// librdkafka's transaction calls block until the coordinator answers (up to TRANSACTION_TIMEOUT),
+// so they run on the blocking pool rather than stalling an async worker
+async fn blocking_transaction_call<F>(producer: &FutureProducer, call: F) -> KafkaResult<()>
+where
+    F: FnOnce(&FutureProducer) -> KafkaResult<()> + Send + 'static,
+{
+    let producer = producer.clone();
+    tokio::task::spawn_blocking(move || call(&producer))
+        .await
+        .expect("Kafka transaction call panicked")
+}
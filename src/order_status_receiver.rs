@@ -1,23 +1,96 @@
 use rdkafka::config::ClientConfig;
-use rdkafka::consumer::{CommitMode, Consumer, StreamConsumer};
+use rdkafka::consumer::{Consumer, StreamConsumer};
 use rdkafka::message::Message;
 use tokio::time::timeout;
 use std::time::Duration;
-use crate::broker::update_client_portfolio_in_json;
-use crate::models::{Order, OrderAction};
-use std::collections:: HashSet;
+use crate::broker::{record_client_rejection_in_json, update_client_portfolio_in_json};
+use crate::broker::data::{is_already_applied, load_consumer_offsets, ConsumedOffset};
+use crate::fees::{FeeSchedule, FEE_SCHEDULE_PATH};
+use crate::broker::blotter::{Blotter, OrderState};
+use crate::broker::borrow::BorrowDesk;
+use crate::money::Money;
+use crate::dedup::RecentIds;
+use crate::dead_letter::{commit_or_rewind, dead_letter_producer, send_to_dead_letter};
+use crate::models::{CancelAck, Order, OrderAck, OrderAction, RejectReason};
+use crate::reconciliation::{reset_fill_journal, APPLIED_FILLS_PATH};
+use crate::wire;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
 use colored::*;
 //trading side
 
-pub async fn order_status_receiver() {
+// How many recent rejected order IDs are remembered for duplicate detection
+const PROCESSED_REJECT_CAPACITY: usize = 100_000;
+
+// Broker ID -> That broker's order blotter
+pub type Blotters = HashMap<u64, Arc<Mutex<Blotter>>>;
+
+// An ack, fill, reject or cancel as applied to the broker's blotter, streamed to API subscribers
+#[derive(Serialize, Debug, Clone)]
+pub struct ExecutionReport {
+    pub broker_id: u64,
+    pub client_id: u64,
+    pub order_id: String,
+    pub stock_symbol: String,
+    pub order_action: OrderAction,
+    pub state: OrderState,
+    pub filled_quantity: u64,
+    pub last_quantity: u64, // Zero unless this report is a fill
+    pub last_price: Option<Money>,
+    pub exec_id: Option<String>,
+    pub text: Option<String>,
+}
+
+// Report the order's state after an update; orders the blotter never saw are not reported
+fn execution_report(blotter: &Blotter, order_id: &str, fill: Option<&Order>, text: Option<&str>) -> Option<ExecutionReport> {
+    let entry = blotter.entry(order_id)?;
+    Some(ExecutionReport {
+        broker_id: blotter.broker_id(),
+        client_id: entry.order.client_id,
+        order_id: order_id.to_string(),
+        stock_symbol: entry.order.stock_symbol.clone(),
+        order_action: entry.order.order_action.clone(),
+        state: entry.state,
+        filled_quantity: entry.filled_quantity,
+        last_quantity: fill.map_or(0, |fill| fill.quantity),
+        last_price: fill.map(|fill| fill.price),
+        exec_id: fill.and_then(|fill| fill.exec_id.clone()),
+        text: text.map(str::to_string),
+    })
+}
+
+// Locates held for orders that can no longer fill go back to the borrow desk
+pub async fn order_status_receiver(
+    blotters: Blotters,
+    borrow_desk: Arc<Mutex<BorrowDesk>>,
+    exec_tx: broadcast::Sender<ExecutionReport>,
+) {
     let brokers = "localhost:9092";
     let completed_topic = "completed_order";
     let rejected_topic = "rejected_order";
+    let ack_topic = "order_acks";
+    let cancel_ack_topic = "cancel_acks";
+
+    let ack_consumer: StreamConsumer = ClientConfig::new()
+        .set("bootstrap.servers", brokers)
+        .set("group.id", "order-ack-processor-group")
+        .set("enable.auto.commit", "false")
+        .set("isolation.level", "read_committed")
+        .set("fetch.min.bytes", "1")
+        .set("fetch.wait.max.ms", "1")
+        .create()
+        .expect("Failed to create Kafka consumer for order acks");
+    ack_consumer
+        .subscribe(&[ack_topic])
+        .expect("Failed to subscribe to ack topic");
 
     let completed_consumer: StreamConsumer = ClientConfig::new()
         .set("bootstrap.servers", brokers)
         .set("group.id", "completed-order-processor-group")
         .set("enable.auto.commit", "false")
+        .set("isolation.level", "read_committed") // Only fills from committed matcher transactions
         .set("fetch.min.bytes", "1")
         .set("fetch.wait.max.ms", "1")
         .create()
@@ -27,11 +100,25 @@ pub async fn order_status_receiver() {
         .set("bootstrap.servers", brokers)
         .set("group.id", "rejected-order-processor-group")
         .set("enable.auto.commit", "false")
+        .set("isolation.level", "read_committed")
         .set("fetch.min.bytes", "1")
         .set("fetch.wait.max.ms", "1")
         .create()
         .expect("Failed to create Kafka consumer for rejected orders");
 
+    let cancel_ack_consumer: StreamConsumer = ClientConfig::new()
+        .set("bootstrap.servers", brokers)
+        .set("group.id", "cancel-ack-processor-group")
+        .set("enable.auto.commit", "false")
+        .set("isolation.level", "read_committed")
+        .set("fetch.min.bytes", "1")
+        .set("fetch.wait.max.ms", "1")
+        .create()
+        .expect("Failed to create Kafka consumer for cancel acks");
+    cancel_ack_consumer
+        .subscribe(&[cancel_ack_topic])
+        .expect("Failed to subscribe to cancel ack topic");
+
     completed_consumer
         .subscribe(&[completed_topic])
         .expect("Failed to subscribe to completed topic");
@@ -40,39 +127,188 @@ pub async fn order_status_receiver() {
         .expect("Failed to subscribe to rejected topic");
 
     //println!("Order processor started, waiting for messages...");
+    reset_fill_journal(APPLIED_FILLS_PATH);
+    let dlq_producer = dead_letter_producer();
 
     // Process completed orders
     let processing_result = timeout(Duration::from_secs(50), async {
+        // Acks mark orders live in the broker's blotter; nacks reject them
+        let ack_task = tokio::spawn({
+            let blotters = blotters.clone();
+            let borrow_desk = borrow_desk.clone();
+            let dlq_producer = dlq_producer.clone();
+            let exec_tx = exec_tx.clone();
+            async move {
+                loop {
+                    match ack_consumer.recv().await {
+                        Ok(m) => {
+                            let mut parked = Ok(());
+                            match m.payload().map(wire::decode::<OrderAck>) {
+                                Some(Ok(ack)) => {
+                                    if !ack.accepted {
+                                        borrow_desk.lock().unwrap().release(&ack.order_id);
+                                    }
+                                    if let Some(blotter) = blotters.get(&ack.broker_id) {
+                                        let mut blotter = blotter.lock().unwrap();
+                                        // Illegal transitions are flagged by the blotter itself
+                                        let _ = if ack.accepted {
+                                            blotter.acknowledge(&ack.order_id, ack.exchange_order_id.as_deref())
+                                        } else {
+                                            blotter.reject(&ack.order_id)
+                                        };
+                                        if let Some(report) = execution_report(&blotter, &ack.order_id, None, ack.reject_text.as_deref()) {
+                                            let _ = exec_tx.send(report); // No subscribers is fine
+                                        }
+                                    }
+                                    if ack.accepted {
+                                        println!(
+                                            "Order {} live as {}",
+                                            ack.order_id,
+                                            ack.exchange_order_id.as_deref().unwrap_or("unknown")
+                                        );
+                                    }
+                                }
+                                Some(Err(err)) => {
+                                    println!("Failed to parse order ack: {}", err);
+                                    parked = send_to_dead_letter(&dlq_producer, &m, &format!("Failed to parse order ack: {}", err)).await;
+                                }
+                                None => parked = send_to_dead_letter(&dlq_producer, &m, "Order ack has no payload").await,
+                            }
+                            commit_or_rewind(&ack_consumer, &m, parked);
+                        }
+                        Err(err) => {
+                            println!("Kafka error on ack topic: {}", err);
+                        }
+                    }
+                }
+            }
+        });
+
+        // Cancels only take effect in the blotter once the matcher has taken the order out of its book
+        let cancel_ack_task = tokio::spawn({
+            let blotters = blotters.clone();
+            let borrow_desk = borrow_desk.clone();
+            let dlq_producer = dlq_producer.clone();
+            let exec_tx = exec_tx.clone();
+            async move {
+                loop {
+                    match cancel_ack_consumer.recv().await {
+                        Ok(m) => {
+                            let mut parked = Ok(());
+                            match m.payload().map(wire::decode::<CancelAck>) {
+                                Some(Ok(cancel_ack)) => {
+                                    if cancel_ack.cancelled {
+                                        borrow_desk.lock().unwrap().release(&cancel_ack.order_id);
+                                    }
+                                    if let Some(blotter) = blotters.get(&cancel_ack.broker_id) {
+                                        let mut blotter = blotter.lock().unwrap();
+                                        let text = if cancel_ack.cancelled {
+                                            let _ = blotter.cancel(&cancel_ack.order_id);
+                                            None
+                                        } else {
+                                            Some(format!(
+                                                "Cancel rejected: {}",
+                                                cancel_ack.reject_text.as_deref().unwrap_or("no detail")
+                                            ))
+                                        };
+                                        if let Some(report) = execution_report(&blotter, &cancel_ack.order_id, None, text.as_deref()) {
+                                            let _ = exec_tx.send(report);
+                                        }
+                                    }
+                                }
+                                Some(Err(err)) => {
+                                    println!("Failed to parse cancel ack: {}", err);
+                                    parked = send_to_dead_letter(&dlq_producer, &m, &format!("Failed to parse cancel ack: {}", err)).await;
+                                }
+                                None => parked = send_to_dead_letter(&dlq_producer, &m, "Cancel ack has no payload").await,
+                            }
+                            commit_or_rewind(&cancel_ack_consumer, &m, parked);
+                        }
+                        Err(err) => {
+                            println!("Kafka error on cancel ack topic: {}", err);
+                        }
+                    }
+                }
+            }
+        });
+
         let completed_task = tokio::spawn({
             let json_file_path = "src/data/client_holdings.json";
+            let fee_schedule = FeeSchedule::load(FEE_SCHEDULE_PATH);
+            let blotters = blotters.clone();
+            let borrow_desk = borrow_desk.clone();
+            let dlq_producer = dlq_producer.clone();
+            let exec_tx = exec_tx.clone();
+            // Offsets written with the holdings are the source of truth across restarts
+            let applied_offsets = load_consumer_offsets(json_file_path);
             async move {
                 loop {
                     match completed_consumer.recv().await {
                         Ok(m) => {
-                            if let Some(payload) = m.payload() {
-                                let message = String::from_utf8_lossy(payload);
+                            let mut parked = Ok(());
+                            if is_already_applied(&applied_offsets, m.topic(), m.partition(), m.offset()) {
+                                println!("Skipping fill at offset {} on {}: already applied", m.offset(), m.topic());
+                            } else if let Some(payload) = m.payload() {
+                                match wire::decode::<Order>(payload) {
+                                    Ok(order) => {
+                                        let valid = match order.order_action {
+                                            OrderAction::Buy => {
+                                                println!("{}",format!("Completed Buy Order Execute: {:?}", order).bright_cyan().bold());
+                                                true
+                                            }
+                                            OrderAction::Sell => {
+                                                println!("{}",format!("Completed Sell Order Execute: {:?}", order).bright_magenta().bold());
+                                                true
+                                            }
+                                            _ => false,
+                                        };
 
-                                if let Ok(order) = serde_json::from_str::<Order>(&message) {
-                                    match order.order_action {
-                                        OrderAction::Buy => {
-                                            println!("{}",format!("Completed Buy Order Execute: {:?}", order).bright_cyan().bold());
-                                        }
-                                        OrderAction::Sell => {
-                                            println!("{}",format!("Completed Sell Order Execute: {:?}", order).bright_magenta().bold());
-                                        }
-                                        _ => {
-                                            println!("Invalid order action for order ID {}: {:?}",order.order_id, order.order_action);
-                                            continue;
+                                        if !valid {
+                                            let error = format!("Invalid order action for order ID {}: {:?}", order.order_id, order.order_action);
+                                            println!("{}", error);
+                                            parked = send_to_dead_letter(&dlq_producer, &m, &error).await;
+                                        } else {
+                                            let fees = fee_schedule.fees_for_fill(&order);
+                                            let result = update_client_portfolio_in_json(json_file_path, &order, fees,
+                                            Some(ConsumedOffset { topic: m.topic(), partition: m.partition(), offset: m.offset() })).await;
+                                            match result {
+                                                Ok(Some(shorted_quantity)) => {
+                                                    let unlocated = borrow_desk.lock().unwrap().consume(&order.order_id, shorted_quantity);
+                                                    if unlocated > 0 {
+                                                        println!("Fill for order {} sold {} {} short without a locate", order.order_id, unlocated, order.stock_symbol);
+                                                    }
+                                                    let mut filled = false;
+                                                    if let Some(blotter) = blotters.get(&order.broker_id) {
+                                                        let mut blotter = blotter.lock().unwrap();
+                                                        let _ = blotter.apply_fill(&order.order_id, order.quantity);
+                                                        filled = blotter.state(&order.order_id) == Some(OrderState::Filled);
+                                                        if let Some(report) = execution_report(&blotter, &order.order_id, Some(&order), None) {
+                                                            let _ = exec_tx.send(report);
+                                                        }
+                                                    }
+                                                    // Nothing more will be shorted against a completed order
+                                                    if filled {
+                                                        borrow_desk.lock().unwrap().release(&order.order_id);
+                                                    }
+                                                }
+                                                Ok(None) => {}
+                                                Err(error) => {
+                                                    println!("Failed to apply fill for order {}: {}", order.order_id, error);
+                                                    parked = send_to_dead_letter(&dlq_producer, &m, &error).await;
+                                                }
+                                            }
                                         }
                                     }
-
-                                    update_client_portfolio_in_json(json_file_path,order.client_id,order.stock_symbol.clone(),
-                                    order.quantity,matches!(order.order_action, OrderAction::Buy), order.price,).await;
-                                } else {
-                                    println!("Failed to parse order message: {}", message);
+                                    Err(err) => {
+                                        println!("Failed to parse order message ({}): {}", wire::describe_payload(payload), err);
+                                        parked = send_to_dead_letter(&dlq_producer, &m, &format!("Failed to parse order message: {}", err)).await;
+                                    }
                                 }
+                            } else {
+                                parked = send_to_dead_letter(&dlq_producer, &m, "Completed order has no payload").await;
                             }
-                            completed_consumer.commit_message(&m, CommitMode::Async).unwrap();
+                            // Kafka's committed offset only saves re-reading; the holdings file has the real position
+                            commit_or_rewind(&completed_consumer, &m, parked);
                         }
                         Err(err) => {
                             println!("Kafka error on completed topic: {}", err);
@@ -84,28 +320,72 @@ pub async fn order_status_receiver() {
 
     // Process rejected orders
     let rejected_task =tokio::spawn({
-        let mut processed_orders = HashSet::new(); // Track processed orders
+        let mut processed_orders = RecentIds::new(PROCESSED_REJECT_CAPACITY); // Track processed orders
+        let json_file_path = "src/data/client_holdings.json";
+        let applied_offsets = load_consumer_offsets(json_file_path);
+        let borrow_desk = borrow_desk.clone();
+        let dlq_producer = dlq_producer.clone();
+        let exec_tx = exec_tx.clone();
         async move {
             loop {
                 match rejected_consumer.recv().await {
                     Ok(m) => {
-                        if let Some(payload) = m.payload() {
-                            let message = String::from_utf8_lossy(payload);
-                            if let Ok(order) = serde_json::from_str::<Order>(&message) {
-                                // Skip if already processed
-                                if !processed_orders.insert(order.order_id.clone()) {
-                                    continue;
-                                }
+                        let mut parked = Ok(());
+                        if is_already_applied(&applied_offsets, m.topic(), m.partition(), m.offset()) {
+                            println!("Skipping reject at offset {} on {}: already applied", m.offset(), m.topic());
+                        } else if let Some(payload) = m.payload() {
+                            match wire::decode::<Order>(payload) {
+                                Ok(order) => {
+                                    // Skip if already processed
+                                    if !processed_orders.insert(&order.order_id) {
+                                        continue;
+                                    }
+                                    borrow_desk.lock().unwrap().release(&order.order_id);
+
+                                    let reason = order.reject_reason.unwrap_or_else(|| {
+                                        println!("Rejected order {} carries no reject reason", order.order_id);
+                                        RejectReason::Unknown
+                                    });
+                                    println!(
+                                        "{}",
+                                        format!(
+                                            "Order Rejected ({:?}: {}): {} {:?} {} {} @ {:.2} for Client {}",
+                                            reason,
+                                            order.reject_text.as_deref().unwrap_or("no detail"),
+                                            order.order_id, order.order_action, order.quantity, order.stock_symbol, order.price, order.client_id
+                                        )
+                                        .bright_black().bold()
+                                    );
+                                    if let Err(error) = record_client_rejection_in_json(
+                                        json_file_path,
+                                        order.client_id,
+                                        reason,
+                                        Some(ConsumedOffset { topic: m.topic(), partition: m.partition(), offset: m.offset() }),
+                                    ) {
+                                        println!("Failed to record rejection for order {}: {}", order.order_id, error);
+                                        parked = send_to_dead_letter(&dlq_producer, &m, &error).await;
+                                    }
 
-                                println!(
-                                    "{}",
-                                    format!("Order Rejected: {:?}", order).bright_black().bold()
-                                );
+                                    // Validation rejects were already nacked; only reject orders still working
+                                    if let Some(blotter) = blotters.get(&order.broker_id) {
+                                        let mut blotter = blotter.lock().unwrap();
+                                        if blotter.state(&order.order_id) != Some(OrderState::Rejected) {
+                                            let _ = blotter.reject(&order.order_id);
+                                            if let Some(report) = execution_report(&blotter, &order.order_id, None, order.reject_text.as_deref()) {
+                                                let _ = exec_tx.send(report);
+                                            }
+                                        }
+                                    }
+                                }
+                                Err(err) => {
+                                    println!("Failed to parse rejected order message ({}): {}", wire::describe_payload(payload), err);
+                                    parked = send_to_dead_letter(&dlq_producer, &m, &format!("Failed to parse order message: {}", err)).await;
+                                }
                             }
+                        } else {
+                            parked = send_to_dead_letter(&dlq_producer, &m, "Rejected order has no payload").await;
                         }
-                        rejected_consumer
-                            .commit_message(&m, CommitMode::Async)
-                            .unwrap();
+                        commit_or_rewind(&rejected_consumer, &m, parked);
                     }
                     Err(err) => {
                         println!("Kafka error on rejected topic: {}", err);
@@ -114,6 +394,10 @@ pub async fn order_status_receiver() {
             }
         }
     });
-    let _ = tokio::join!(completed_task, rejected_task);
-}).await;
+    let _ = tokio::join!(ack_task, cancel_ack_task, completed_task, rejected_task);
+});
+
+    if processing_result.await.is_err() {
+        println!("Stopping order status receiver.");
+    }
 }
\ No newline at end of file
@@ -1,29 +1,72 @@
 use serde_json::Value;
 use std::collections::HashMap;
 use std::fs;
+use std::io::Write;
 use colored::*; // Use colored crate for text colors
+use crate::auction::load_official_prices;
+use crate::broker::margin::{AccountType, MarginRequirements, MarginStatus, MARGIN_REQUIREMENTS_PATH};
+use crate::currency::{instrument_currency, CurrencyConfig, FxRates, CURRENCY_CONFIG_PATH, FX_RATES_PATH, HOME_CURRENCY};
+use crate::bars::{load_bars, BarConfig, BAR_CONFIG_PATH, BARS_PATH};
+use crate::tick_store::TICK_STORE_DIR;
+use crate::trade_tape::summarize_tape;
 
-const INITIAL_CAPITAL: f64 = 20_000.0;
+const INITIAL_CAPITAL: f64 = 20_000.0; // In USD
+pub const CLOSED_LOTS_PATH: &str = "src/data/closed_lots.csv";
 
 #[derive(Debug)]
 struct ClientPerformance {
     client_id: u64,
     total_investment: f64,
-    remaining_capital: f64,
+    market_value: f64, // Holdings marked at the official close (or open) price
+    remaining_capital: f64, // All cash balances converted to the base currency
+    cash_balances: HashMap<String, f64>, // Currency -> Cash in that currency
     total_transactions: u64,
-    portfolio: HashMap<String, (u64, f64)>, // Stock symbol -> (Quantity, Average Price)
+    portfolio: HashMap<String, (i64, f64)>, // Stock symbol -> (Quantity, Average Price), negative when short
     total_value: f64,
     pnl: f64, // Profit & Loss
+    commissions: f64,
+    exchange_fees: f64, // Net of rebates
+    borrow_fees: f64,
+    dividends: f64,
+    realized_gain: f64, // Sum of closed-lot gains, before fees
+    lot_method: String,
+    rejections: HashMap<String, u64>, // Reject reason -> Count
+    account_type: AccountType,
+    margin: MarginStatus,
 }
 
-pub fn generate_client_report(json_file_path: &str) {
+// The session's trades are read from the tick store, starting at session_start_ms
+pub fn generate_client_report(json_file_path: &str, session_start_ms: i64) {
     // Step 1: Read the JSON data from the file
     let json_data = fs::read_to_string(json_file_path)
         .expect("Failed to read client data JSON file.");
     let data: Value = serde_json::from_str(&json_data)
         .expect("Failed to parse JSON data.");
 
+    let official_prices = load_official_prices();
+    let tape = summarize_tape(TICK_STORE_DIR, session_start_ms, i64::MAX);
+    let margin_requirements = MarginRequirements::load(MARGIN_REQUIREMENTS_PATH);
+    let fx_rates = FxRates::load(FX_RATES_PATH);
+    // Amounts are summed in USD, then converted to the base currency for display. A base currency
+    // with no rate is reported in USD instead.
+    let configured_currency = CurrencyConfig::load(CURRENCY_CONFIG_PATH).base_currency;
+    let (base_currency, base_usd_rate) = match fx_rates.usd_rate(&configured_currency) {
+        Ok(usd_rate) => (configured_currency, usd_rate),
+        Err(e) => {
+            println!("{}; reporting in {}", e, HOME_CURRENCY);
+            (HOME_CURRENCY.to_string(), 1.0)
+        }
+    };
+    let to_base = |amount_usd: f64| amount_usd / base_usd_rate;
+    // Amounts in a currency with no rate are left out, and said so
+    let to_usd = |amount: f64, currency: &str, client_id: u64| {
+        fx_rates.convert(amount, currency, HOME_CURRENCY).unwrap_or_else(|e| {
+            println!("Client {}: {} {:.2} left out of the report: {}", client_id, currency, amount, e);
+            0.0
+        })
+    };
     let mut reports: Vec<ClientPerformance> = Vec::new();
+    let mut total_rejections: HashMap<String, u64> = HashMap::new();
 
     // Step 2: Parse the JSON data
     if let Some(brokers) = data["brokers"].as_array() {
@@ -31,36 +74,114 @@ pub fn generate_client_report(json_file_path: &str) {
             if let Some(clients) = broker["clients"].as_array() {
                 for client in clients {
                     let client_id = client["client_id"].as_u64().unwrap_or(0);
-                    let capital = client["capital"].as_f64().unwrap_or(0.0);
+                    let mut cash_balances: HashMap<String, f64> = HashMap::new();
+                    cash_balances.insert(HOME_CURRENCY.to_string(), client["capital"].as_f64().unwrap_or(0.0));
+                    if let Some(balances) = client["cash_balances"].as_object() {
+                        for (currency, amount) in balances {
+                            *cash_balances.entry(currency.clone()).or_insert(0.0) += amount.as_f64().unwrap_or(0.0);
+                        }
+                    }
+                    let capital: f64 = cash_balances
+                        .iter()
+                        .map(|(currency, amount)| to_usd(*amount, currency, client_id))
+                        .sum();
                     let buy_count = client["buy_transaction_count"].as_u64().unwrap_or(0);
                     let sell_count = client["sell_transaction_count"].as_u64().unwrap_or(0);
-                    let mut portfolio: HashMap<String, (u64, f64)> = HashMap::new();
+                    let mut portfolio: HashMap<String, (i64, f64)> = HashMap::new();
                     let mut total_investment = 0.0;
+                    let mut market_value = 0.0;
+                    let mut marked_positions: HashMap<String, (i64, f64)> = HashMap::new();
 
                     // Step 3: Parse the client's portfolio
                     if let Some(portfolio_data) = client["portfolio"].as_object() {
                         for (stock, details) in portfolio_data {
-                            let quantity = details["quantity"].as_u64().unwrap_or(0);
+                            let quantity = details["quantity"].as_i64().unwrap_or(0);
                             let avg_price = details["average_price"].as_f64().unwrap_or(0.0);
                             portfolio.insert(stock.clone(), (quantity, avg_price));
 
+                            // Prices are in the instrument's currency
+                            let usd_rate = match fx_rates.usd_rate(instrument_currency(stock)) {
+                                Ok(usd_rate) => usd_rate,
+                                Err(e) => {
+                                    println!("Client {}: {} position left out of the report: {}", client_id, stock, e);
+                                    continue;
+                                }
+                            };
+
                             // Calculate total investment
-                            total_investment += quantity as f64 * avg_price;
+                            total_investment += quantity as f64 * avg_price * usd_rate;
+
+                            // Mark at the auction price, then the last trade, falling back to cost when the stock never traded
+                            let mark_price = official_prices
+                                .get(stock)
+                                .and_then(|p| p.close.or(p.open))
+                                .or_else(|| tape.get(stock).map(|summary| summary.last))
+                                .unwrap_or(avg_price);
+                            market_value += quantity as f64 * mark_price * usd_rate;
+                            marked_positions.insert(stock.clone(), (quantity, mark_price * usd_rate));
                         }
                     }
 
-                    let total_value = total_investment + capital;
-                    let pnl = total_value - INITIAL_CAPITAL; // P&L calculation
+                    // Tally rejected orders by reason
+                    let mut rejections: HashMap<String, u64> = HashMap::new();
+                    if let Some(rejection_data) = client["rejection_counts"].as_object() {
+                        for (reason, count) in rejection_data {
+                            let count = count.as_u64().unwrap_or(0);
+                            rejections.insert(reason.clone(), count);
+                            *total_rejections.entry(reason.clone()).or_insert(0) += count;
+                        }
+                    }
+
+                    let commissions = client["commissions_paid"].as_f64().unwrap_or(0.0);
+                    let exchange_fees = client["exchange_fees_paid"].as_f64().unwrap_or(0.0);
+                    let borrow_fees = client["borrow_fees_paid"].as_f64().unwrap_or(0.0);
+                    let dividends = client["dividends_received"].as_f64().unwrap_or(0.0);
+                    let realized_gain: f64 = client["realized_gains"]
+                        .as_array()
+                        .map(|gains| {
+                            gains
+                                .iter()
+                                .map(|g| {
+                                    let currency = g["currency"].as_str().unwrap_or(HOME_CURRENCY);
+                                    to_usd(g["gain"].as_f64().unwrap_or(0.0), currency, client_id)
+                                })
+                                .sum()
+                        })
+                        .unwrap_or(0.0);
+                    let lot_method = client["lot_method"].as_str().unwrap_or("Fifo").to_string();
+
+                    let account_type: AccountType =
+                        serde_json::from_value(client["account_type"].clone()).unwrap_or_default();
+                    let margin = margin_requirements.status(capital, &marked_positions);
+                    let margin = MarginStatus {
+                        equity: to_base(margin.equity),
+                        initial_requirement: to_base(margin.initial_requirement),
+                        maintenance_requirement: to_base(margin.maintenance_requirement),
+                    };
+
+                    let total_value = market_value + capital;
+                    let pnl = total_value - INITIAL_CAPITAL; // P&L calculation, after costs (capital is net of fees)
 
                     // Step 4: Add to the report
                     reports.push(ClientPerformance {
                         client_id,
-                        total_investment,
-                        remaining_capital: capital,
+                        total_investment: to_base(total_investment),
+                        market_value: to_base(market_value),
+                        remaining_capital: to_base(capital),
+                        cash_balances,
                         total_transactions: buy_count + sell_count,
                         portfolio,
-                        total_value,
-                        pnl,
+                        total_value: to_base(total_value),
+                        pnl: to_base(pnl),
+                        commissions: to_base(commissions),
+                        exchange_fees: to_base(exchange_fees),
+                        borrow_fees: to_base(borrow_fees),
+                        dividends: to_base(dividends),
+                        realized_gain: to_base(realized_gain),
+                        lot_method,
+                        rejections,
+                        account_type,
+                        margin,
                     });
                 }
             }
@@ -69,35 +190,158 @@ pub fn generate_client_report(json_file_path: &str) {
 
     // Step 5: Display the report
     println!("========== Client Performance Report ==========");
+    println!("Base Currency: {}", base_currency);
     for report in reports {
         println!("Client ID: {}", report.client_id);
         println!("  Total Investment: {:.2}", report.total_investment);
         println!("  Remaining Capital: {:.2}", report.remaining_capital);
+        if report.cash_balances.len() > 1 {
+            println!("  Cash Balances:");
+            for (currency, amount) in &report.cash_balances {
+                println!("    - {}: {:.2}", currency, amount);
+            }
+        }
         println!("  Total Transactions: {}", report.total_transactions);
 
         println!("  Portfolio:");
         for (stock, (quantity, avg_price)) in &report.portfolio {
+            let side = if *quantity < 0 { "short" } else { "long" };
             println!(
-                "    - {}: {} shares at {:.2} average price",
-                stock, quantity, avg_price
+                "    - {}: {} shares {} at {:.2} {} average price",
+                stock, quantity.abs(), side, avg_price, instrument_currency(stock)
             );
         }
 
-        println!("  Total Value (Investment + Capital): {:.2}", report.total_value);
+        println!("  Market Value (Official Close): {:.2}", report.market_value);
+        println!("  Total Value (Market Value + Capital): {:.2}", report.total_value);
+
+        println!("  Commissions Paid: {:.2}", report.commissions);
+        println!("  Exchange Fees (Net of Rebates): {:.2}", report.exchange_fees);
+        println!("  Borrow Fees: {:.2}", report.borrow_fees);
+        println!("  Dividends Received: {:.2}", report.dividends);
+        println!("  Realized Gains ({} lots): {:.2}", report.lot_method, report.realized_gain);
+        println!(
+            "  P&L Before Costs: {:.2}",
+            report.pnl + report.commissions + report.exchange_fees + report.borrow_fees
+        );
 
         // Display P&L with color
         if report.pnl >= 0.0 {
             println!(
-                "  Profit & Loss (P&L): {}",
+                "  Profit & Loss (P&L, After Costs): {}",
                 format!("+{:.2}", report.pnl).green()
             );
         } else {
             println!(
-                "  Profit & Loss (P&L): {}",
+                "  Profit & Loss (P&L, After Costs): {}",
                 format!("{:.2}", report.pnl).red()
             );
         }
 
+        println!("  Account Type: {:?}", report.account_type);
+        if report.account_type == AccountType::Margin {
+            let margin_status = if report.margin.is_margin_call() {
+                "MARGIN CALL".red()
+            } else {
+                "OK".green()
+            };
+            println!(
+                "  Margin: Equity {:.2}, Initial {:.2}, Maintenance {:.2}, Excess {:.2} [{}]",
+                report.margin.equity,
+                report.margin.initial_requirement,
+                report.margin.maintenance_requirement,
+                report.margin.excess(),
+                margin_status
+            );
+        }
+
+        if !report.rejections.is_empty() {
+            println!("  Rejected Orders:");
+            for (reason, count) in &report.rejections {
+                println!("    - {}: {}", reason, count);
+            }
+        }
+
         println!("----------------------------------------------");
     }
+
+    // Step 6: Rejections across all clients
+    println!("========== Rejected Orders by Reason ==========");
+    let mut total_rejections: Vec<(String, u64)> = total_rejections.into_iter().collect();
+    total_rejections.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+    for (reason, count) in total_rejections {
+        println!("  {}: {}", reason, count.to_string().yellow());
+    }
+
+    // Step 7: Market activity from the public trade tape
+    println!("========== Trade Tape ==========");
+    let mut tape: Vec<_> = tape.into_iter().collect();
+    tape.sort_by(|a, b| a.0.cmp(&b.0));
+    for (stock, summary) in tape {
+        println!(
+            "  {}: {} trades, {} shares, VWAP {:.2}, last {:.2} {}",
+            stock, summary.trades, summary.volume, summary.vwap(), summary.last, instrument_currency(&stock)
+        );
+    }
+
+    // Step 8: Session range per symbol from the finest stored bars
+    println!("========== Session Bars ==========");
+    let signal_interval_secs = BarConfig::load(BAR_CONFIG_PATH).signal_interval_secs();
+    let mut ranges: HashMap<String, (u64, f64, f64, f64, f64)> = HashMap::new(); // Bars, open, high, low, close
+    for bar in load_bars(BARS_PATH).into_iter().filter(|bar| bar.interval_secs == signal_interval_secs) {
+        let range = ranges
+            .entry(bar.stock_symbol.clone())
+            .or_insert((0, bar.open, bar.high, bar.low, bar.close));
+        range.0 += 1;
+        range.2 = range.2.max(bar.high);
+        range.3 = range.3.min(bar.low);
+        range.4 = bar.close;
+    }
+    let mut ranges: Vec<_> = ranges.into_iter().collect();
+    ranges.sort_by(|a, b| a.0.cmp(&b.0));
+    for (stock, (count, open, high, low, close)) in ranges {
+        println!(
+            "  {}: {} x {}s bars, O {:.2} H {:.2} L {:.2} C {:.2}",
+            stock, count, signal_interval_secs, open, high, low, close
+        );
+    }
+}
+
+// Write every closed lot to a CSV file, one row per realized-gain record
+pub fn export_closed_lots(json_file_path: &str, output_path: &str) {
+    let json_data = fs::read_to_string(json_file_path)
+        .expect("Failed to read client data JSON file.");
+    let data: Value = serde_json::from_str(&json_data)
+        .expect("Failed to parse JSON data.");
+
+    let mut csv = String::from(
+        "broker_id,client_id,stock_symbol,method,quantity,open_price,close_price,gain,currency,opened_at,closed_at\n",
+    );
+    let mut rows = 0;
+    for broker in data["brokers"].as_array().into_iter().flatten() {
+        for client in broker["clients"].as_array().into_iter().flatten() {
+            for gain in client["realized_gains"].as_array().into_iter().flatten() {
+                csv.push_str(&format!(
+                    "{},{},{},{},{},{},{},{},{},{},{}\n",
+                    broker["broker_id"],
+                    client["client_id"],
+                    gain["stock_symbol"].as_str().unwrap_or(""),
+                    gain["method"].as_str().unwrap_or(""),
+                    gain["quantity"],
+                    gain["open_price"],
+                    gain["close_price"],
+                    gain["gain"],
+                    gain["currency"].as_str().unwrap_or(""),
+                    gain["opened_at"].as_str().unwrap_or(""),
+                    gain["closed_at"].as_str().unwrap_or(""),
+                ));
+                rows += 1;
+            }
+        }
+    }
+
+    match fs::File::create(output_path).and_then(|mut f| f.write_all(csv.as_bytes())) {
+        Ok(()) => println!("Exported {} closed lots to {}", rows, output_path),
+        Err(e) => println!("Error writing closed lots to {}: {}", output_path, e),
+    }
 }
@@ -0,0 +1,442 @@
+// reconciliation.rs
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use colored::*;
+use crate::broker::data::load_positions;
+use crate::corporate_actions::split_quantity;
+use crate::models::{Order, OrderAction};
+use crate::money::Money;
+
+// Fills the matcher emitted on completed_order (exchange side)
+pub const EXCHANGE_FILLS_PATH: &str = "src/data/exchange_fills.jsonl";
+// Fills order_status_receiver applied to client holdings (trading side)
+pub const APPLIED_FILLS_PATH: &str = "src/data/applied_fills.jsonl";
+pub const RECONCILIATION_BREAKS_PATH: &str = "src/data/reconciliation_breaks.json";
+// Client positions in the holdings file when the session started
+pub const START_POSITIONS_PATH: &str = "src/data/start_positions.json";
+pub const POSITION_BREAKS_PATH: &str = "src/data/position_breaks.json";
+
+// One fill as seen by either side of the journal
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FillRecord {
+    pub broker_id: u64,
+    pub client_id: u64,
+    pub order_id: String,
+    pub stock_symbol: String,
+    pub quantity: i64, // Negative for sells
+    pub price: Money,
+}
+
+impl FillRecord {
+    pub fn from_order(order: &Order) -> Self {
+        let quantity = order.quantity as i64;
+        FillRecord {
+            broker_id: order.broker_id,
+            client_id: order.client_id,
+            order_id: order.order_id.clone(),
+            stock_symbol: order.stock_symbol.clone(),
+            quantity: if order.order_action == OrderAction::Sell { -quantity } else { quantity },
+            price: order.price,
+        }
+    }
+}
+
+// A split applied to the holdings, journalled between the applied fills so positions replay in order
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SplitRecord {
+    pub stock_symbol: String,
+    pub ratio: f64,
+}
+
+// One line of a journal. Fill lines are tried first, so journals of fills alone read as before.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum JournalEntry {
+    Fill(FillRecord),
+    Split(SplitRecord),
+}
+
+// A client's share count at the start of the session
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PositionRecord {
+    pub client_id: u64,
+    pub stock_symbol: String,
+    pub quantity: i64,
+}
+
+// A client/symbol where the two sides disagree
+#[derive(Serialize, Debug)]
+pub struct Break {
+    pub client_id: u64,
+    pub stock_symbol: String,
+    pub exchange_quantity: i64,
+    pub applied_quantity: i64,
+    pub exchange_notional: Money,
+    pub applied_notional: Money,
+    pub missing_order_ids: Vec<String>,    // Filled by the exchange, never applied
+    pub unexpected_order_ids: Vec<String>, // Applied with no matching exchange fill
+}
+
+// A client/symbol whose holdings don't match the start position carried through the applied journal
+#[derive(Serialize, Debug, PartialEq)]
+pub struct PositionBreak {
+    pub client_id: u64,
+    pub stock_symbol: String,
+    pub start_quantity: i64,
+    pub journal_quantity: i64, // Start position plus applied fills, split as the session went
+    pub holdings_quantity: i64,
+}
+
+// Each session starts with an empty journal
+pub fn reset_fill_journal(path: &str) {
+    if let Err(e) = fs::write(path, "") {
+        println!("Error resetting fill journal {}: {}", path, e);
+    }
+}
+
+pub fn append_fill(path: &str, order: &Order) {
+    append_entry(path, &JournalEntry::Fill(FillRecord::from_order(order)));
+}
+
+pub fn append_split(path: &str, stock_symbol: &str, ratio: f64) {
+    append_entry(path, &JournalEntry::Split(SplitRecord { stock_symbol: stock_symbol.to_string(), ratio }));
+}
+
+fn append_entry(path: &str, entry: &JournalEntry) {
+    let line = serde_json::to_string(entry).expect("Failed to serialize journal entry");
+    let result = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| writeln!(file, "{}", line));
+    if let Err(e) = result {
+        println!("Error appending to fill journal {}: {}", path, e);
+    }
+}
+
+pub fn load_fills(path: &str) -> Vec<FillRecord> {
+    fills(&load_journal(path))
+}
+
+fn fills(journal: &[JournalEntry]) -> Vec<FillRecord> {
+    journal
+        .iter()
+        .filter_map(|entry| match entry {
+            JournalEntry::Fill(fill) => Some(fill.clone()),
+            JournalEntry::Split(_) => None,
+        })
+        .collect()
+}
+
+pub fn load_journal(path: &str) -> Vec<JournalEntry> {
+    load_lines(path)
+}
+
+fn load_lines<T: DeserializeOwned>(path: &str) -> Vec<T> {
+    let Ok(content) = fs::read_to_string(path) else {
+        return Vec::new();
+    };
+    content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| match serde_json::from_str(line) {
+            Ok(record) => Some(record),
+            Err(e) => {
+                println!("Skipping unreadable journal entry in {}: {}", path, e);
+                None
+            }
+        })
+        .collect()
+}
+
+#[derive(Default)]
+struct Side {
+    quantity: i64,
+    notional: Money,
+    order_ids: HashMap<String, u32>, // Order ID -> Fill count
+}
+
+fn tally(fills: &[FillRecord]) -> BTreeMap<(u64, String), Side> {
+    let mut sides: BTreeMap<(u64, String), Side> = BTreeMap::new();
+    for fill in fills {
+        let side = sides.entry((fill.client_id, fill.stock_symbol.clone())).or_default();
+        side.quantity += fill.quantity;
+        side.notional += fill.price.times(fill.quantity);
+        *side.order_ids.entry(fill.order_id.clone()).or_insert(0) += 1;
+    }
+    sides
+}
+
+// Compare net quantity and notional per client and symbol, listing the fills behind any difference
+pub fn reconcile(exchange_fills: &[FillRecord], applied_fills: &[FillRecord]) -> Vec<Break> {
+    let exchange = tally(exchange_fills);
+    let applied = tally(applied_fills);
+    let empty = Side::default();
+
+    let mut keys: Vec<&(u64, String)> = exchange.keys().chain(applied.keys()).collect();
+    keys.sort();
+    keys.dedup();
+
+    let mut breaks = Vec::new();
+    for key in keys {
+        let exchange_side = exchange.get(key).unwrap_or(&empty);
+        let applied_side = applied.get(key).unwrap_or(&empty);
+        if exchange_side.quantity == applied_side.quantity && exchange_side.notional == applied_side.notional {
+            continue;
+        }
+
+        // An order counts once per fill, so duplicated or dropped fills show up here too
+        let count_difference = |from: &Side, against: &Side| {
+            let mut order_ids: Vec<String> = from
+                .order_ids
+                .iter()
+                .filter(|(order_id, count)| against.order_ids.get(*order_id).copied().unwrap_or(0) < **count)
+                .map(|(order_id, _)| order_id.clone())
+                .collect();
+            order_ids.sort();
+            order_ids
+        };
+
+        breaks.push(Break {
+            client_id: key.0,
+            stock_symbol: key.1.clone(),
+            exchange_quantity: exchange_side.quantity,
+            applied_quantity: applied_side.quantity,
+            exchange_notional: exchange_side.notional,
+            applied_notional: applied_side.notional,
+            missing_order_ids: count_difference(exchange_side, applied_side),
+            unexpected_order_ids: count_difference(applied_side, exchange_side),
+        });
+    }
+    breaks
+}
+
+// Record the holdings' positions before any of the session's fills are applied
+pub fn snapshot_positions(holdings_path: &str) {
+    let positions: Vec<PositionRecord> = load_positions(holdings_path)
+        .into_iter()
+        .map(|((client_id, stock_symbol), quantity)| PositionRecord { client_id, stock_symbol, quantity })
+        .collect();
+    let json_data = serde_json::to_string_pretty(&positions).expect("Failed to serialize start positions");
+    if let Err(e) = fs::write(START_POSITIONS_PATH, json_data) {
+        println!("Error writing start positions: {}", e);
+    }
+}
+
+fn load_start_positions(path: &str) -> BTreeMap<(u64, String), i64> {
+    let positions: Vec<PositionRecord> = match fs::read_to_string(path).map(|json| serde_json::from_str(&json)) {
+        Ok(Ok(positions)) => positions,
+        _ => {
+            println!("No start positions in {}, reconciling from flat", path);
+            Vec::new()
+        }
+    };
+    positions
+        .into_iter()
+        .map(|position| ((position.client_id, position.stock_symbol), position.quantity))
+        .collect()
+}
+
+// Carry the start positions through a journal in order; fractional shares from a
+// split are paid as cash in lieu, so they are dropped here as they are in the holdings
+pub fn derive_positions(
+    start: &BTreeMap<(u64, String), i64>,
+    journal: &[JournalEntry],
+) -> BTreeMap<(u64, String), i64> {
+    let mut positions = start.clone();
+    for entry in journal {
+        match entry {
+            JournalEntry::Fill(fill) => {
+                *positions.entry((fill.client_id, fill.stock_symbol.clone())).or_insert(0) += fill.quantity;
+            }
+            JournalEntry::Split(split) => {
+                for ((_, stock_symbol), quantity) in positions.iter_mut() {
+                    if *stock_symbol == split.stock_symbol {
+                        *quantity = split_quantity(*quantity, split.ratio).0;
+                    }
+                }
+            }
+        }
+    }
+    positions.retain(|_, quantity| *quantity != 0);
+    positions
+}
+
+// Compare the journal-derived positions with the holdings file, both ways
+pub fn reconcile_positions(
+    start: &BTreeMap<(u64, String), i64>,
+    journal_positions: &BTreeMap<(u64, String), i64>,
+    holdings_positions: &BTreeMap<(u64, String), i64>,
+) -> Vec<PositionBreak> {
+    let mut keys: Vec<&(u64, String)> = journal_positions.keys().chain(holdings_positions.keys()).collect();
+    keys.sort();
+    keys.dedup();
+    keys.into_iter()
+        .filter_map(|key| {
+            let journal_quantity = journal_positions.get(key).copied().unwrap_or(0);
+            let holdings_quantity = holdings_positions.get(key).copied().unwrap_or(0);
+            (journal_quantity != holdings_quantity).then(|| PositionBreak {
+                client_id: key.0,
+                stock_symbol: key.1.clone(),
+                start_quantity: start.get(key).copied().unwrap_or(0),
+                journal_quantity,
+                holdings_quantity,
+            })
+        })
+        .collect()
+}
+
+// End-of-day job: reconcile the session's journals and the resulting positions, print the breaks and save them
+pub fn run_end_of_day_reconciliation(holdings_path: &str) {
+    let exchange_fills = load_fills(EXCHANGE_FILLS_PATH);
+    let applied_journal = load_journal(APPLIED_FILLS_PATH);
+    let applied_fills = fills(&applied_journal);
+    let breaks = reconcile(&exchange_fills, &applied_fills);
+
+    println!("========== End-of-Day Reconciliation ==========");
+    println!(
+        "  Exchange fills: {}, Applied fills: {}",
+        exchange_fills.len(),
+        applied_fills.len()
+    );
+    if breaks.is_empty() {
+        println!("  {}", "No breaks: all fills applied".green());
+    }
+    for b in &breaks {
+        println!(
+            "  {} Client {} {}: exchange {} shares ({:.2}), applied {} shares ({:.2})",
+            "BREAK".red().bold(),
+            b.client_id,
+            b.stock_symbol,
+            b.exchange_quantity,
+            b.exchange_notional,
+            b.applied_quantity,
+            b.applied_notional
+        );
+        if !b.missing_order_ids.is_empty() {
+            println!("    - Not applied: {}", b.missing_order_ids.join(", "));
+        }
+        if !b.unexpected_order_ids.is_empty() {
+            println!("    - Applied without exchange fill: {}", b.unexpected_order_ids.join(", "));
+        }
+    }
+
+    let json_data = serde_json::to_string_pretty(&breaks).expect("Failed to serialize reconciliation breaks");
+    if let Err(e) = fs::write(RECONCILIATION_BREAKS_PATH, json_data) {
+        println!("Error writing reconciliation breaks: {}", e);
+    }
+
+    // Positions: what the applied fills and splits say each client should hold against what the holdings
+    // file says. Fills the exchange made but the holdings never took are the fill breaks above.
+    let start_positions = load_start_positions(START_POSITIONS_PATH);
+    let journal_positions = derive_positions(&start_positions, &applied_journal);
+    let holdings_positions = load_positions(holdings_path);
+    let position_breaks = reconcile_positions(&start_positions, &journal_positions, &holdings_positions);
+    if position_breaks.is_empty() {
+        println!("  {}", "No position breaks: holdings match the journal".green());
+    }
+    for b in &position_breaks {
+        println!(
+            "  {} Client {} {}: started at {}, journal says {} shares, holdings say {}",
+            "POSITION BREAK".red().bold(),
+            b.client_id,
+            b.stock_symbol,
+            b.start_quantity,
+            b.journal_quantity,
+            b.holdings_quantity
+        );
+    }
+
+    let json_data = serde_json::to_string_pretty(&position_breaks).expect("Failed to serialize position breaks");
+    if let Err(e) = fs::write(POSITION_BREAKS_PATH, json_data) {
+        println!("Error writing position breaks: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fill(client_id: u64, order_id: &str, stock_symbol: &str, quantity: i64) -> JournalEntry {
+        JournalEntry::Fill(FillRecord {
+            broker_id: 1,
+            client_id,
+            order_id: order_id.to_string(),
+            stock_symbol: stock_symbol.to_string(),
+            quantity,
+            price: Money::from_f64(100.0),
+        })
+    }
+
+    fn split(stock_symbol: &str, ratio: f64) -> JournalEntry {
+        JournalEntry::Split(SplitRecord { stock_symbol: stock_symbol.to_string(), ratio })
+    }
+
+    fn positions(entries: &[(u64, &str, i64)]) -> BTreeMap<(u64, String), i64> {
+        entries.iter().map(|&(client_id, stock_symbol, quantity)| ((client_id, stock_symbol.to_string()), quantity)).collect()
+    }
+
+    #[test]
+    fn splits_apply_only_to_fills_before_them() {
+        let start = positions(&[(1, "NVDA", 10), (2, "AMZN", 3)]);
+        let journal = [fill(1, "a", "NVDA", 5), split("NVDA", 4.0), fill(1, "b", "NVDA", -20), split("AMZN", 1.5)];
+        // (10 + 5) * 4 - 20 = 40; 3 * 1.5 = 4.5, the half share paid as cash
+        assert_eq!(derive_positions(&start, &journal), positions(&[(1, "NVDA", 40), (2, "AMZN", 4)]));
+    }
+
+    #[test]
+    fn closed_positions_drop_out() {
+        let start = positions(&[(1, "KO", 5)]);
+        assert!(derive_positions(&start, &[fill(1, "a", "KO", -5)]).is_empty());
+    }
+
+    #[test]
+    fn holdings_that_disagree_break_both_ways() {
+        let start = positions(&[(1, "KO", 5)]);
+        let journal = positions(&[(1, "KO", 8), (2, "XOM", 1)]);
+        let holdings = positions(&[(1, "KO", 8), (3, "BP", 2)]);
+        let breaks = reconcile_positions(&start, &journal, &holdings);
+        assert_eq!(
+            breaks,
+            vec![
+                PositionBreak { client_id: 2, stock_symbol: "XOM".to_string(), start_quantity: 0, journal_quantity: 1, holdings_quantity: 0 },
+                PositionBreak { client_id: 3, stock_symbol: "BP".to_string(), start_quantity: 0, journal_quantity: 0, holdings_quantity: 2 },
+            ]
+        );
+    }
+
+    #[test]
+    fn dropped_and_duplicated_fills_break_with_their_order_ids() {
+        let exchange = fills(&[fill(1, "a", "KO", 5), fill(1, "b", "KO", 3), fill(2, "c", "XOM", -2)]);
+        let applied = fills(&[fill(1, "a", "KO", 5), fill(2, "c", "XOM", -2), fill(2, "c", "XOM", -2)]);
+        let breaks = reconcile(&exchange, &applied);
+
+        assert_eq!(breaks.len(), 2);
+        assert_eq!((breaks[0].client_id, breaks[0].stock_symbol.as_str()), (1, "KO"));
+        assert_eq!((breaks[0].exchange_quantity, breaks[0].applied_quantity), (8, 5));
+        assert_eq!(breaks[0].missing_order_ids, vec!["b".to_string()]);
+        assert!(breaks[0].unexpected_order_ids.is_empty());
+        assert_eq!((breaks[1].client_id, breaks[1].stock_symbol.as_str()), (2, "XOM"));
+        assert_eq!((breaks[1].exchange_quantity, breaks[1].applied_quantity), (-2, -4));
+        assert!(breaks[1].missing_order_ids.is_empty());
+        assert_eq!(breaks[1].unexpected_order_ids, vec!["c".to_string()]);
+    }
+
+    #[test]
+    fn matching_journals_have_no_breaks() {
+        let journal = fills(&[fill(1, "a", "KO", 5), split("KO", 2.0), fill(1, "b", "KO", -3)]);
+        assert!(reconcile(&journal, &journal).is_empty());
+    }
+
+    #[test]
+    fn fill_only_journal_lines_still_read_as_fills() {
+        let line = r#"{"broker_id":1,"client_id":2,"order_id":"x","stock_symbol":"KO","quantity":-3,"price":61.5}"#;
+        assert!(matches!(serde_json::from_str::<JournalEntry>(line), Ok(JournalEntry::Fill(fill)) if fill.quantity == -3));
+        let line = r#"{"stock_symbol":"NVDA","ratio":4.0}"#;
+        assert!(matches!(serde_json::from_str::<JournalEntry>(line), Ok(JournalEntry::Split(_))));
+    }
+}
@@ -2,14 +2,22 @@ use colored::Colorize;
 use rdkafka::config::ClientConfig;
 use rdkafka::consumer::{Consumer, StreamConsumer};
 use rdkafka::Message;
-use serde_json;
 use tokio::time::timeout;
 use std::collections::HashMap;
 use std::fs::{self};
 use std::time::Duration;
 use tokio_stream::StreamExt;
-use crate::models::PriceUpdate;
+use crate::broker::data::apply_corporate_action_in_json;
+use crate::currency::FX_RATES_PATH;
+use crate::dead_letter::{commit_or_rewind, dead_letter_producer, send_to_dead_letter};
+use crate::market_data::QUOTE_TOPIC;
+use crate::money::Money;
+use crate::models::{CorporateAction, FxRate, HaltEvent, OrderAction, PriceUpdate, Quote, Trade};
+use crate::tick_store::{Side, TickEvent, TickRecord, TickWriter, TICK_STORE_DIR};
+use crate::trade_tape::TRADE_TOPIC;
+use crate::wire;
 
+// Last price per symbol as of the end of the latest session; the tick store has the full history
 const JSON_FILE_PATH: &str = "src/data/price_store.json";
 
 pub async fn run_consumer(price_tx: tokio::sync::broadcast::Sender<PriceUpdate>) {
@@ -18,7 +26,7 @@ pub async fn run_consumer(price_tx: tokio::sync::broadcast::Sender<PriceUpdate>)
         .set("group.id", "stock-price-consumer-group")
         .set("bootstrap.servers", "localhost:9092")
         .set("auto.offset.reset", "latest")
-        .set("enable.auto.commit", "true")
+        .set("enable.auto.commit", "false")
         .create()
         .expect("Consumer creation failed");
 
@@ -28,33 +36,39 @@ pub async fn run_consumer(price_tx: tokio::sync::broadcast::Sender<PriceUpdate>)
     //println!("Consumer started, waiting for messages...");
 
     let mut message_stream = consumer.stream();
+    let dlq_producer = dead_letter_producer();
+    let mut last_prices: HashMap<String, f64> = HashMap::new();
 
     // Continuously consume message
     let result = timeout(Duration::from_secs(50), async {
     while let Some(message) = message_stream.next().await { 
         match message {
             Ok(m) => {
+                let mut parked = Ok(());
                 if let Some(payload) = m.payload() {
-                    println!("{}", format!("Stock: {}", String::from_utf8_lossy(payload)).bold().white());
-
                     // Deserialize the payload into a Vec<PriceUpdate>
-                    match serde_json::from_slice::<PriceUpdate>(payload) {
+                    match wire::decode::<PriceUpdate>(payload) {
                         Ok(price_update) => {
+                            println!(
+                                "{}",
+                                format!("Stock: {} {:.2} {}", price_update.name, price_update.price, price_update.currency).bold().white()
+                            );
                             //println!("Received Price Update: Stock: {}, Price: {:.2}", price_update.name, price_update.price);
 
                             // Broadcast to brokers
                             if let Err(e) = price_tx.send(price_update.clone()) {
                                 eprintln!("Failed to broadcast price update: {:?}", e);
                             }
-                            // Update the JSON file with the new price
-                            update_json_file(&price_update).await;
+                            last_prices.insert(price_update.name, price_update.price);
                         }
                         Err(e) => {
-                            eprintln!("Error deserializing price update: {:?}", e);
+                            eprintln!("Error deserializing price update ({}): {}", wire::describe_payload(payload), e);
+                            parked = send_to_dead_letter(&dlq_producer, &m, &format!("Error deserializing price update: {}", e)).await;
                         }
                     }
 
                 }
+                commit_or_rewind(&consumer, &m, parked);
             }
             Err(e) => {
                 eprintln!("Error while consuming from stream: {:?}", e);
@@ -64,13 +78,346 @@ pub async fn run_consumer(price_tx: tokio::sync::broadcast::Sender<PriceUpdate>)
 });
 
     // Handle timeout
-    if result.await.is_err() {
+    let timed_out = result.await.is_err();
+    // Written once at the end rather than rewritten for every price
+    save_last_prices(&last_prices);
+    if timed_out {
         println!("Stopping Kafka consumer.");
     }
 }
 
-// Update the JSON file with new prices
-async fn update_json_file(price_update: &PriceUpdate) {
+// Forward top-of-book quotes from the matcher to the brokers
+pub async fn run_quote_consumer(quote_tx: tokio::sync::broadcast::Sender<Quote>) {
+    let consumer: StreamConsumer = ClientConfig::new()
+        .set("group.id", "quote-consumer-group")
+        .set("bootstrap.servers", "localhost:9092")
+        .set("auto.offset.reset", "latest")
+        .set("enable.auto.commit", "false")
+        .create()
+        .expect("Consumer creation failed");
+
+    consumer.subscribe(&[QUOTE_TOPIC]).expect("Can't subscribe to specified topic");
+
+    let mut message_stream = consumer.stream();
+    let dlq_producer = dead_letter_producer();
+
+    let result = timeout(Duration::from_secs(50), async {
+        while let Some(message) = message_stream.next().await {
+            match message {
+                Ok(m) => {
+                    let mut parked = Ok(());
+                    if let Some(payload) = m.payload() {
+                        match wire::decode::<Quote>(payload) {
+                            Ok(quote) => {
+                                // No subscribers just means no broker is running yet
+                                let _ = quote_tx.send(quote);
+                            }
+                            Err(e) => {
+                                eprintln!("Error deserializing quote: {:?}", e);
+                                parked = send_to_dead_letter(&dlq_producer, &m, &format!("Error deserializing quote: {}", e)).await;
+                            }
+                        }
+                    }
+                    commit_or_rewind(&consumer, &m, parked);
+                }
+                Err(e) => {
+                    eprintln!("Error while consuming quotes: {:?}", e);
+                }
+            }
+        }
+    });
+
+    if result.await.is_err() {
+        println!("Stopping quote consumer.");
+    }
+}
+
+// Forward the public trade tape to the brokers
+pub async fn run_trade_consumer(trade_tx: tokio::sync::broadcast::Sender<Trade>) {
+    // Trades are produced in the matcher's transactions, so only committed ones are read
+    let consumer: StreamConsumer = ClientConfig::new()
+        .set("group.id", "trade-consumer-group")
+        .set("bootstrap.servers", "localhost:9092")
+        .set("auto.offset.reset", "latest")
+        .set("enable.auto.commit", "false")
+        .set("isolation.level", "read_committed")
+        .create()
+        .expect("Consumer creation failed");
+
+    consumer.subscribe(&[TRADE_TOPIC]).expect("Can't subscribe to specified topic");
+
+    let mut message_stream = consumer.stream();
+    let dlq_producer = dead_letter_producer();
+
+    let result = timeout(Duration::from_secs(50), async {
+        while let Some(message) = message_stream.next().await {
+            match message {
+                Ok(m) => {
+                    let mut parked = Ok(());
+                    if let Some(payload) = m.payload() {
+                        match wire::decode::<Trade>(payload) {
+                            Ok(trade) => {
+                                println!("{}", format!("Trade: {} {} @ {:.2}", trade.stock_symbol, trade.quantity, trade.price).bold().cyan());
+                                let _ = trade_tx.send(trade);
+                            }
+                            Err(e) => {
+                                eprintln!("Error deserializing trade: {:?}", e);
+                                parked = send_to_dead_letter(&dlq_producer, &m, &format!("Error deserializing trade: {}", e)).await;
+                            }
+                        }
+                    }
+                    commit_or_rewind(&consumer, &m, parked);
+                }
+                Err(e) => {
+                    eprintln!("Error while consuming trades: {:?}", e);
+                }
+            }
+        }
+    });
+
+    if result.await.is_err() {
+        println!("Stopping trade consumer.");
+    }
+}
+
+// How often buffered ticks are written out to the tick store
+const TICK_FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+// Append every price update, quote and trade to the tick store
+pub async fn run_tick_recorder() {
+    // Trades are transactional, so only committed ones are recorded
+    let consumer: StreamConsumer = ClientConfig::new()
+        .set("group.id", "tick-recorder-group")
+        .set("bootstrap.servers", "localhost:9092")
+        .set("auto.offset.reset", "latest")
+        .set("enable.auto.commit", "true")
+        .set("isolation.level", "read_committed")
+        .create()
+        .expect("Consumer creation failed");
+
+    consumer.subscribe(&["stock", QUOTE_TOPIC, TRADE_TOPIC]).expect("Can't subscribe to specified topic");
+
+    let mut writer = match TickWriter::open(TICK_STORE_DIR) {
+        Ok(writer) => writer,
+        Err(e) => {
+            eprintln!("Error opening tick store {}: {}", TICK_STORE_DIR, e);
+            return;
+        }
+    };
+    let mut flush_ticker = tokio::time::interval(TICK_FLUSH_INTERVAL);
+
+    let result = timeout(Duration::from_secs(50), async {
+        loop {
+            tokio::select! {
+                _ = flush_ticker.tick() => {
+                    if let Err(e) = writer.flush() {
+                        eprintln!("Error flushing tick store: {}", e);
+                    }
+                }
+                message = consumer.recv() => {
+                    let m = match message {
+                        Ok(m) => m,
+                        Err(e) => {
+                            eprintln!("Error while consuming ticks: {:?}", e);
+                            continue;
+                        }
+                    };
+                    let Some(payload) = m.payload() else { continue };
+                    // Every record is stamped with its Kafka message timestamp, so prices, quotes and trades
+                    // from different producers share one clock and can be interleaved by time
+                    let produced_ms = m.timestamp().to_millis().unwrap_or_else(|| chrono::Utc::now().timestamp_millis());
+                    let tick = match m.topic() {
+                        "stock" => wire::decode::<PriceUpdate>(payload).ok().map(|update| {
+                            (update.name, TickRecord { timestamp_ms: produced_ms, event: TickEvent::Price { price: update.price } })
+                        }),
+                        QUOTE_TOPIC => wire::decode::<Quote>(payload).ok().map(|quote| {
+                            let event = TickEvent::Quote {
+                                bid: quote.bid.map(Money::to_f64),
+                                bid_size: quote.bid_size,
+                                ask: quote.ask.map(Money::to_f64),
+                                ask_size: quote.ask_size,
+                            };
+                            (quote.stock_symbol, TickRecord { timestamp_ms: produced_ms, event })
+                        }),
+                        _ => wire::decode::<Trade>(payload).ok().map(|trade| {
+                            let event = TickEvent::Trade {
+                                price: trade.price,
+                                quantity: trade.quantity,
+                                aggressor: trade.aggressor.map(|action| if action == OrderAction::Buy { Side::Buy } else { Side::Sell }),
+                            };
+                            (trade.stock_symbol, TickRecord { timestamp_ms: produced_ms, event })
+                        }),
+                    };
+                    // Bad payloads are dead-lettered by the consumers that own these topics
+                    if let Some((stock_symbol, record)) = tick {
+                        if let Err(e) = writer.append(&stock_symbol, &record) {
+                            eprintln!("Error appending to tick store for {}: {}", stock_symbol, e);
+                        }
+                    }
+                }
+            }
+        }
+    });
+
+    let timed_out = result.await.is_err();
+    if let Err(e) = writer.flush() {
+        eprintln!("Error flushing tick store: {}", e);
+    }
+    if timed_out {
+        println!("Stopping tick recorder.");
+    }
+}
+
+// Forward circuit breaker halts and resumes to the brokers
+pub async fn run_halt_consumer(halt_tx: tokio::sync::broadcast::Sender<HaltEvent>) {
+    let consumer: StreamConsumer = ClientConfig::new()
+        .set("group.id", "halt-consumer-group")
+        .set("bootstrap.servers", "localhost:9092")
+        .set("auto.offset.reset", "latest")
+        .set("enable.auto.commit", "false")
+        .create()
+        .expect("Consumer creation failed");
+
+    consumer.subscribe(&["halts"]).expect("Can't subscribe to specified topic");
+
+    let mut message_stream = consumer.stream();
+    let dlq_producer = dead_letter_producer();
+
+    let result = timeout(Duration::from_secs(50), async {
+        while let Some(message) = message_stream.next().await {
+            match message {
+                Ok(m) => {
+                    let mut parked = Ok(());
+                    if let Some(payload) = m.payload() {
+                        match wire::decode::<HaltEvent>(payload) {
+                            Ok(event) => {
+                                println!("{}", format!("Trading {:?}: {} ({})", event.status, event.stock_symbol, event.reason).bold().yellow());
+                                if let Err(e) = halt_tx.send(event) {
+                                    eprintln!("Failed to broadcast halt event: {:?}", e);
+                                }
+                            }
+                            Err(e) => {
+                                eprintln!("Error deserializing halt event: {:?}", e);
+                                parked = send_to_dead_letter(&dlq_producer, &m, &format!("Error deserializing halt event: {}", e)).await;
+                            }
+                        }
+                    }
+                    commit_or_rewind(&consumer, &m, parked);
+                }
+                Err(e) => {
+                    eprintln!("Error while consuming from stream: {:?}", e);
+                }
+            }
+        }
+    });
+
+    if result.await.is_err() {
+        println!("Stopping halt consumer.");
+    }
+}
+
+// Apply splits and dividends to client holdings on their ex-date
+pub async fn run_corporate_action_consumer(json_file_path: &str) {
+    let consumer: StreamConsumer = ClientConfig::new()
+        .set("group.id", "corporate-action-consumer-group")
+        .set("bootstrap.servers", "localhost:9092")
+        .set("auto.offset.reset", "latest")
+        .set("enable.auto.commit", "false")
+        .create()
+        .expect("Consumer creation failed");
+
+    consumer.subscribe(&["corporate_actions"]).expect("Can't subscribe to specified topic");
+
+    let mut message_stream = consumer.stream();
+    let dlq_producer = dead_letter_producer();
+
+    let result = timeout(Duration::from_secs(50), async {
+        while let Some(message) = message_stream.next().await {
+            match message {
+                Ok(m) => {
+                    let mut parked = Ok(());
+                    if let Some(payload) = m.payload() {
+                        match wire::decode::<CorporateAction>(payload) {
+                            Ok(action) => {
+                                println!("{}", format!("Corporate Action: {} {:?}", action.stock_symbol, action.kind).bold().cyan());
+                                apply_corporate_action_in_json(json_file_path, &action);
+                            }
+                            Err(e) => {
+                                eprintln!("Error deserializing corporate action: {:?}", e);
+                                parked = send_to_dead_letter(&dlq_producer, &m, &format!("Error deserializing corporate action: {}", e)).await;
+                            }
+                        }
+                    }
+                    commit_or_rewind(&consumer, &m, parked);
+                }
+                Err(e) => {
+                    eprintln!("Error while consuming from stream: {:?}", e);
+                }
+            }
+        }
+    });
+
+    if result.await.is_err() {
+        println!("Stopping corporate action consumer.");
+    }
+}
+
+// Keep the latest FX rates on disk for conversions on the trading side
+pub async fn run_fx_consumer() {
+    let consumer: StreamConsumer = ClientConfig::new()
+        .set("group.id", "fx-consumer-group")
+        .set("bootstrap.servers", "localhost:9092")
+        .set("auto.offset.reset", "latest")
+        .set("enable.auto.commit", "false")
+        .create()
+        .expect("Consumer creation failed");
+
+    consumer.subscribe(&["fx"]).expect("Can't subscribe to specified topic");
+
+    let mut message_stream = consumer.stream();
+    let dlq_producer = dead_letter_producer();
+
+    let result = timeout(Duration::from_secs(50), async {
+        while let Some(message) = message_stream.next().await {
+            match message {
+                Ok(m) => {
+                    let mut parked = Ok(());
+                    if let Some(payload) = m.payload() {
+                        match wire::decode::<FxRate>(payload) {
+                            Ok(fx_rate) => update_fx_rates_file(&fx_rate),
+                            Err(e) => {
+                                eprintln!("Error deserializing FX rate: {:?}", e);
+                                parked = send_to_dead_letter(&dlq_producer, &m, &format!("Error deserializing FX rate: {}", e)).await;
+                            }
+                        }
+                    }
+                    commit_or_rewind(&consumer, &m, parked);
+                }
+                Err(e) => {
+                    eprintln!("Error while consuming from stream: {:?}", e);
+                }
+            }
+        }
+    });
+
+    if result.await.is_err() {
+        println!("Stopping FX consumer.");
+    }
+}
+
+fn update_fx_rates_file(fx_rate: &FxRate) {
+    let mut existing_rates: HashMap<String, f64> = match fs::read_to_string(FX_RATES_PATH) {
+        Ok(content) => serde_json::from_str(&content).unwrap_or_else(|_| HashMap::new()),
+        Err(_) => HashMap::new(),
+    };
+    existing_rates.insert(fx_rate.currency.clone(), fx_rate.usd_rate);
+
+    let json_data = serde_json::to_string_pretty(&existing_rates).expect("Failed to serialize FX rates");
+    fs::write(FX_RATES_PATH, json_data).expect("Failed to write updated FX rates");
+}
+
+// Merge the session's last prices into the JSON file; symbols that didn't trade keep their old price
+fn save_last_prices(last_prices: &HashMap<String, f64>) {
     // Read the existing data from the file
     let mut existing_data: HashMap<String, f64> = match fs::read_to_string(JSON_FILE_PATH) {
         Ok(content) => serde_json::from_str(&content).unwrap_or_else(|_| HashMap::new()),
@@ -80,8 +427,7 @@ async fn update_json_file(price_update: &PriceUpdate) {
         },
     };
 
-    // Update the price for the given stock
-    existing_data.insert(price_update.name.clone(), price_update.price);
+    existing_data.extend(last_prices.iter().map(|(name, price)| (name.clone(), *price)));
 
     // Write the updated data back to the file
     let json_data = serde_json::to_string_pretty(&existing_data).expect("Failed to serialize prices");
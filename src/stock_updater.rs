@@ -1,21 +1,36 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 use rdkafka::config::ClientConfig;
+use rdkafka::consumer::{Consumer, StreamConsumer};
 use rdkafka::producer::{FutureProducer, FutureRecord};
-use serde::Serialize;
+use rdkafka::Message;
 use rand::{seq::IteratorRandom, Rng};
 use rand::rngs::StdRng;
 use rand::SeedableRng;
-use tokio::time::{sleep, timeout, Duration};
+use tokio::time::{sleep, timeout, Duration, Instant};
+use crate::circuit_breaker::CircuitBreaker;
+use crate::corporate_actions::{adjust_price, load_corporate_actions, CORPORATE_ACTIONS_PATH};
+use crate::currency::{instrument_currency, INITIAL_USD_RATES};
+use crate::models::{CorporateAction, FxRate, HaltEvent, PriceUpdate, Trade, TradingStatus};
+use crate::money::{round_price, Money, Rounding};
+use crate::trade_tape::TRADE_TOPIC;
+use crate::wire;
 
-#[derive(Serialize, Debug)]
-struct PriceUpdate {
-    name: String,
-    price: f64,
-}
+// Symbols listed on the simulated exchange
+pub const LISTED_SYMBOLS: [&str; 60] = [
+    "APPL", "MSFT", "GOOG", "AMZN", "TSLA", "NVDA", "META", "ORCL", "IBM", "AMD",
+    "ADM", "BG", "FMC", "CTVA", "DE", "MOS", "AGCO", "CF", "CALM", "SMG",
+    "XOM", "CVX", "BP", "COP", "TOT", "HAL", "SLB", "PSX", "VLO", "OXY",
+    "JNJ", "PFE", "MRK", "UNH", "ABBV", "AMGN", "TMO", "BMY", "GILD", "BIIB",
+    "JPM", "BAC", "WFC", "GS", "MS", "C", "USB", "BK", "TFC", "AXP",
+    "PG", "KO", "PEP", "UL", "NKE", "COST", "MCD", "WMT", "SBUX", "HD",
+];
 
 #[derive(Debug, Clone)]
 struct Stock {
     name: String,
     price: f64,
+    breaker: CircuitBreaker,
 }
 
 impl Stock {
@@ -23,12 +38,99 @@ impl Stock {
         Stock {
             name: name.to_string(),
             price,
+            breaker: CircuitBreaker::new(price),
         }
     }
 }
 
+async fn send_corporate_action(producer: &FutureProducer, topic: &str, action: &CorporateAction) {
+    let payload = wire::encode(action);
+    if let Err((err, _)) = producer
+        .send(
+            FutureRecord::to(topic)
+                .key(&action.stock_symbol)
+                .payload(&payload),
+            Duration::from_secs(0),
+        )
+        .await
+    {
+        println!("Failed to send corporate action: {}", err);
+    }
+}
+
+async fn send_halt_event(producer: &FutureProducer, topic: &str, event: &HaltEvent) {
+    let payload = wire::encode(event);
+    if let Err((err, _)) = producer
+        .send(
+            FutureRecord::to(topic)
+                .key(&event.stock_symbol)
+                .payload(&payload),
+            Duration::from_secs(0),
+        )
+        .await
+    {
+        println!("Failed to send halt event: {}", err);
+    }
+}
+
+// Publish a new last price and feed it to the symbol's breaker, halting it on a volatility interruption
+async fn publish_price(producer: &FutureProducer, topic: &str, halt_topic: &str, stock: &mut Stock, now: Instant) {
+    let payload = wire::encode(&PriceUpdate {
+        name: stock.name.clone(),
+        price: stock.price,
+        currency: instrument_currency(&stock.name).to_string(),
+    });
+    producer
+        .send(
+            FutureRecord::to(topic)
+                .key(&stock.name)
+                .payload(&payload),
+            Duration::from_secs(0),
+        )
+        .await
+        .expect("Failed to send price update");
+
+    if let Some(reason) = stock.breaker.record_price(now, stock.price) {
+        let event = HaltEvent {
+            stock_symbol: stock.name.clone(),
+            status: TradingStatus::Halted,
+            reason,
+        };
+        send_halt_event(producer, halt_topic, &event).await;
+    }
+}
+
+// Keep the latest traded price per symbol so the random walk can follow the tape
+async fn follow_trades(traded_prices: Arc<Mutex<HashMap<String, f64>>>) {
+    let consumer: StreamConsumer = ClientConfig::new()
+        .set("bootstrap.servers", "localhost:9092")
+        .set("group.id", "stock-updater-trade-group")
+        .set("auto.offset.reset", "latest")
+        .set("enable.auto.commit", "true")
+        .set("isolation.level", "read_committed")
+        .create()
+        .expect("Failed to create Kafka trade consumer");
+    consumer.subscribe(&[TRADE_TOPIC]).expect("Failed to subscribe to trades topic");
+
+    let _ = timeout(Duration::from_secs(50), async {
+        loop {
+            match consumer.recv().await {
+                Ok(m) => {
+                    if let Some(Ok(trade)) = m.payload().map(wire::decode::<Trade>) {
+                        traded_prices.lock().unwrap().insert(trade.stock_symbol, trade.price);
+                    }
+                }
+                Err(err) => println!("Error reading trades: {}", err),
+            }
+        }
+    })
+    .await;
+}
+
 pub async fn start_price_updater() {
     let topic = "stock";
+    let halt_topic = "halts";
+    let corporate_action_topic = "corporate_actions";
     // Kafka producer
     let producer: FutureProducer = ClientConfig::new()
         .set("bootstrap.servers", "localhost:9092")
@@ -36,78 +138,26 @@ pub async fn start_price_updater() {
         .expect("Failed to create Kafka producer");
 
     let mut rng = StdRng::from_entropy(); // Use StdRng for thread-safe RNG
+    let session_start = Instant::now();
+    let mut pending_actions = load_corporate_actions(CORPORATE_ACTIONS_PATH).into_iter().peekable();
+
+    // Prices that actually traded since the last tick
+    let traded_prices: Arc<Mutex<HashMap<String, f64>>> = Arc::new(Mutex::new(HashMap::new()));
+    tokio::spawn(follow_trades(traded_prices.clone()));
 
     // Initialize stock data
-    let mut stock_data = vec![
-        Stock::new("APPL", 100.00),
-        Stock::new("MSFT", 100.00),
-        Stock::new("GOOG", 100.00),
-        Stock::new("AMZN", 100.00),
-        Stock::new("TSLA", 100.00),
-        Stock::new("NVDA", 100.00),
-        Stock::new("META", 100.00),
-        Stock::new("ORCL", 100.00),
-        Stock::new("IBM", 100.00),
-        Stock::new("AMD", 100.00),
-        Stock::new("ADM", 100.00),
-        Stock::new("BG", 100.00),
-        Stock::new("FMC", 100.00),
-        Stock::new("CTVA", 100.00),
-        Stock::new("DE", 100.00),
-        Stock::new("MOS", 100.00),
-        Stock::new("AGCO", 100.00),
-        Stock::new("CF", 100.00),
-        Stock::new("CALM", 100.00),
-        Stock::new("SMG", 100.00),
-        Stock::new("XOM", 100.00),
-        Stock::new("CVX", 100.00),
-        Stock::new("BP", 100.00),
-        Stock::new("COP", 100.00),
-        Stock::new("TOT", 100.00),
-        Stock::new("HAL", 100.00),
-        Stock::new("SLB", 100.00),
-        Stock::new("PSX", 100.00),
-        Stock::new("VLO", 100.00),
-        Stock::new("OXY", 100.00),
-        Stock::new("JNJ", 100.00),
-        Stock::new("PFE", 100.00),
-        Stock::new("MRK", 100.00),
-        Stock::new("UNH", 100.00),
-        Stock::new("ABBV", 100.00),
-        Stock::new("AMGN", 100.00),
-        Stock::new("TMO", 100.00),
-        Stock::new("BMY", 100.00),
-        Stock::new("GILD", 100.00),
-        Stock::new("BIIB", 100.00),
-        Stock::new("JPM", 100.00),
-        Stock::new("BAC", 100.00),
-        Stock::new("WFC", 100.00),
-        Stock::new("GS", 100.00),
-        Stock::new("MS", 100.00),
-        Stock::new("C", 100.00),
-        Stock::new("USB", 100.00),
-        Stock::new("BK", 100.00),
-        Stock::new("TFC", 100.00),
-        Stock::new("AXP", 100.00),
-        Stock::new("PG", 100.00),
-        Stock::new("KO", 100.00),
-        Stock::new("PEP", 100.00),
-        Stock::new("UL", 100.00),
-        Stock::new("NKE", 100.00),
-        Stock::new("COST", 100.00),
-        Stock::new("MCD", 100.00),
-        Stock::new("WMT", 100.00),
-        Stock::new("SBUX", 100.00),
-        Stock::new("HD", 100.00),
-    ];
+    let mut stock_data: Vec<Stock> = LISTED_SYMBOLS
+        .iter()
+        .map(|name| Stock::new(name, 100.00))
+        .collect();
 
     // Send initial stock prices
     for stock in &stock_data {
-        let payload = serde_json::to_string(&PriceUpdate {
+        let payload = wire::encode(&PriceUpdate {
             name: stock.name.clone(),
             price: stock.price,
-        })
-        .expect("Failed to serialize price update");
+            currency: instrument_currency(&stock.name).to_string(),
+        });
 
         producer
             .send(
@@ -126,19 +176,38 @@ pub async fn start_price_updater() {
     // Timeout after 30 seconds
     let result = timeout(Duration::from_secs(50), async {
         loop {
-            let num_updates = rng.gen_range(2..=4);
+            let now = Instant::now();
 
-            for stock in stock_data.iter_mut().choose_multiple(&mut rng, num_updates) {
-                let change = rng.gen_range(-10.0..10.0);
-                stock.price = (stock.price + change).max(1.0); // Avoid negative prices
+            // Resume symbols whose halt has run its course
+            for stock in stock_data.iter_mut() {
+                if stock.breaker.try_resume(now, stock.price) {
+                    let event = HaltEvent {
+                        stock_symbol: stock.name.clone(),
+                        status: TradingStatus::Resumed,
+                        reason: "Halt period elapsed".to_string(),
+                    };
+                    send_halt_event(&producer, halt_topic, &event).await;
+                }
+            }
+
+            // Corporate actions reaching their ex-date: adjust the price and publish the action
+            let elapsed_secs = session_start.elapsed().as_secs();
+            while let Some(mut action) = pending_actions.next_if(|a| a.ex_time_secs <= elapsed_secs) {
+                let Some(stock) = stock_data.iter_mut().find(|s| s.name == action.stock_symbol) else {
+                    println!("Corporate action for unknown symbol {}", action.stock_symbol);
+                    continue;
+                };
+                let adjusted_price = adjust_price(&stock.name, Money::from_f64(stock.price), &action.kind).to_f64();
+                stock.breaker.rebase(adjusted_price / stock.price);
+                stock.price = adjusted_price;
+                action.adjusted_price = Some(adjusted_price);
+                send_corporate_action(&producer, corporate_action_topic, &action).await;
 
-                let payload = serde_json::to_string(&PriceUpdate {
+                let payload = wire::encode(&PriceUpdate {
                     name: stock.name.clone(),
                     price: stock.price,
-                })
-                .expect("Failed to serialize price update");
-
-                // Send updated price to Kafka
+                    currency: instrument_currency(&stock.name).to_string(),
+                });
                 producer
                     .send(
                         FutureRecord::to(topic)
@@ -150,6 +219,29 @@ pub async fn start_price_updater() {
                     .expect("Failed to send price update");
             }
 
+            // Last is set by real trades; the random walk only moves prices between them.
+            // Trades are held to the same band and volatility checks as the walk.
+            let traded: Vec<(String, f64)> = traded_prices.lock().unwrap().drain().collect();
+            for (name, traded_price) in traded {
+                let Some(stock) = stock_data.iter_mut().find(|s| s.name == name && !s.breaker.is_halted()) else {
+                    continue;
+                };
+                let banded_price = stock.breaker.apply_band(traded_price);
+                stock.price = round_price(&stock.name, banded_price, Rounding::Nearest).to_f64();
+                publish_price(&producer, topic, halt_topic, stock, now).await;
+            }
+
+            let num_updates = rng.gen_range(2..=4);
+
+            let active_stocks = stock_data.iter_mut().filter(|stock| !stock.breaker.is_halted());
+            for stock in active_stocks.choose_multiple(&mut rng, num_updates) {
+                let change = rng.gen_range(-10.0..10.0);
+                let proposed_price = (stock.price + change).max(1.0); // Avoid negative prices
+                let banded_price = stock.breaker.apply_band(proposed_price); // Limit-up/limit-down
+                stock.price = round_price(&stock.name, banded_price, Rounding::Nearest).to_f64();
+                publish_price(&producer, topic, halt_topic, stock, now).await;
+            }
+
             // Sleep between updates
             sleep(Duration::from_secs(1)).await;
         }
@@ -160,3 +252,48 @@ pub async fn start_price_updater() {
         println!("Stopping stock price updates.");
     }
 }
+
+// Simulated FX rates, published alongside the stock prices
+pub async fn start_fx_updater() {
+    let topic = "fx";
+    let producer: FutureProducer = ClientConfig::new()
+        .set("bootstrap.servers", "localhost:9092")
+        .create()
+        .expect("Failed to create Kafka producer");
+
+    let mut rng = StdRng::from_entropy();
+    let mut rates: Vec<FxRate> = INITIAL_USD_RATES
+        .iter()
+        .map(|(currency, usd_rate)| FxRate {
+            currency: currency.to_string(),
+            usd_rate: *usd_rate,
+        })
+        .collect();
+
+    let result = timeout(Duration::from_secs(50), async {
+        loop {
+            for rate in rates.iter_mut() {
+                let change = rng.gen_range(-0.002..0.002); // +/- 0.2% per tick
+                rate.usd_rate = (rate.usd_rate * (1.0 + change) * 10_000.0).round() / 10_000.0;
+
+                let payload = wire::encode(rate);
+                producer
+                    .send(
+                        FutureRecord::to(topic)
+                            .key(&rate.currency)
+                            .payload(&payload),
+                        Duration::from_secs(0),
+                    )
+                    .await
+                    .expect("Failed to send FX rate");
+            }
+
+            sleep(Duration::from_secs(1)).await;
+        }
+    })
+    .await;
+
+    if result.is_err() {
+        println!("Stopping FX rate updates.");
+    }
+}
@@ -0,0 +1,585 @@
+// tick_store.rs
+//
+// Append-only price history, one file per symbol under TICK_STORE_DIR. Export lives in the ticks tool.
+//
+// Each file starts with FILE_MAGIC. Each record is framed as payload length (u16), payload, CRC-32 of the payload (u32), so a record
+// torn by a crash mid-append is detected and cut off the next time the file is opened for writing.
+// The payload is little-endian: kind (u8), timestamp_ms (i64), then by kind
+//   Price: price (i64)                                              17 bytes
+//   Quote: bid (i64), bid_size (u64), ask (i64), ask_size (u64)     41 bytes
+//   Trade: price (i64), quantity (u64), aggressor (u8)              26 bytes
+// Prices are fixed-point micro-units like Money; a quote side with no price is stored as NO_PRICE.
+//
+// Beside each tick file is a sparse index: one entry per block of about INDEX_BLOCK_BYTES, giving
+// the block's byte range and its earliest and latest timestamp. Queries read only the blocks whose
+// times overlap the range, plus the unindexed tail. Timestamps need not be in order within a file.
+
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+pub const TICK_STORE_DIR: &str = "src/data/ticks";
+const TICK_FILE_EXTENSION: &str = "ticks";
+const INDEX_FILE_EXTENSION: &str = "idx";
+// Marks the framed format; files from before framing are refused rather than truncated
+const FILE_MAGIC: &[u8; 4] = b"TCK2";
+const PRICE_SCALE: f64 = 1_000_000.0;
+const NO_PRICE: i64 = i64::MIN;
+// Data covered by one index entry
+const INDEX_BLOCK_BYTES: u64 = 64 * 1024;
+// Index entry: start offset (u64), end offset (u64), min timestamp_ms (i64), max timestamp_ms (i64)
+const INDEX_ENTRY_BYTES: usize = 32;
+
+const KIND_PRICE: u8 = 0;
+const KIND_QUOTE: u8 = 1;
+const KIND_TRADE: u8 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Side {
+    Buy,
+    Sell,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TickEvent {
+    Price { price: f64 },
+    Quote { bid: Option<f64>, bid_size: u64, ask: Option<f64>, ask_size: u64 },
+    Trade { price: f64, quantity: u64, aggressor: Option<Side> },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TickRecord {
+    pub timestamp_ms: i64,
+    pub event: TickEvent,
+}
+
+fn to_fixed(price: f64) -> i64 {
+    (price * PRICE_SCALE).round() as i64
+}
+
+fn from_fixed(value: i64) -> f64 {
+    value as f64 / PRICE_SCALE
+}
+
+// CRC-32 (IEEE), bitwise; records are a few dozen bytes so a table isn't worth it
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+// Append one framed record to out
+fn encode_record(record: &TickRecord, out: &mut Vec<u8>) {
+    let frame_start = out.len();
+    out.extend_from_slice(&[0, 0]); // Length, filled in below
+    encode_payload(record, &mut *out);
+    let payload_len = out.len() - frame_start - 2;
+    out[frame_start..frame_start + 2].copy_from_slice(&(payload_len as u16).to_le_bytes());
+    let crc = crc32(&out[frame_start + 2..]);
+    out.extend_from_slice(&crc.to_le_bytes());
+}
+
+fn encode_payload(record: &TickRecord, out: &mut Vec<u8>) {
+    let kind = match record.event {
+        TickEvent::Price { .. } => KIND_PRICE,
+        TickEvent::Quote { .. } => KIND_QUOTE,
+        TickEvent::Trade { .. } => KIND_TRADE,
+    };
+    out.push(kind);
+    out.extend_from_slice(&record.timestamp_ms.to_le_bytes());
+    match &record.event {
+        TickEvent::Price { price } => out.extend_from_slice(&to_fixed(*price).to_le_bytes()),
+        TickEvent::Quote { bid, bid_size, ask, ask_size } => {
+            out.extend_from_slice(&bid.map_or(NO_PRICE, to_fixed).to_le_bytes());
+            out.extend_from_slice(&bid_size.to_le_bytes());
+            out.extend_from_slice(&ask.map_or(NO_PRICE, to_fixed).to_le_bytes());
+            out.extend_from_slice(&ask_size.to_le_bytes());
+        }
+        TickEvent::Trade { price, quantity, aggressor } => {
+            out.extend_from_slice(&to_fixed(*price).to_le_bytes());
+            out.extend_from_slice(&quantity.to_le_bytes());
+            out.push(match aggressor {
+                None => 0,
+                Some(Side::Buy) => 1,
+                Some(Side::Sell) => 2,
+            });
+        }
+    }
+}
+
+// Reads fixed-width fields off the front of a byte slice
+struct Cursor<'a> {
+    bytes: &'a [u8],
+}
+
+impl Cursor<'_> {
+    fn take<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let (head, rest) = self.bytes.split_first_chunk::<N>()?;
+        self.bytes = rest;
+        Some(*head)
+    }
+
+    fn i64(&mut self) -> Option<i64> {
+        self.take::<8>().map(i64::from_le_bytes)
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        self.take::<8>().map(u64::from_le_bytes)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take::<1>().map(|[byte]| byte)
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.take::<2>().map(u16::from_le_bytes)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.take::<4>().map(u32::from_le_bytes)
+    }
+}
+
+impl<'a> Cursor<'a> {
+    fn slice(&mut self, len: usize) -> Option<&'a [u8]> {
+        let (head, rest) = self.bytes.split_at_checked(len)?;
+        self.bytes = rest;
+        Some(head)
+    }
+}
+
+// Decode every valid record up to the first torn or corrupt one. Also returns how many bytes
+// those records take, which is where a writer resumes.
+fn decode_records(bytes: &[u8]) -> (Vec<TickRecord>, usize) {
+    let mut cursor = Cursor { bytes };
+    let mut records = Vec::new();
+    while let Some(record) = decode_record(&mut cursor) {
+        records.push(record);
+    }
+    (records, bytes.len() - cursor.bytes.len())
+}
+
+// Decode one framed record. None, leaving the cursor where it was, if the frame is incomplete,
+// fails its CRC or holds an unknown kind.
+fn decode_record(cursor: &mut Cursor) -> Option<TickRecord> {
+    let mut frame = Cursor { bytes: cursor.bytes };
+    let payload_len = frame.u16()? as usize;
+    let payload = frame.slice(payload_len)?;
+    if frame.u32()? != crc32(payload) {
+        return None;
+    }
+    let record = decode_payload(&mut Cursor { bytes: payload })?;
+    cursor.bytes = frame.bytes;
+    Some(record)
+}
+
+fn decode_payload(cursor: &mut Cursor) -> Option<TickRecord> {
+    let kind = cursor.u8()?;
+    let timestamp_ms = cursor.i64()?;
+    let optional_price = |value: i64| (value != NO_PRICE).then(|| from_fixed(value));
+    let event = match kind {
+        KIND_PRICE => TickEvent::Price { price: from_fixed(cursor.i64()?) },
+        KIND_QUOTE => TickEvent::Quote {
+            bid: optional_price(cursor.i64()?),
+            bid_size: cursor.u64()?,
+            ask: optional_price(cursor.i64()?),
+            ask_size: cursor.u64()?,
+        },
+        KIND_TRADE => TickEvent::Trade {
+            price: from_fixed(cursor.i64()?),
+            quantity: cursor.u64()?,
+            aggressor: match cursor.u8()? {
+                1 => Some(Side::Buy),
+                2 => Some(Side::Sell),
+                _ => None,
+            },
+        },
+        _ => return None,
+    };
+    Some(TickRecord { timestamp_ms, event })
+}
+
+fn tick_file(dir: &str, symbol: &str) -> PathBuf {
+    Path::new(dir).join(format!("{}.{}", symbol, TICK_FILE_EXTENSION))
+}
+
+fn index_file(dir: &str, symbol: &str) -> PathBuf {
+    Path::new(dir).join(format!("{}.{}", symbol, INDEX_FILE_EXTENSION))
+}
+
+// A block of the tick file and the times of the records in it
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct IndexEntry {
+    start: u64,
+    end: u64,
+    min_ms: i64,
+    max_ms: i64,
+}
+
+impl IndexEntry {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.start.to_le_bytes());
+        out.extend_from_slice(&self.end.to_le_bytes());
+        out.extend_from_slice(&self.min_ms.to_le_bytes());
+        out.extend_from_slice(&self.max_ms.to_le_bytes());
+    }
+
+    fn overlaps(&self, from_ms: i64, to_ms: i64) -> bool {
+        self.max_ms >= from_ms && self.min_ms < to_ms
+    }
+}
+
+// Index entries that are whole, contiguous from the start of the file and within its valid data.
+// Anything after the first one that isn't was torn by a crash or outlived a truncated tick file.
+fn decode_index(bytes: &[u8], data_len: u64) -> Vec<IndexEntry> {
+    let mut cursor = Cursor { bytes };
+    let mut entries: Vec<IndexEntry> = Vec::new();
+    while let (Some(start), Some(end), Some(min_ms), Some(max_ms)) = (cursor.u64(), cursor.u64(), cursor.i64(), cursor.i64()) {
+        let expected_start = entries.last().map_or(FILE_MAGIC.len() as u64, |entry| entry.end);
+        if start != expected_start || end <= start || end > data_len {
+            break;
+        }
+        entries.push(IndexEntry { start, end, min_ms, max_ms });
+    }
+    entries
+}
+
+// False for a file that is empty or was torn while its magic was being written
+fn has_magic(path: &Path, head: &[u8]) -> io::Result<bool> {
+    if head.len() < FILE_MAGIC.len() && FILE_MAGIC.starts_with(head) {
+        return Ok(false);
+    }
+    if !head.starts_with(FILE_MAGIC) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} is not a framed tick file", path.display()),
+        ));
+    }
+    Ok(true)
+}
+
+fn read_optional(path: &Path) -> io::Result<Vec<u8>> {
+    match fs::read(path) {
+        Ok(bytes) => Ok(bytes),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e),
+    }
+}
+
+// Append handles for one symbol and the block being filled
+struct SymbolFile {
+    data: BufWriter<File>,
+    index: BufWriter<File>,
+    len: u64,
+    block_start: u64,
+    block_times: Option<(i64, i64)>, // Earliest and latest timestamp in the open block
+}
+
+impl SymbolFile {
+    // Cut any torn record off the tick file and any stale entries off its index, then resume after them
+    fn open(dir: &str, symbol: &str) -> io::Result<Self> {
+        let data_path = tick_file(dir, symbol);
+        let mut bytes = read_optional(&data_path)?;
+        let mut data = OpenOptions::new().create(true).append(true).open(&data_path)?;
+        if !has_magic(&data_path, &bytes)? {
+            data.set_len(0)?;
+            data.write_all(FILE_MAGIC)?;
+            bytes = FILE_MAGIC.to_vec();
+        }
+        let valid_len = FILE_MAGIC.len() + decode_records(&bytes[FILE_MAGIC.len()..]).1;
+        if valid_len < bytes.len() {
+            println!(
+                "Tick file {} ends in {} unreadable byte(s), truncating to the last complete record",
+                data_path.display(),
+                bytes.len() - valid_len
+            );
+            data.set_len(valid_len as u64)?;
+        }
+
+        let index_path = index_file(dir, symbol);
+        let entries = decode_index(&read_optional(&index_path)?, valid_len as u64);
+        let index = OpenOptions::new().create(true).append(true).open(&index_path)?;
+        index.set_len((entries.len() * INDEX_ENTRY_BYTES) as u64)?;
+
+        // Records after the last indexed block start the next block
+        let block_start = entries.last().map_or(FILE_MAGIC.len() as u64, |entry| entry.end);
+        let (tail, _) = decode_records(&bytes[block_start as usize..valid_len]);
+        let block_times = tail.iter().fold(None, |times, record| Some(widen(times, record.timestamp_ms)));
+        Ok(SymbolFile {
+            data: BufWriter::new(data),
+            index: BufWriter::new(index),
+            len: valid_len as u64,
+            block_start,
+            block_times,
+        })
+    }
+
+    fn append(&mut self, frame: &[u8], timestamp_ms: i64) -> io::Result<()> {
+        self.data.write_all(frame)?;
+        self.len += frame.len() as u64;
+        self.block_times = Some(widen(self.block_times, timestamp_ms));
+        if self.len - self.block_start >= INDEX_BLOCK_BYTES {
+            let (min_ms, max_ms) = self.block_times.take().expect("Block has a record");
+            let mut entry = Vec::with_capacity(INDEX_ENTRY_BYTES);
+            IndexEntry { start: self.block_start, end: self.len, min_ms, max_ms }.encode(&mut entry);
+            self.index.write_all(&entry)?;
+            self.block_start = self.len;
+        }
+        Ok(())
+    }
+
+    // The index must never point past data on disk, so the data goes first
+    fn flush(&mut self) -> io::Result<()> {
+        self.data.flush()?;
+        self.index.flush()
+    }
+}
+
+fn widen(times: Option<(i64, i64)>, timestamp_ms: i64) -> (i64, i64) {
+    times.map_or((timestamp_ms, timestamp_ms), |(min_ms, max_ms)| (min_ms.min(timestamp_ms), max_ms.max(timestamp_ms)))
+}
+
+// Keeps one buffered append handle per symbol; records reach disk on flush
+pub struct TickWriter {
+    dir: String,
+    files: HashMap<String, SymbolFile>,
+    buffer: Vec<u8>,
+}
+
+impl TickWriter {
+    pub fn open(dir: &str) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        Ok(TickWriter {
+            dir: dir.to_string(),
+            files: HashMap::new(),
+            buffer: Vec::new(),
+        })
+    }
+
+    pub fn append(&mut self, symbol: &str, record: &TickRecord) -> io::Result<()> {
+        if !self.files.contains_key(symbol) {
+            let file = SymbolFile::open(&self.dir, symbol)?;
+            self.files.insert(symbol.to_string(), file);
+        }
+        self.buffer.clear();
+        encode_record(record, &mut self.buffer);
+        self.files
+            .get_mut(symbol)
+            .expect("Tick file was just opened")
+            .append(&self.buffer, record.timestamp_ms)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        for file in self.files.values_mut() {
+            file.flush()?;
+        }
+        Ok(())
+    }
+}
+
+// Symbols with any stored history
+pub fn stored_symbols(dir: &str) -> Vec<String> {
+    let mut symbols: Vec<String> = fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .filter(|path| path.extension().is_some_and(|extension| extension == TICK_FILE_EXTENSION))
+                .filter_map(|path| path.file_stem().map(|stem| stem.to_string_lossy().into_owned()))
+                .collect()
+        })
+        .unwrap_or_default();
+    symbols.sort();
+    symbols
+}
+
+// Records for one symbol with from_ms <= timestamp < to_ms, in the order they were appended.
+// Only indexed blocks overlapping the range are read, then everything after the last indexed block.
+pub fn query(dir: &str, symbol: &str, from_ms: i64, to_ms: i64) -> io::Result<Vec<TickRecord>> {
+    let mut file = match File::open(tick_file(dir, symbol)) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut head = Vec::new();
+    (&mut file).take(FILE_MAGIC.len() as u64).read_to_end(&mut head)?;
+    if !has_magic(&tick_file(dir, symbol), &head)? {
+        return Ok(Vec::new());
+    }
+    let data_len = file.metadata()?.len();
+    let entries = decode_index(&read_optional(&index_file(dir, symbol))?, data_len);
+    let tail_start = entries.last().map_or(FILE_MAGIC.len() as u64, |entry| entry.end);
+
+    let mut records = Vec::new();
+    let mut bytes = Vec::new();
+    let ranges = entries
+        .iter()
+        .filter(|entry| entry.overlaps(from_ms, to_ms))
+        .map(|entry| (entry.start, Some(entry.end - entry.start)))
+        .chain([(tail_start, None)]);
+    for (start, len) in ranges {
+        bytes.clear();
+        file.seek(SeekFrom::Start(start))?;
+        match len {
+            Some(len) => (&mut file).take(len).read_to_end(&mut bytes)?,
+            None => file.read_to_end(&mut bytes)?,
+        };
+        let (block, _) = decode_records(&bytes);
+        records.extend(block.into_iter().filter(|record| (from_ms..to_ms).contains(&record.timestamp_ms)));
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_records() -> Vec<TickRecord> {
+        vec![
+            TickRecord { timestamp_ms: 1_700_000_000_000, event: TickEvent::Price { price: 187.25 } },
+            TickRecord {
+                timestamp_ms: 1_700_000_000_001,
+                event: TickEvent::Quote { bid: Some(187.2), bid_size: 300, ask: None, ask_size: 0 },
+            },
+            TickRecord {
+                timestamp_ms: 1_700_000_000_002,
+                event: TickEvent::Trade { price: 187.21, quantity: 50, aggressor: Some(Side::Sell) },
+            },
+            TickRecord {
+                timestamp_ms: -5,
+                event: TickEvent::Trade { price: 0.000001, quantity: u64::MAX, aggressor: None },
+            },
+        ]
+    }
+
+    fn encode_all(records: &[TickRecord]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for record in records {
+            encode_record(record, &mut bytes);
+        }
+        bytes
+    }
+
+    // A fresh directory per test, so tests can run in parallel
+    fn temp_dir(name: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("tick_store_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn records_round_trip() {
+        let records = sample_records();
+        let bytes = encode_all(&records);
+        // Each payload plus its length prefix and CRC
+        assert_eq!(bytes.len(), 17 + 41 + 26 + 26 + 4 * (2 + 4));
+        assert_eq!(decode_records(&bytes), (records, bytes.len()));
+    }
+
+    #[test]
+    fn torn_record_ends_the_read_at_the_last_complete_one() {
+        let records = sample_records();
+        let bytes = encode_all(&records);
+        let complete = encode_all(&records[..3]).len();
+        for cut in complete..bytes.len() {
+            assert_eq!(decode_records(&bytes[..cut]), (records[..3].to_vec(), complete), "cut at {}", cut);
+        }
+    }
+
+    #[test]
+    fn corrupt_record_fails_its_crc() {
+        let records = sample_records();
+        let mut bytes = encode_all(&records);
+        let second = encode_all(&records[..1]).len();
+        bytes[second + 5] ^= 0x40; // Inside the quote's timestamp
+        assert_eq!(decode_records(&bytes), (records[..1].to_vec(), second));
+    }
+
+    #[test]
+    fn crc_matches_the_standard_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn reopening_truncates_a_torn_tail_and_appends_after_it() {
+        let dir = temp_dir("torn_tail");
+        let records = sample_records();
+        let mut writer = TickWriter::open(&dir).unwrap();
+        writer.append("BP", &records[0]).unwrap();
+        writer.append("BP", &records[1]).unwrap();
+        writer.flush().unwrap();
+        drop(writer);
+
+        // A crash halfway through appending a third record
+        let mut torn = Vec::new();
+        encode_record(&records[2], &mut torn);
+        let mut file = OpenOptions::new().append(true).open(tick_file(&dir, "BP")).unwrap();
+        file.write_all(&torn[..torn.len() / 2]).unwrap();
+        drop(file);
+
+        let mut writer = TickWriter::open(&dir).unwrap();
+        writer.append("BP", &records[2]).unwrap();
+        writer.flush().unwrap();
+        assert_eq!(query(&dir, "BP", i64::MIN, i64::MAX).unwrap(), records[..3].to_vec());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn indexed_query_matches_a_full_scan() {
+        let dir = temp_dir("indexed_query");
+        let mut writer = TickWriter::open(&dir).unwrap();
+        // Enough records for several index blocks, slightly out of time order like real feeds
+        let records: Vec<TickRecord> = (0..10_000)
+            .map(|i| TickRecord { timestamp_ms: i * 10 + (i % 3), event: TickEvent::Price { price: i as f64 } })
+            .collect();
+        for record in &records {
+            writer.append("KO", record).unwrap();
+        }
+        writer.flush().unwrap();
+        drop(writer);
+
+        let data_len = fs::metadata(tick_file(&dir, "KO")).unwrap().len();
+        let entries = decode_index(&fs::read(index_file(&dir, "KO")).unwrap(), data_len);
+        assert!(entries.len() >= 3, "{} index entries", entries.len());
+
+        for (from_ms, to_ms) in [(i64::MIN, i64::MAX), (0, 1), (25_000, 61_234), (99_990, i64::MAX), (200_000, 300_000)] {
+            let expected: Vec<TickRecord> = records
+                .iter()
+                .filter(|record| (from_ms..to_ms).contains(&record.timestamp_ms))
+                .cloned()
+                .collect();
+            assert_eq!(query(&dir, "KO", from_ms, to_ms).unwrap(), expected, "{}..{}", from_ms, to_ms);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn files_from_before_framing_are_refused() {
+        let dir = temp_dir("unframed");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(tick_file(&dir, "BP"), [0u8; 17]).unwrap();
+        assert!(query(&dir, "BP", i64::MIN, i64::MAX).is_err());
+        let mut writer = TickWriter::open(&dir).unwrap();
+        assert!(writer.append("BP", &sample_records()[0]).is_err());
+        assert_eq!(fs::read(tick_file(&dir, "BP")).unwrap(), [0u8; 17]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn index_entries_past_the_data_are_ignored() {
+        let entry = IndexEntry { start: FILE_MAGIC.len() as u64, end: 100, min_ms: 1, max_ms: 2 };
+        let beyond = IndexEntry { start: 100, end: 300, min_ms: 3, max_ms: 4 };
+        let mut bytes = Vec::new();
+        entry.encode(&mut bytes);
+        beyond.encode(&mut bytes);
+        bytes.extend_from_slice(&[1, 2, 3]); // Torn entry
+        assert_eq!(decode_index(&bytes, 200), vec![entry]);
+        assert_eq!(decode_index(&bytes, 300), vec![entry, beyond]);
+    }
+}
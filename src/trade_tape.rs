@@ -0,0 +1,77 @@
+// trade_tape.rs
+
+use std::collections::HashMap;
+use crate::models::{new_unique_id, Liquidity, Order, OrderAction, Trade};
+use crate::tick_store::{query, stored_symbols, TickEvent};
+
+pub const TRADE_TOPIC: &str = "trades";
+
+// Public print for a single fill. A maker fill was hit by the other side, so the aggressor is the opposite side.
+pub fn trade_from_fill(fill: &Order) -> Trade {
+    let aggressor = match (fill.liquidity, &fill.order_action) {
+        (Some(Liquidity::Auction), _) => None,
+        (Some(Liquidity::Maker), OrderAction::Buy) => Some(OrderAction::Sell),
+        (Some(Liquidity::Maker), _) => Some(OrderAction::Buy),
+        (_, action) => Some(action.clone()),
+    };
+    Trade {
+        trade_id: new_unique_id(),
+        stock_symbol: fill.stock_symbol.clone(),
+        price: fill.price.to_f64(),
+        quantity: fill.quantity,
+        aggressor,
+        timestamp_ms: chrono::Utc::now().timestamp_millis(),
+    }
+}
+
+// One print for a whole uncross: both sides of every auction fill trade at the single price
+pub fn auction_trade(stock_symbol: &str, price: f64, volume: u64) -> Trade {
+    Trade {
+        trade_id: new_unique_id(),
+        stock_symbol: stock_symbol.to_string(),
+        price,
+        quantity: volume,
+        aggressor: None,
+        timestamp_ms: chrono::Utc::now().timestamp_millis(),
+    }
+}
+
+// Per-symbol summary of the tape
+#[derive(Debug, Clone, Default)]
+pub struct TapeSummary {
+    pub trades: u64,
+    pub volume: u64,
+    pub notional: f64,
+    pub last: f64,
+}
+
+impl TapeSummary {
+    pub fn vwap(&self) -> f64 {
+        if self.volume == 0 {
+            0.0
+        } else {
+            self.notional / self.volume as f64
+        }
+    }
+}
+
+// Summarise the trades recorded in the tick store between from_ms and to_ms
+pub fn summarize_tape(tick_store_dir: &str, from_ms: i64, to_ms: i64) -> HashMap<String, TapeSummary> {
+    let mut summaries: HashMap<String, TapeSummary> = HashMap::new();
+    for stock_symbol in stored_symbols(tick_store_dir) {
+        let records = query(tick_store_dir, &stock_symbol, from_ms, to_ms).unwrap_or_else(|e| {
+            println!("Error reading tick store for {}: {}", stock_symbol, e);
+            Vec::new()
+        });
+        for record in records {
+            if let TickEvent::Trade { price, quantity, .. } = record.event {
+                let summary = summaries.entry(stock_symbol.clone()).or_default();
+                summary.trades += 1;
+                summary.volume += quantity;
+                summary.notional += price * quantity as f64;
+                summary.last = price;
+            }
+        }
+    }
+    summaries
+}
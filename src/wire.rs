@@ -0,0 +1,355 @@
+// wire.rs
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::sync::OnceLock;
+use crate::models::{AuctionIndicative, Bar, CancelAck, CorporateAction, DepthUpdate, FxRate, HaltEvent, Order, OrderAck, PriceUpdate, Quote, Trade};
+
+pub const WIRE_CONFIG_PATH: &str = "src/data/wire_config.json";
+
+// Every Kafka message is wrapped in an envelope carrying its schema version and message type.
+// Compatibility rules, which both the stock side and the trading side rely on:
+//  1. Readers accept any version from MIN_SUPPORTED_VERSION up to SCHEMA_VERSION and reject newer ones.
+//  2. Within a version, changes are additive only: new fields are #[serde(default)] and readers ignore
+//     fields they don't know.
+//  3. Renaming, removing or retyping a field or enum variant requires bumping SCHEMA_VERSION.
+//  4. A message whose type is not the one the topic carries is rejected, not guessed at.
+//  5. Bare JSON without an envelope is read as LEGACY_VERSION while producers migrate.
+// Both encodings write field names, so rule 2 holds for MessagePack as well as JSON.
+pub const SCHEMA_VERSION: u16 = 1;
+pub const MIN_SUPPORTED_VERSION: u16 = LEGACY_VERSION;
+pub const LEGACY_VERSION: u16 = 0;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum MessageType {
+    Order,
+    PriceUpdate,
+    OrderAck,
+    HaltEvent,
+    CorporateAction,
+    FxRate,
+    AuctionIndicative,
+    Quote,
+    DepthUpdate,
+    Trade,
+    Bar,
+    CancelAck,
+}
+
+// A payload type that can travel in an envelope
+pub trait WireMessage: Serialize + DeserializeOwned {
+    const MESSAGE_TYPE: MessageType;
+}
+
+impl WireMessage for Order {
+    const MESSAGE_TYPE: MessageType = MessageType::Order;
+}
+
+impl WireMessage for PriceUpdate {
+    const MESSAGE_TYPE: MessageType = MessageType::PriceUpdate;
+}
+
+impl WireMessage for OrderAck {
+    const MESSAGE_TYPE: MessageType = MessageType::OrderAck;
+}
+
+impl WireMessage for HaltEvent {
+    const MESSAGE_TYPE: MessageType = MessageType::HaltEvent;
+}
+
+impl WireMessage for CorporateAction {
+    const MESSAGE_TYPE: MessageType = MessageType::CorporateAction;
+}
+
+impl WireMessage for FxRate {
+    const MESSAGE_TYPE: MessageType = MessageType::FxRate;
+}
+
+impl WireMessage for AuctionIndicative {
+    const MESSAGE_TYPE: MessageType = MessageType::AuctionIndicative;
+}
+
+impl WireMessage for Quote {
+    const MESSAGE_TYPE: MessageType = MessageType::Quote;
+}
+
+impl WireMessage for DepthUpdate {
+    const MESSAGE_TYPE: MessageType = MessageType::DepthUpdate;
+}
+
+impl WireMessage for Trade {
+    const MESSAGE_TYPE: MessageType = MessageType::Trade;
+}
+
+impl WireMessage for Bar {
+    const MESSAGE_TYPE: MessageType = MessageType::Bar;
+}
+
+impl WireMessage for CancelAck {
+    const MESSAGE_TYPE: MessageType = MessageType::CancelAck;
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub enum Encoding {
+    #[default]
+    Json,
+    MessagePack,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct WireConfig {
+    #[serde(default)]
+    pub encoding: Encoding, // Used when producing; readers detect the encoding per message
+}
+
+impl WireConfig {
+    // Missing or unreadable config keeps plain JSON
+    pub fn load(path: &str) -> Self {
+        match fs::read_to_string(path) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+                println!("Error parsing wire config, using JSON: {}", e);
+                WireConfig::default()
+            }),
+            Err(_) => WireConfig::default(),
+        }
+    }
+}
+
+// Loaded once so every producer in the process agrees on the encoding
+fn configured_encoding() -> Encoding {
+    static ENCODING: OnceLock<Encoding> = OnceLock::new();
+    *ENCODING.get_or_init(|| WireConfig::load(WIRE_CONFIG_PATH).encoding)
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Envelope<T> {
+    schema_version: u16,
+    message_type: MessageType,
+    payload: T,
+}
+
+// Just the envelope fields, read before committing to a payload type
+#[derive(Deserialize)]
+struct EnvelopeHeader {
+    schema_version: u16,
+    message_type: MessageType,
+}
+
+#[derive(Debug)]
+pub enum WireError {
+    Json(serde_json::Error),
+    MessagePack(rmp_serde::decode::Error),
+    UnsupportedVersion(u16),
+    UnexpectedType { expected: MessageType, found: MessageType },
+}
+
+impl fmt::Display for WireError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WireError::Json(e) => write!(f, "invalid JSON message: {}", e),
+            WireError::MessagePack(e) => write!(f, "invalid MessagePack message: {}", e),
+            WireError::UnsupportedVersion(version) => write!(
+                f,
+                "schema version {} not supported (accepting {} to {})",
+                version, MIN_SUPPORTED_VERSION, SCHEMA_VERSION
+            ),
+            WireError::UnexpectedType { expected, found } => {
+                write!(f, "expected a {:?} message, got {:?}", expected, found)
+            }
+        }
+    }
+}
+
+// Encode with the configured encoding
+pub fn encode<T: WireMessage>(value: &T) -> Vec<u8> {
+    encode_with(value, configured_encoding())
+}
+
+pub fn encode_with<T: WireMessage>(value: &T, encoding: Encoding) -> Vec<u8> {
+    let envelope = Envelope {
+        schema_version: SCHEMA_VERSION,
+        message_type: T::MESSAGE_TYPE,
+        payload: value,
+    };
+    match encoding {
+        Encoding::Json => serde_json::to_vec(&envelope).expect("Failed to encode JSON message"),
+        Encoding::MessagePack => rmp_serde::to_vec_named(&envelope).expect("Failed to encode MessagePack message"),
+    }
+}
+
+// Decode either encoding: JSON always starts with '{', MessagePack envelopes never do
+pub fn decode<T: WireMessage>(bytes: &[u8]) -> Result<T, WireError> {
+    match bytes.iter().find(|byte| !byte.is_ascii_whitespace()) {
+        Some(b'{') => decode_json(bytes),
+        _ => decode_message_pack(bytes),
+    }
+}
+
+fn decode_json<T: WireMessage>(bytes: &[u8]) -> Result<T, WireError> {
+    let value: serde_json::Value = serde_json::from_slice(bytes).map_err(WireError::Json)?;
+    if value.get("schema_version").is_none() {
+        // Pre-envelope message
+        return serde_json::from_value(value).map_err(WireError::Json);
+    }
+    let header: EnvelopeHeader = serde_json::from_value(value.clone()).map_err(WireError::Json)?;
+    check_header(&header, T::MESSAGE_TYPE)?;
+    serde_json::from_value::<Envelope<T>>(value)
+        .map(|envelope| envelope.payload)
+        .map_err(WireError::Json)
+}
+
+fn decode_message_pack<T: WireMessage>(bytes: &[u8]) -> Result<T, WireError> {
+    let header: EnvelopeHeader = rmp_serde::from_slice(bytes).map_err(WireError::MessagePack)?;
+    check_header(&header, T::MESSAGE_TYPE)?;
+    rmp_serde::from_slice::<Envelope<T>>(bytes)
+        .map(|envelope| envelope.payload)
+        .map_err(WireError::MessagePack)
+}
+
+// Length and leading bytes in hex, for logging a payload that failed to decode; MessagePack
+// doesn't survive being printed as text
+pub fn describe_payload(bytes: &[u8]) -> String {
+    const SHOWN_BYTES: usize = 32;
+    let hex: String = bytes.iter().take(SHOWN_BYTES).map(|byte| format!("{:02x}", byte)).collect();
+    let more = if bytes.len() > SHOWN_BYTES { "..." } else { "" };
+    format!("{} bytes: {}{}", bytes.len(), hex, more)
+}
+
+fn check_header(header: &EnvelopeHeader, expected: MessageType) -> Result<(), WireError> {
+    if !(MIN_SUPPORTED_VERSION..=SCHEMA_VERSION).contains(&header.schema_version) {
+        return Err(WireError::UnsupportedVersion(header.schema_version));
+    }
+    if header.message_type != expected {
+        return Err(WireError::UnexpectedType { expected, found: header.message_type });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{OrderAction, OrderStatus, OrderType, RejectReason};
+    use crate::money::Money;
+
+    fn sample_order() -> Order {
+        Order {
+            broker_id: 2,
+            client_id: 17,
+            order_id: "5b0e5c3e-6c0a-4e57-9d8e-0f0c3e1f4a21".to_string(),
+            stock_symbol: "AAPL".to_string(),
+            order_type: OrderType::Limit,
+            order_action: OrderAction::Sell,
+            price: Money::from_f64(187.25),
+            quantity: 12,
+            status: OrderStatus::Rejected,
+            reject_reason: Some(RejectReason::PriceOutOfBand),
+            reject_text: Some("Limit too far from last".to_string()),
+            liquidity: None,
+            exec_id: Some("exec-1".to_string()),
+            liquidation: false,
+            lot_ids: Vec::new(),
+        }
+    }
+
+    fn sample_price() -> PriceUpdate {
+        PriceUpdate {
+            name: "BP".to_string(),
+            price: 4.8125,
+            currency: "GBP".to_string(),
+        }
+    }
+
+    #[test]
+    fn order_round_trips_in_both_encodings() {
+        for encoding in [Encoding::Json, Encoding::MessagePack] {
+            let decoded: Order = decode(&encode_with(&sample_order(), encoding)).unwrap();
+            assert_eq!(
+                serde_json::to_value(&decoded).unwrap(),
+                serde_json::to_value(sample_order()).unwrap(),
+                "{:?}",
+                encoding
+            );
+        }
+    }
+
+    #[test]
+    fn price_update_round_trips_in_both_encodings() {
+        for encoding in [Encoding::Json, Encoding::MessagePack] {
+            let decoded: PriceUpdate = decode(&encode_with(&sample_price(), encoding)).unwrap();
+            assert_eq!(decoded.name, "BP");
+            assert_eq!(decoded.price, 4.8125);
+            assert_eq!(decoded.currency, "GBP");
+        }
+    }
+
+    #[test]
+    fn message_pack_is_smaller_than_json() {
+        let json = encode_with(&sample_order(), Encoding::Json);
+        let message_pack = encode_with(&sample_order(), Encoding::MessagePack);
+        assert!(message_pack.len() < json.len());
+    }
+
+    #[test]
+    fn json_envelope_carries_version_and_type() {
+        let value: serde_json::Value = serde_json::from_slice(&encode_with(&sample_price(), Encoding::Json)).unwrap();
+        assert_eq!(value["schema_version"], SCHEMA_VERSION);
+        assert_eq!(value["message_type"], "PriceUpdate");
+        assert_eq!(value["payload"]["name"], "BP");
+    }
+
+    #[test]
+    fn legacy_bare_json_is_accepted() {
+        let legacy = br#"{"name":"AAPL","price":190.5}"#;
+        let decoded: PriceUpdate = decode(legacy).unwrap();
+        assert_eq!(decoded.name, "AAPL");
+        assert_eq!(decoded.currency, "USD");
+    }
+
+    #[test]
+    fn unknown_fields_are_ignored() {
+        let newer = br#"{"schema_version":1,"message_type":"PriceUpdate","trace_id":"x",
+            "payload":{"name":"AAPL","price":190.5,"currency":"USD","venue":"XNAS"}}"#;
+        let decoded: PriceUpdate = decode(newer).unwrap();
+        assert_eq!(decoded.price, 190.5);
+    }
+
+    #[test]
+    fn newer_schema_version_is_rejected() {
+        for encoding in [Encoding::Json, Encoding::MessagePack] {
+            let envelope = Envelope {
+                schema_version: SCHEMA_VERSION + 1,
+                message_type: MessageType::PriceUpdate,
+                payload: sample_price(),
+            };
+            let bytes = match encoding {
+                Encoding::Json => serde_json::to_vec(&envelope).unwrap(),
+                Encoding::MessagePack => rmp_serde::to_vec_named(&envelope).unwrap(),
+            };
+            assert!(matches!(
+                decode::<PriceUpdate>(&bytes),
+                Err(WireError::UnsupportedVersion(version)) if version == SCHEMA_VERSION + 1
+            ));
+        }
+    }
+
+    #[test]
+    fn payload_description_is_length_and_hex() {
+        assert_eq!(describe_payload(&[0x82, 0xa4, 0x00]), "3 bytes: 82a400");
+        let long = describe_payload(&[0xff; 40]);
+        assert!(long.starts_with("40 bytes: ffff"));
+        assert!(long.ends_with("..."));
+    }
+
+    #[test]
+    fn wrong_message_type_is_rejected() {
+        for encoding in [Encoding::Json, Encoding::MessagePack] {
+            let bytes = encode_with(&sample_price(), encoding);
+            assert!(matches!(
+                decode::<Order>(&bytes),
+                Err(WireError::UnexpectedType { expected: MessageType::Order, found: MessageType::PriceUpdate })
+            ));
+        }
+    }
+}